@@ -0,0 +1,131 @@
+//! Inverters connected to an AhoyDTU, the power limit of the inverter is the setpoint
+
+use async_trait::async_trait;
+use serde_json::json;
+
+use super::{get_json, post_json, ActorBackend, ActorError};
+
+pub(crate) struct AhoyDtu {
+    address: String,
+    /// index of the inverter in the AhoyDTU
+    inverter_id: u8,
+    /// empty if the settings are not protected
+    password: String,
+}
+
+impl AhoyDtu {
+    pub(crate) fn new(address: &str, inverter_id: u8, password: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            inverter_id,
+            password: password.to_string(),
+        }
+    }
+
+    /// Send a command to the inverter, logging in first if a password is set
+    async fn command(&self, command: &str, value: &str) -> Result<(), ActorError> {
+        let mut request = json!({ "id": self.inverter_id, "cmd": command, "val": value });
+        if !self.password.is_empty() {
+            let login = json!({ "cmd": "auth", "val": self.password });
+            let response = post_json(&self.address, "/api/ctrl", &login)
+                .await?
+                .ok_or(ActorError::InvalidResponse)?;
+            let token = response["token"]
+                .as_str()
+                .ok_or(ActorError::InvalidResponse)?;
+            request["token"] = json!(token);
+        }
+        let response = post_json(&self.address, "/api/ctrl", &request)
+            .await?
+            .ok_or(ActorError::InvalidResponse)?;
+        match response["success"].as_bool() {
+            Some(true) => Ok(()),
+            _ => Err(ActorError::InvalidResponse),
+        }
+    }
+
+    /// AC output of the inverter, the first channel holds the values named in `fld_names`
+    async fn ac_power(&self) -> Result<Option<f64>, ActorError> {
+        let response = get_json(
+            &self.address,
+            &format!("/api/inverter/id/{}", self.inverter_id),
+        )
+        .await?
+        .ok_or(ActorError::InvalidResponse)?;
+        let index = response["fld_names"]
+            .as_array()
+            .and_then(|names| names.iter().position(|name| name == "P_AC"));
+        Ok(index.and_then(|index| response["ch"][0][index].as_f64()))
+    }
+}
+
+#[async_trait]
+impl ActorBackend for AhoyDtu {
+    async fn switch(&self, on: bool) -> Result<(), ActorError> {
+        self.command("power", if on { "1" } else { "0" }).await
+    }
+
+    async fn is_on(&self) -> Result<bool, ActorError> {
+        Ok(self.ac_power().await?.is_some_and(|power| power > 0.0))
+    }
+
+    /// Power fed in by the inverter
+    async fn power(&self) -> Result<Option<f64>, ActorError> {
+        self.ac_power().await
+    }
+
+    /// Set a temporary absolute power limit
+    async fn set_power(&self, watts: f64) -> Result<(), ActorError> {
+        let watts = (watts.max(0.0).round() as u32).to_string();
+        self.command("limit_nonpersistent_absolute", &watts).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::actors::backend::mock::http_server;
+
+    #[tokio::test]
+    pub async fn sets_limit() {
+        let (address, requests) = http_server(vec![
+            ("/api/ctrl", r#"{"success": true, "token": "abc"}"#),
+            (
+                "/api/inverter/id/1",
+                r#"{"id": 1, "ch": [[230.1, 1.5, 345.5, 50]],
+                    "fld_names": ["U_AC", "I_AC", "P_AC", "F_AC"]}"#,
+            ),
+        ])
+        .await;
+        let ahoy = AhoyDtu::new(&address, 1, "secret");
+
+        ahoy.set_power(400.4).await.unwrap();
+
+        assert_eq!(ahoy.power().await.unwrap(), Some(345.5));
+        assert!(ahoy.is_on().await.unwrap());
+        let requests = requests.lock().unwrap();
+        let body = |i: usize| serde_json::from_str::<Value>(&requests[i].body).unwrap();
+        assert_eq!(body(0), json!({"cmd": "auth", "val": "secret"}));
+        assert_eq!(
+            body(1),
+            json!({"id": 1, "cmd": "limit_nonpersistent_absolute", "val": "400", "token": "abc"})
+        );
+    }
+
+    #[tokio::test]
+    pub async fn reports_failed_commands() {
+        let (address, _) = http_server(vec![("/api/ctrl", r#"{"success": false}"#)]).await;
+        let ahoy = AhoyDtu::new(&address, 0, "");
+
+        assert!(matches!(
+            ahoy.switch(false).await,
+            Err(ActorError::InvalidResponse)
+        ));
+        assert!(matches!(
+            ahoy.power().await,
+            Err(ActorError::InvalidResponse)
+        ));
+    }
+}
@@ -0,0 +1,197 @@
+//! TP-Link smart home protocol: JSON over TCP, XOR "autokey" encrypted with a length prefix
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use super::{ActorBackend, ActorError, REQUEST_TIMEOUT};
+
+const DEFAULT_PORT: u16 = 9999;
+const INITIAL_KEY: u8 = 171;
+/// Responses are a few hundred bytes, anything beyond this is not a plug
+const MAX_RESPONSE_LENGTH: usize = 64 * 1024;
+
+pub(crate) struct Kasa {
+    address: String,
+}
+
+impl Kasa {
+    pub(crate) fn new(address: &str) -> Self {
+        let address = if address.contains(':') {
+            address.to_string()
+        } else {
+            format!("{}:{}", address, DEFAULT_PORT)
+        };
+        Self { address }
+    }
+
+    async fn request(&self, request: Value) -> Result<Value, ActorError> {
+        let exchange = async {
+            let mut stream = TcpStream::connect(&self.address).await?;
+            stream
+                .write_all(&encrypt(request.to_string().as_bytes()))
+                .await?;
+            let length = stream.read_u32().await? as usize;
+            if length > MAX_RESPONSE_LENGTH {
+                return Err(ActorError::InvalidResponse);
+            }
+            let mut response = vec![0; length];
+            stream.read_exact(&mut response).await?;
+            Ok::<_, ActorError>(response)
+        };
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, exchange)
+            .await
+            .map_err(|_| ActorError::Timeout)??;
+        serde_json::from_slice(&decrypt(&response)).map_err(|_| ActorError::InvalidResponse)
+    }
+}
+
+#[async_trait]
+impl ActorBackend for Kasa {
+    async fn switch(&self, on: bool) -> Result<(), ActorError> {
+        let response = self
+            .request(json!({"system": {"set_relay_state": {"state": on as u8}}}))
+            .await?;
+        match response["system"]["set_relay_state"]["err_code"].as_i64() {
+            Some(0) => Ok(()),
+            _ => Err(ActorError::InvalidResponse),
+        }
+    }
+
+    async fn is_on(&self) -> Result<bool, ActorError> {
+        let response = self.request(json!({"system": {"get_sysinfo": {}}})).await?;
+        response["system"]["get_sysinfo"]["relay_state"]
+            .as_u64()
+            .map(|state| state == 1)
+            .ok_or(ActorError::InvalidResponse)
+    }
+
+    async fn power(&self) -> Result<Option<f64>, ActorError> {
+        let response = self
+            .request(json!({"emeter": {"get_realtime": {}}}))
+            .await?;
+        let realtime = &response["emeter"]["get_realtime"];
+        // older hardware reports watts, newer milliwatts, plugs without meter an error
+        Ok(realtime["power"]
+            .as_f64()
+            .or_else(|| realtime["power_mw"].as_f64().map(|mw| mw / 1000.0)))
+    }
+}
+
+fn encrypt(plain: &[u8]) -> Vec<u8> {
+    let mut key = INITIAL_KEY;
+    let mut result = (plain.len() as u32).to_be_bytes().to_vec();
+    for byte in plain {
+        key ^= byte;
+        result.push(key);
+    }
+    result
+}
+
+fn decrypt(cipher: &[u8]) -> Vec<u8> {
+    let mut key = INITIAL_KEY;
+    cipher
+        .iter()
+        .map(|byte| {
+            let plain = key ^ byte;
+            key = *byte;
+            plain
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Plug answering get_sysinfo, set_relay_state and get_realtime like a HS110
+    async fn mock_plug() -> (String, Arc<Mutex<Vec<Value>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let requests = Arc::new(Mutex::new(vec![]));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            let mut relay_state = 0;
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let length = socket.read_u32().await.unwrap() as usize;
+                let mut request = vec![0; length];
+                socket.read_exact(&mut request).await.unwrap();
+                let request: Value = serde_json::from_slice(&decrypt(&request)).unwrap();
+                recorded.lock().unwrap().push(request.clone());
+
+                let response = if let Some(state) =
+                    request["system"]["set_relay_state"]["state"].as_u64()
+                {
+                    relay_state = state;
+                    json!({"system": {"set_relay_state": {"err_code": 0}}})
+                } else if request["system"]["get_sysinfo"].is_object() {
+                    json!({"system": {"get_sysinfo": {"relay_state": relay_state, "err_code": 0}}})
+                } else {
+                    json!({"emeter": {"get_realtime": {"power_mw": 12500, "err_code": 0}}})
+                };
+                socket
+                    .write_all(&encrypt(response.to_string().as_bytes()))
+                    .await
+                    .unwrap();
+            }
+        });
+        (address, requests)
+    }
+
+    #[test]
+    pub fn encrypts_like_the_plug() {
+        let plain = br#"{"system":{"get_sysinfo":{}}}"#;
+        let cipher = encrypt(plain);
+
+        assert_eq!(&cipher[..4], &[0, 0, 0, 29]);
+        assert_eq!(&cipher[4..8], &[0xd0, 0xf2, 0x81, 0xf8]);
+        assert_eq!(decrypt(&cipher[4..]), plain.to_vec());
+    }
+
+    #[tokio::test]
+    pub async fn switches_and_reads_state() {
+        let (address, requests) = mock_plug().await;
+        let plug = Kasa::new(&address);
+
+        plug.switch(true).await.unwrap();
+
+        assert!(plug.is_on().await.unwrap());
+        assert_eq!(plug.power().await.unwrap(), Some(12.5));
+        assert_eq!(
+            requests.lock().unwrap()[0],
+            json!({"system": {"set_relay_state": {"state": 1}}})
+        );
+    }
+
+    #[tokio::test]
+    pub async fn rejects_oversized_responses() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            socket.write_u32(u32::MAX).await.unwrap();
+            let mut request = vec![];
+            socket.read_to_end(&mut request).await.unwrap();
+        });
+
+        let result = Kasa::new(&address).is_on().await;
+
+        assert!(matches!(result, Err(ActorError::InvalidResponse)));
+    }
+
+    #[test]
+    pub fn adds_default_port() {
+        assert_eq!(Kasa::new("192.168.178.12").address, "192.168.178.12:9999");
+        assert_eq!(
+            Kasa::new("192.168.178.12:9999").address,
+            "192.168.178.12:9999"
+        );
+    }
+}
@@ -0,0 +1,332 @@
+use std::{fmt, io, time::Duration};
+
+use async_trait::async_trait;
+use hyper::{
+    body::{to_bytes, Bytes},
+    header, Body, Client, Method, Request, StatusCode, Uri,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{modbus::ModbusError, ActorConfiguration};
+
+use self::{
+    ahoydtu::AhoyDtu,
+    kasa::Kasa,
+    modbus::Modbus,
+    mypv::MyPv,
+    opendtu::OpenDtu,
+    shelly::{ShellyGen1, ShellyGen2},
+    tasmota::Tasmota,
+};
+
+pub(crate) mod ahoydtu;
+mod kasa;
+pub(crate) mod modbus;
+mod mypv;
+pub(crate) mod opendtu;
+mod shelly;
+mod tasmota;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Kind of device, the `type` of an actor in the configuration
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ActorType {
+    /// TP-Link Kasa smart plugs (HS100, HS110, KP115, ...)
+    #[default]
+    Kasa,
+    ShellyGen1,
+    ShellyGen2,
+    Tasmota,
+    /// my-PV power controllers for heating rods (ELWA, AC THOR), power setpoint
+    MyPv,
+    /// inverters connected to OpenDTU, the setpoint lowers the power limit
+    OpenDtu,
+    /// inverters connected to AhoyDTU, `channel` is the index of the inverter
+    AhoyDtu,
+    /// devices controlled via Modbus TCP, see `modbus` for the register map
+    Modbus,
+}
+
+impl ActorType {
+    /// Whether the device accepts a power setpoint instead of being switched
+    pub(crate) fn is_adjustable(&self) -> bool {
+        matches!(
+            self,
+            ActorType::MyPv | ActorType::OpenDtu | ActorType::AhoyDtu
+        )
+    }
+
+    /// Whether the device feeds in, it takes up the surplus by lowering its power limit
+    pub(crate) fn is_inverter(&self) -> bool {
+        matches!(self, ActorType::OpenDtu | ActorType::AhoyDtu)
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub(crate) enum ActorError {
+    Io(io::Error),
+    Http(hyper::Error),
+    InvalidUri,
+    Status(StatusCode),
+    Timeout,
+    InvalidResponse,
+    Unsupported,
+    Modbus(ModbusError),
+}
+
+impl fmt::Display for ActorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ActorError::Io(e) => write!(f, "{}", e),
+            ActorError::Http(e) => write!(f, "{}", e),
+            ActorError::InvalidUri => write!(f, "invalid address"),
+            ActorError::Status(status) => write!(f, "device answered with {}", status),
+            ActorError::Timeout => write!(f, "no answer from device"),
+            ActorError::InvalidResponse => write!(f, "unexpected answer from device"),
+            ActorError::Unsupported => write!(f, "not supported by the device"),
+            ActorError::Modbus(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<ModbusError> for ActorError {
+    fn from(e: ModbusError) -> Self {
+        ActorError::Modbus(e)
+    }
+}
+
+impl From<io::Error> for ActorError {
+    fn from(e: io::Error) -> Self {
+        ActorError::Io(e)
+    }
+}
+
+impl From<hyper::Error> for ActorError {
+    fn from(e: hyper::Error) -> Self {
+        ActorError::Http(e)
+    }
+}
+
+/// A switchable device
+#[async_trait]
+pub(crate) trait ActorBackend: Send + Sync {
+    async fn switch(&self, on: bool) -> Result<(), ActorError>;
+
+    async fn is_on(&self) -> Result<bool, ActorError>;
+
+    /// Current power draw in watts, `None` if the device cannot measure it
+    async fn power(&self) -> Result<Option<f64>, ActorError>;
+
+    /// Set the power of an adjustable device in watts
+    async fn set_power(&self, _watts: f64) -> Result<(), ActorError> {
+        Err(ActorError::Unsupported)
+    }
+}
+
+/// Backend for the device at `address` (`host` or `host:port`) of an actor
+pub(crate) fn create_backend(actor: &ActorConfiguration) -> Box<dyn ActorBackend> {
+    let address = actor.address.as_str();
+    match actor.actor_type {
+        ActorType::Kasa => Box::new(Kasa::new(address)),
+        ActorType::ShellyGen1 => Box::new(ShellyGen1::new(address, actor.channel)),
+        ActorType::ShellyGen2 => Box::new(ShellyGen2::new(address, actor.channel)),
+        ActorType::Tasmota => Box::new(Tasmota::new(address, actor.channel)),
+        ActorType::MyPv => Box::new(MyPv::new(address)),
+        ActorType::OpenDtu => Box::new(OpenDtu::new(
+            address,
+            actor.serial.as_deref().unwrap_or_default(),
+            actor.password.as_deref().unwrap_or_default(),
+        )),
+        ActorType::AhoyDtu => Box::new(AhoyDtu::new(
+            address,
+            actor.channel,
+            actor.password.as_deref().unwrap_or_default(),
+        )),
+        ActorType::Modbus => Box::new(Modbus::new(address, actor.modbus.clone())),
+    }
+}
+
+/// Credentials for HTTP basic authentication
+struct BasicAuth<'a> {
+    user: &'a str,
+    password: &'a str,
+}
+
+async fn request(
+    method: Method,
+    address: &str,
+    path_and_query: &str,
+    content: Option<(&str, String)>,
+    auth: Option<BasicAuth<'_>>,
+) -> Result<(StatusCode, Bytes), ActorError> {
+    let uri = format!("http://{}{}", address, path_and_query)
+        .parse::<Uri>()
+        .map_err(|_| ActorError::InvalidUri)?;
+    let mut builder = Request::builder().method(method).uri(uri);
+    if let Some(auth) = auth {
+        let credentials = base64::encode(format!("{}:{}", auth.user, auth.password));
+        builder = builder.header(header::AUTHORIZATION, format!("Basic {}", credentials));
+    }
+    let body = match content {
+        Some((content_type, content)) => {
+            builder = builder.header(header::CONTENT_TYPE, content_type);
+            Body::from(content)
+        }
+        None => Body::empty(),
+    };
+    let request = builder.body(body).map_err(|_| ActorError::InvalidUri)?;
+    let exchange = async {
+        let response = Client::new().request(request).await?;
+        let status = response.status();
+        let body = to_bytes(response.into_body()).await?;
+        Ok::<_, ActorError>((status, body))
+    };
+    tokio::time::timeout(REQUEST_TIMEOUT, exchange)
+        .await
+        .map_err(|_| ActorError::Timeout)?
+}
+
+/// GET request to a device answering with JSON
+///
+/// `Ok(None)` if the device does not know the path (404).
+async fn get_json(address: &str, path_and_query: &str) -> Result<Option<Value>, ActorError> {
+    let (status, body) = request(Method::GET, address, path_and_query, None, None).await?;
+    json_response(status, &body)
+}
+
+/// GET request to a device, ignoring the content of the answer
+async fn get(address: &str, path_and_query: &str) -> Result<(), ActorError> {
+    match request(Method::GET, address, path_and_query, None, None).await? {
+        (status, _) if status.is_success() => Ok(()),
+        (status, _) => Err(ActorError::Status(status)),
+    }
+}
+
+/// POST request with a form to a device answering with JSON
+async fn post_form(
+    address: &str,
+    path: &str,
+    form: &[(&str, &str)],
+    auth: Option<BasicAuth<'_>>,
+) -> Result<Option<Value>, ActorError> {
+    let form = form
+        .iter()
+        .map(|(key, value)| format!("{}={}", percent_encode(key), percent_encode(value)))
+        .collect::<Vec<_>>()
+        .join("&");
+    let content = ("application/x-www-form-urlencoded", form);
+    let (status, body) = request(Method::POST, address, path, Some(content), auth).await?;
+    json_response(status, &body)
+}
+
+/// POST request with JSON to a device answering with JSON
+async fn post_json(address: &str, path: &str, json: &Value) -> Result<Option<Value>, ActorError> {
+    let content = ("application/json", json.to_string());
+    let (status, body) = request(Method::POST, address, path, Some(content), None).await?;
+    json_response(status, &body)
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn json_response(status: StatusCode, body: &[u8]) -> Result<Option<Value>, ActorError> {
+    match status {
+        StatusCode::NOT_FOUND => Ok(None),
+        status if status.is_success() => serde_json::from_slice(body)
+            .map(Some)
+            .map_err(|_| ActorError::InvalidResponse),
+        status => Err(ActorError::Status(status)),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// A request received by the mock server
+    #[derive(Clone, Debug)]
+    pub(crate) struct Request {
+        pub(crate) path: String,
+        /// request line and headers
+        pub(crate) head: String,
+        pub(crate) body: String,
+    }
+
+    /// HTTP server answering requests whose path starts with one of the prefixes with
+    /// the given JSON, other requests with 404
+    ///
+    /// Returns the address and all requests.
+    pub(crate) async fn http_server(
+        responses: Vec<(&'static str, &'static str)>,
+    ) -> (String, Arc<Mutex<Vec<Request>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let requests = Arc::new(Mutex::new(vec![]));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = vec![];
+                let mut buf = [0; 1024];
+                let mut head_length = None;
+                loop {
+                    if head_length.is_none() {
+                        head_length = request
+                            .windows(4)
+                            .position(|w| w == b"\r\n\r\n")
+                            .map(|p| p + 4);
+                    }
+                    if let Some(head_length) = head_length {
+                        let head = String::from_utf8_lossy(&request[..head_length]).to_lowercase();
+                        let content_length = head
+                            .lines()
+                            .find_map(|line| line.strip_prefix("content-length:"))
+                            .and_then(|length| length.trim().parse::<usize>().ok())
+                            .unwrap_or(0);
+                        if request.len() >= head_length + content_length {
+                            break;
+                        }
+                    }
+                    match socket.read(&mut buf).await {
+                        Ok(n) if n > 0 => request.extend_from_slice(&buf[..n]),
+                        _ => break,
+                    }
+                }
+                let head_length = head_length.unwrap_or(request.len());
+                let head = String::from_utf8_lossy(&request[..head_length]).to_string();
+                let body = String::from_utf8_lossy(&request[head_length..]).to_string();
+                let path = head.split(' ').nth(1).unwrap_or_default().to_string();
+                let response = match responses.iter().find(|(p, _)| path.starts_with(p)) {
+                    Some((_, body)) => format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    ),
+                    None => "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                        .to_string(),
+                };
+                recorded.lock().unwrap().push(Request { path, head, body });
+                let _ = socket.write_all(response.as_bytes()).await;
+                let _ = socket.shutdown().await;
+            }
+        });
+        (address, requests)
+    }
+}
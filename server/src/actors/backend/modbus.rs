@@ -0,0 +1,167 @@
+//! Devices controlled via Modbus TCP, e.g. inverters, batteries and wallboxes
+
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::modbus::{Client, Register, RegisterKind};
+
+use super::{ActorBackend, ActorError};
+
+/// Registers of a device, the `modbus` section of an actor
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct ModbusMap {
+    #[serde(default = "default_unit_id")]
+    pub(crate) unit_id: u8,
+    /// switches the device on and off
+    pub(crate) switch: Option<Switch>,
+    /// the device is adjustable if set
+    pub(crate) setpoint: Option<Register>,
+    /// current power draw
+    pub(crate) power: Option<Register>,
+}
+
+impl Default for ModbusMap {
+    fn default() -> Self {
+        Self {
+            unit_id: default_unit_id(),
+            switch: None,
+            setpoint: None,
+            power: None,
+        }
+    }
+}
+
+fn default_unit_id() -> u8 {
+    1
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Switch {
+    Coil {
+        address: u16,
+    },
+    /// holding register set to `on` or `off`
+    Register {
+        address: u16,
+        #[serde(default = "default_on")]
+        on: u16,
+        #[serde(default)]
+        off: u16,
+    },
+}
+
+fn default_on() -> u16 {
+    1
+}
+
+pub(crate) struct Modbus {
+    client: Client,
+    map: ModbusMap,
+}
+
+impl Modbus {
+    pub(crate) fn new(address: &str, map: ModbusMap) -> Self {
+        Self {
+            client: Client::new(address, map.unit_id),
+            map,
+        }
+    }
+}
+
+#[async_trait]
+impl ActorBackend for Modbus {
+    async fn switch(&self, on: bool) -> Result<(), ActorError> {
+        match self.map.switch {
+            Some(Switch::Coil { address }) => Ok(self.client.write_coil(address, on).await?),
+            Some(Switch::Register {
+                address,
+                on: on_value,
+                off: off_value,
+            }) => {
+                let value = if on { on_value } else { off_value };
+                Ok(self.client.write_registers(address, &[value]).await?)
+            }
+            None => Err(ActorError::Unsupported),
+        }
+    }
+
+    async fn is_on(&self) -> Result<bool, ActorError> {
+        match (&self.map.switch, &self.map.setpoint) {
+            (Some(Switch::Coil { address }), _) => Ok(self.client.read_coil(*address).await?),
+            (Some(Switch::Register { address, on, .. }), _) => {
+                let value = self
+                    .client
+                    .read_registers(RegisterKind::Holding, *address, 1)
+                    .await?;
+                Ok(value[0] == *on)
+            }
+            (None, Some(setpoint)) => Ok(self.client.read(setpoint).await? > 0.0),
+            (None, None) => Err(ActorError::Unsupported),
+        }
+    }
+
+    async fn power(&self) -> Result<Option<f64>, ActorError> {
+        match &self.map.power {
+            Some(register) => Ok(Some(self.client.read(register).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn set_power(&self, watts: f64) -> Result<(), ActorError> {
+        match &self.map.setpoint {
+            Some(register) => Ok(self.client.write(register, watts.max(0.0)).await?),
+            None => Err(ActorError::Unsupported),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::modbus::simulator::simulator;
+
+    #[tokio::test]
+    pub async fn switches_register() {
+        let (address, device) = simulator(3).await;
+        device.lock().unwrap().input.insert(100, 2300);
+        let map = serde_yaml::from_str::<ModbusMap>(
+            "unit_id: 3\n\
+             switch: {type: register, address: 10, on: 2, off: 1}\n\
+             power: {address: 100, kind: input}",
+        )
+        .unwrap();
+        let modbus = Modbus::new(&address, map);
+
+        modbus.switch(true).await.unwrap();
+
+        assert_eq!(device.lock().unwrap().holding[&10], 2);
+        assert!(modbus.is_on().await.unwrap());
+        assert_eq!(modbus.power().await.unwrap(), Some(2300.0));
+        modbus.switch(false).await.unwrap();
+        assert!(!modbus.is_on().await.unwrap());
+        assert!(matches!(
+            modbus.set_power(100.0).await,
+            Err(ActorError::Unsupported)
+        ));
+    }
+
+    #[tokio::test]
+    pub async fn sets_power() {
+        let (address, device) = simulator(1).await;
+        let map = serde_yaml::from_str::<ModbusMap>(
+            "switch: {type: coil, address: 0}\n\
+             setpoint: {address: 2000, data_type: i16, scale: 10}",
+        )
+        .unwrap();
+        let modbus = Modbus::new(&address, map);
+
+        modbus.set_power(1500.0).await.unwrap();
+        modbus.switch(true).await.unwrap();
+
+        assert_eq!(device.lock().unwrap().holding[&2000], 150);
+        assert!(device.lock().unwrap().coils[&0]);
+        assert!(modbus.is_on().await.unwrap());
+        assert_eq!(modbus.power().await.unwrap(), None);
+    }
+}
@@ -0,0 +1,74 @@
+//! my-PV power controllers for heating rods (ELWA, AC THOR) via their HTTP API
+
+use async_trait::async_trait;
+
+use super::{get, get_json, ActorBackend, ActorError};
+
+pub(crate) struct MyPv {
+    address: String,
+}
+
+impl MyPv {
+    pub(crate) fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl ActorBackend for MyPv {
+    /// The heating rod can only be switched on by a power setpoint
+    async fn switch(&self, on: bool) -> Result<(), ActorError> {
+        if on {
+            Err(ActorError::Unsupported)
+        } else {
+            self.set_power(0.0).await
+        }
+    }
+
+    async fn is_on(&self) -> Result<bool, ActorError> {
+        Ok(self.power().await?.is_some_and(|power| power > 0.0))
+    }
+
+    async fn power(&self) -> Result<Option<f64>, ActorError> {
+        let response = get_json(&self.address, "/data.jsn")
+            .await?
+            .ok_or(ActorError::InvalidResponse)?;
+        Ok(response["power"].as_f64())
+    }
+
+    async fn set_power(&self, watts: f64) -> Result<(), ActorError> {
+        let path = format!("/control.html?power={}", watts.max(0.0).round() as u32);
+        get(&self.address, &path).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::actors::backend::mock::http_server;
+
+    #[tokio::test]
+    pub async fn sets_power() {
+        let (address, requests) = http_server(vec![
+            ("/control.html", "{}"),
+            ("/data.jsn", r#"{"device": "AC-THOR", "power": 1250}"#),
+        ])
+        .await;
+        let mypv = MyPv::new(&address);
+
+        mypv.set_power(1249.6).await.unwrap();
+        mypv.switch(false).await.unwrap();
+
+        assert!(mypv.is_on().await.unwrap());
+        assert_eq!(mypv.power().await.unwrap(), Some(1250.0));
+        assert!(matches!(
+            mypv.switch(true).await,
+            Err(ActorError::Unsupported)
+        ));
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0].path, "/control.html?power=1250");
+        assert_eq!(requests[1].path, "/control.html?power=0");
+    }
+}
@@ -0,0 +1,140 @@
+//! Inverters connected to an OpenDTU, the power limit of the inverter is the setpoint
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use super::{get_json, post_form, ActorBackend, ActorError, BasicAuth};
+
+/// Web interface user allowed to change settings
+const USER: &str = "admin";
+
+pub(crate) struct OpenDtu {
+    address: String,
+    /// serial number of the inverter
+    serial: String,
+    password: String,
+}
+
+impl OpenDtu {
+    pub(crate) fn new(address: &str, serial: &str, password: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            serial: serial.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    /// Send a setting, OpenDTU expects the JSON in the form field `data`
+    async fn configure(&self, path: &str, data: Value) -> Result<(), ActorError> {
+        let auth = BasicAuth {
+            user: USER,
+            password: &self.password,
+        };
+        let response = post_form(
+            &self.address,
+            path,
+            &[("data", &data.to_string())],
+            Some(auth),
+        )
+        .await?
+        .ok_or(ActorError::InvalidResponse)?;
+        match response["type"].as_str() {
+            Some("success") => Ok(()),
+            _ => Err(ActorError::InvalidResponse),
+        }
+    }
+
+    /// Live data of the inverter
+    async fn status(&self) -> Result<Value, ActorError> {
+        let response = get_json(
+            &self.address,
+            &format!("/api/livedata/status?inv={}", self.serial),
+        )
+        .await?
+        .ok_or(ActorError::InvalidResponse)?;
+        response["inverters"]
+            .as_array()
+            .and_then(|inverters| {
+                inverters
+                    .iter()
+                    .find(|inverter| inverter["serial"].as_str() == Some(self.serial.as_str()))
+            })
+            .cloned()
+            .ok_or(ActorError::InvalidResponse)
+    }
+}
+
+#[async_trait]
+impl ActorBackend for OpenDtu {
+    async fn switch(&self, on: bool) -> Result<(), ActorError> {
+        self.configure(
+            "/api/power/config",
+            json!({ "serial": self.serial, "power": on }),
+        )
+        .await
+    }
+
+    async fn is_on(&self) -> Result<bool, ActorError> {
+        self.status().await?["producing"]
+            .as_bool()
+            .ok_or(ActorError::InvalidResponse)
+    }
+
+    /// Power fed in by the inverter
+    async fn power(&self) -> Result<Option<f64>, ActorError> {
+        Ok(self.status().await?["AC"]["0"]["Power"]["v"].as_f64())
+    }
+
+    /// Set a temporary absolute power limit
+    async fn set_power(&self, watts: f64) -> Result<(), ActorError> {
+        self.configure(
+            "/api/limit/config",
+            json!({
+                "serial": self.serial,
+                "limit_type": 0,
+                "limit_value": watts.max(0.0).round() as u32
+            }),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::actors::backend::mock::http_server;
+
+    #[tokio::test]
+    pub async fn sets_limit() {
+        let (address, requests) = http_server(vec![
+            ("/api/limit/config", r#"{"type": "success", "code": 1001}"#),
+            ("/api/power/config", r#"{"type": "warning", "code": 1002}"#),
+            (
+                "/api/livedata/status",
+                r#"{"inverters": [{"serial": "116180400144", "producing": true,
+                    "AC": {"0": {"Power": {"v": 412.5, "u": "W", "d": 1}}}}]}"#,
+            ),
+        ])
+        .await;
+        let dtu = OpenDtu::new(&address, "116180400144", "secret");
+
+        dtu.set_power(600.0).await.unwrap();
+
+        assert!(dtu.is_on().await.unwrap());
+        assert_eq!(dtu.power().await.unwrap(), Some(412.5));
+        assert!(matches!(
+            dtu.switch(false).await,
+            Err(ActorError::InvalidResponse)
+        ));
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0].path, "/api/limit/config");
+        assert!(requests[0]
+            .head
+            .contains(&format!("Basic {}", base64::encode("admin:secret"))));
+        assert_eq!(
+            requests[0].body,
+            "data=%7B%22limit_type%22%3A0%2C%22limit_value%22%3A600%2C%22serial%22%3A%22116180400144%22%7D"
+        );
+        assert_eq!(requests[1].path, "/api/livedata/status?inv=116180400144");
+    }
+}
@@ -0,0 +1,150 @@
+//! Shelly devices, the HTTP API of the first generation and the RPC API of the second
+//! generation (Plus and Pro devices)
+
+use async_trait::async_trait;
+
+use super::{get_json, ActorBackend, ActorError};
+
+pub(crate) struct ShellyGen1 {
+    address: String,
+    channel: u8,
+}
+
+impl ShellyGen1 {
+    pub(crate) fn new(address: &str, channel: u8) -> Self {
+        Self {
+            address: address.to_string(),
+            channel,
+        }
+    }
+}
+
+#[async_trait]
+impl ActorBackend for ShellyGen1 {
+    async fn switch(&self, on: bool) -> Result<(), ActorError> {
+        let turn = if on { "on" } else { "off" };
+        let path = format!("/relay/{}?turn={}", self.channel, turn);
+        match get_json(&self.address, &path).await? {
+            Some(relay) if relay["ison"].as_bool() == Some(on) => Ok(()),
+            _ => Err(ActorError::InvalidResponse),
+        }
+    }
+
+    async fn is_on(&self) -> Result<bool, ActorError> {
+        let path = format!("/relay/{}", self.channel);
+        get_json(&self.address, &path)
+            .await?
+            .and_then(|relay| relay["ison"].as_bool())
+            .ok_or(ActorError::InvalidResponse)
+    }
+
+    async fn power(&self) -> Result<Option<f64>, ActorError> {
+        // devices without power metering do not have a meter
+        let path = format!("/meter/{}", self.channel);
+        Ok(get_json(&self.address, &path)
+            .await?
+            .and_then(|meter| meter["power"].as_f64()))
+    }
+}
+
+pub(crate) struct ShellyGen2 {
+    address: String,
+    channel: u8,
+}
+
+impl ShellyGen2 {
+    pub(crate) fn new(address: &str, channel: u8) -> Self {
+        Self {
+            address: address.to_string(),
+            channel,
+        }
+    }
+}
+
+#[async_trait]
+impl ActorBackend for ShellyGen2 {
+    async fn switch(&self, on: bool) -> Result<(), ActorError> {
+        let path = format!("/rpc/Switch.Set?id={}&on={}", self.channel, on);
+        match get_json(&self.address, &path).await? {
+            // the response contains the previous state
+            Some(response) if response["was_on"].is_boolean() => Ok(()),
+            _ => Err(ActorError::InvalidResponse),
+        }
+    }
+
+    async fn is_on(&self) -> Result<bool, ActorError> {
+        let path = format!("/rpc/Switch.GetStatus?id={}", self.channel);
+        get_json(&self.address, &path)
+            .await?
+            .and_then(|status| status["output"].as_bool())
+            .ok_or(ActorError::InvalidResponse)
+    }
+
+    async fn power(&self) -> Result<Option<f64>, ActorError> {
+        let path = format!("/rpc/Switch.GetStatus?id={}", self.channel);
+        let status = get_json(&self.address, &path)
+            .await?
+            .ok_or(ActorError::InvalidResponse)?;
+        Ok(status["apower"].as_f64())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::actors::backend::mock::http_server;
+
+    #[tokio::test]
+    pub async fn switches_gen1_relay() {
+        let (address, requests) = http_server(vec![
+            ("/relay/1?turn=on", r#"{"ison": true, "has_timer": false}"#),
+            ("/relay/1", r#"{"ison": false, "has_timer": false}"#),
+            ("/meter/1", r#"{"power": 42.5, "is_valid": true}"#),
+        ])
+        .await;
+        let shelly = ShellyGen1::new(&address, 1);
+
+        shelly.switch(true).await.unwrap();
+
+        assert!(!shelly.is_on().await.unwrap());
+        assert_eq!(shelly.power().await.unwrap(), Some(42.5));
+        assert_eq!(requests.lock().unwrap()[0].path, "/relay/1?turn=on");
+    }
+
+    #[tokio::test]
+    pub async fn gen1_without_meter_has_no_power() {
+        let (address, _) = http_server(vec![("/relay/0", r#"{"ison": true}"#)]).await;
+        let shelly = ShellyGen1::new(&address, 0);
+
+        assert_eq!(shelly.power().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    pub async fn switches_gen2_output() {
+        let (address, requests) = http_server(vec![
+            ("/rpc/Switch.Set", r#"{"was_on": true}"#),
+            (
+                "/rpc/Switch.GetStatus",
+                r#"{"id": 0, "output": true, "apower": 8.3}"#,
+            ),
+        ])
+        .await;
+        let shelly = ShellyGen2::new(&address, 0);
+
+        shelly.switch(false).await.unwrap();
+
+        assert!(shelly.is_on().await.unwrap());
+        assert_eq!(shelly.power().await.unwrap(), Some(8.3));
+        assert_eq!(
+            requests.lock().unwrap()[0].path,
+            "/rpc/Switch.Set?id=0&on=false"
+        );
+    }
+
+    #[tokio::test]
+    pub async fn reports_unreachable_devices() {
+        let shelly = ShellyGen2::new("127.0.0.1:1", 0);
+
+        assert!(shelly.switch(true).await.is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//! Tasmota devices, commands sent via the `/cm` HTTP endpoint
+
+use async_trait::async_trait;
+use serde_json::Value;
+
+use super::{get_json, ActorBackend, ActorError};
+
+pub(crate) struct Tasmota {
+    address: String,
+    /// relay index, 0 addresses the first (or only) relay
+    channel: u8,
+}
+
+impl Tasmota {
+    pub(crate) fn new(address: &str, channel: u8) -> Self {
+        Self {
+            address: address.to_string(),
+            channel,
+        }
+    }
+
+    /// relay number as used in Tasmota commands, starting at 1
+    fn relay(&self) -> u16 {
+        u16::from(self.channel) + 1
+    }
+
+    async fn command(&self, command: &str) -> Result<Value, ActorError> {
+        let path = format!("/cm?cmnd={}", command.replace(' ', "%20"));
+        get_json(&self.address, &path)
+            .await?
+            .ok_or(ActorError::InvalidResponse)
+    }
+
+    /// State of the relay in a response like `{"POWER": "ON"}` or `{"POWER2": "OFF"}`
+    fn relay_state(&self, response: &Value) -> Option<bool> {
+        let numbered = format!("POWER{}", self.relay());
+        let state = response.get(&numbered).or_else(|| response.get("POWER"))?;
+        match state.as_str()? {
+            "ON" => Some(true),
+            "OFF" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl ActorBackend for Tasmota {
+    async fn switch(&self, on: bool) -> Result<(), ActorError> {
+        let state = if on { "On" } else { "Off" };
+        let response = self
+            .command(&format!("Power{} {}", self.relay(), state))
+            .await?;
+        match self.relay_state(&response) {
+            Some(state) if state == on => Ok(()),
+            _ => Err(ActorError::InvalidResponse),
+        }
+    }
+
+    async fn is_on(&self) -> Result<bool, ActorError> {
+        let response = self.command(&format!("Power{}", self.relay())).await?;
+        self.relay_state(&response)
+            .ok_or(ActorError::InvalidResponse)
+    }
+
+    async fn power(&self) -> Result<Option<f64>, ActorError> {
+        // sensor status, contains the energy readings of devices with power metering
+        let response = self.command("Status 8").await?;
+        Ok(response["StatusSNS"]["ENERGY"]["Power"].as_f64())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::actors::backend::mock::http_server;
+
+    #[tokio::test]
+    pub async fn switches_relay() {
+        let (address, requests) = http_server(vec![
+            ("/cm?cmnd=Power2%20On", r#"{"POWER2": "ON"}"#),
+            ("/cm?cmnd=Power2", r#"{"POWER2": "OFF"}"#),
+            (
+                "/cm?cmnd=Status%208",
+                r#"{"StatusSNS": {"Time": "2023-01-01T12:00:00", "ENERGY": {"Power": 150}}}"#,
+            ),
+        ])
+        .await;
+        let tasmota = Tasmota::new(&address, 1);
+
+        tasmota.switch(true).await.unwrap();
+
+        assert!(!tasmota.is_on().await.unwrap());
+        assert_eq!(tasmota.power().await.unwrap(), Some(150.0));
+        assert_eq!(requests.lock().unwrap()[0].path, "/cm?cmnd=Power2%20On");
+    }
+
+    #[tokio::test]
+    pub async fn reads_single_relay() {
+        let (address, _) = http_server(vec![
+            ("/cm?cmnd=Power1", r#"{"POWER": "ON"}"#),
+            (
+                "/cm?cmnd=Status%208",
+                r#"{"StatusSNS": {"Time": "2023-01-01T12:00:00"}}"#,
+            ),
+        ])
+        .await;
+        let tasmota = Tasmota::new(&address, 0);
+
+        assert!(tasmota.is_on().await.unwrap());
+        assert_eq!(tasmota.power().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    pub async fn addresses_last_channel() {
+        let (address, requests) =
+            http_server(vec![("/cm?cmnd=Power256%20Off", r#"{"POWER256": "OFF"}"#)]).await;
+        let tasmota = Tasmota::new(&address, u8::MAX);
+
+        tasmota.switch(false).await.unwrap();
+
+        assert_eq!(requests.lock().unwrap()[0].path, "/cm?cmnd=Power256%20Off");
+    }
+}
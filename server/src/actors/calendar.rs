@@ -0,0 +1,268 @@
+use chrono::{
+    DateTime, Datelike, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday,
+};
+use chrono_tz::Tz;
+use serde::{de, Deserialize, Deserializer};
+
+/// Timezone the time windows and days of an actor are evaluated in
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) enum Zone {
+    /// timezone of the system
+    #[default]
+    Local,
+    Named(Tz),
+}
+
+impl From<Option<Tz>> for Zone {
+    fn from(timezone: Option<Tz>) -> Self {
+        timezone.map_or(Zone::Local, Zone::Named)
+    }
+}
+
+impl Zone {
+    pub(crate) fn local_time(&self, now: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            Zone::Local => now.with_timezone(&Local).naive_local(),
+            Zone::Named(tz) => now.with_timezone(tz).naive_local(),
+        }
+    }
+
+    /// The first point in time showing the local time, `None` if it is skipped
+    pub(crate) fn to_utc(self, local: &NaiveDateTime) -> Option<DateTime<Utc>> {
+        match self {
+            Zone::Local => Local
+                .from_local_datetime(local)
+                .earliest()
+                .map(|time| time.with_timezone(&Utc)),
+            Zone::Named(tz) => tz
+                .from_local_datetime(local)
+                .earliest()
+                .map(|time| time.with_timezone(&Utc)),
+        }
+    }
+}
+
+/// Period in which an actor may be switched on
+///
+/// All fields are optional, e.g. a window with just `days` covers these days completely.
+/// A window with `end` before `start` spans midnight, `days`, `from` and `until` refer to
+/// the day it starts on then: `days: [fri]` from 22:00 to 06:00 covers the night to saturday.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub(crate) struct TimeWindow {
+    /// e.g. `[mon, tue]`, all days if empty
+    #[serde(default)]
+    pub(crate) days: Vec<Weekday>,
+    /// e.g. `"08:00"`
+    #[serde(default, deserialize_with = "time_of_day")]
+    pub(crate) start: Option<NaiveTime>,
+    #[serde(default, deserialize_with = "time_of_day")]
+    pub(crate) end: Option<NaiveTime>,
+    /// first day of the window
+    pub(crate) from: Option<NaiveDate>,
+    /// last day of the window
+    pub(crate) until: Option<NaiveDate>,
+}
+
+/// Times with or without seconds
+pub(crate) fn time_of_day<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<NaiveTime>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|time| {
+            NaiveTime::parse_from_str(&time, "%H:%M")
+                .or_else(|_| NaiveTime::parse_from_str(&time, "%H:%M:%S"))
+                .map_err(|_| de::Error::custom(format!("invalid time {}, expected HH:MM", time)))
+        })
+        .transpose()
+}
+
+impl TimeWindow {
+    fn contains(&self, local: NaiveDateTime) -> bool {
+        let time = local.time();
+        // the day the current period of the window started on
+        let date = match (self.start, self.end) {
+            (Some(start), Some(end)) if end < start => {
+                if time >= start {
+                    Some(local.date())
+                } else if time < end {
+                    local.date().pred_opt()
+                } else {
+                    None
+                }
+            }
+            (Some(start), Some(end)) if start < end => {
+                (start <= time && time < end).then_some(local.date())
+            }
+            (Some(start), None) => (start <= time).then_some(local.date()),
+            (None, Some(end)) => (time < end).then_some(local.date()),
+            _ => Some(local.date()),
+        };
+        let Some(date) = date else {
+            return false;
+        };
+        if self.from.is_some_and(|from| date < from) || self.until.is_some_and(|until| date > until)
+        {
+            return false;
+        }
+        self.days.is_empty() || self.days.contains(&date.weekday())
+    }
+}
+
+/// Time windows of an actor, it may run any time without windows
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Schedule {
+    pub(crate) zone: Zone,
+    pub(crate) windows: Vec<TimeWindow>,
+}
+
+impl Schedule {
+    pub(crate) fn is_open(&self, now: DateTime<Utc>) -> bool {
+        let local = self.zone.local_time(now);
+        self.windows.is_empty() || self.windows.iter().any(|window| window.contains(local))
+    }
+
+    pub(crate) fn day(&self, now: DateTime<Utc>) -> NaiveDate {
+        self.zone.local_time(now).date()
+    }
+
+    /// Local midnight at the start of `day`
+    pub(crate) fn start_of_day(&self, day: NaiveDate) -> Option<DateTime<Utc>> {
+        self.zone.to_utc(&day.and_hms_opt(0, 0, 0)?)
+    }
+
+    pub(crate) fn until_midnight(&self, now: DateTime<Utc>) -> chrono::Duration {
+        let midnight = self
+            .day(now)
+            .succ_opt()
+            .and_then(|day| self.start_of_day(day));
+        match midnight {
+            Some(midnight) => midnight - now,
+            None => chrono::Duration::zero(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono_tz::Europe::Berlin;
+
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> Option<NaiveTime> {
+        NaiveTime::from_hms_opt(hour, minute, 0)
+    }
+
+    /// 2024-06-03 is a monday
+    fn local(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 6, day)
+            .and_then(|date| date.and_hms_opt(hour, minute, 0))
+            .unwrap()
+    }
+
+    #[test]
+    pub fn checks_times_of_day() {
+        let window = TimeWindow {
+            start: time(8, 0),
+            end: time(18, 30),
+            ..Default::default()
+        };
+
+        assert!(!window.contains(local(3, 7, 59)));
+        assert!(window.contains(local(3, 8, 0)));
+        assert!(window.contains(local(3, 18, 29)));
+        assert!(!window.contains(local(3, 18, 30)));
+    }
+
+    #[test]
+    pub fn spans_midnight() {
+        let window = TimeWindow {
+            start: time(22, 0),
+            end: time(6, 0),
+            ..Default::default()
+        };
+
+        assert!(window.contains(local(3, 23, 0)));
+        assert!(window.contains(local(4, 5, 59)));
+        assert!(!window.contains(local(4, 12, 0)));
+    }
+
+    #[test]
+    pub fn assigns_night_to_start_day() {
+        let window = TimeWindow {
+            days: vec![Weekday::Fri],
+            start: time(22, 0),
+            end: time(6, 0),
+            ..Default::default()
+        };
+
+        assert!(!window.contains(local(7, 5, 0)));
+        assert!(window.contains(local(7, 22, 0)));
+        assert!(window.contains(local(8, 5, 59)));
+        assert!(!window.contains(local(8, 22, 0)));
+
+        let window = TimeWindow {
+            days: vec![],
+            from: NaiveDate::from_ymd_opt(2024, 6, 8),
+            until: NaiveDate::from_ymd_opt(2024, 6, 9),
+            ..window
+        };
+
+        assert!(!window.contains(local(8, 5, 0)));
+        assert!(window.contains(local(8, 23, 0)));
+        assert!(window.contains(local(10, 5, 0)));
+        assert!(!window.contains(local(10, 23, 0)));
+    }
+
+    #[test]
+    pub fn checks_days_and_dates() {
+        let window = TimeWindow {
+            days: vec![Weekday::Sat, Weekday::Sun],
+            from: NaiveDate::from_ymd_opt(2024, 6, 8),
+            until: NaiveDate::from_ymd_opt(2024, 6, 15),
+            ..Default::default()
+        };
+
+        assert!(!window.contains(local(3, 12, 0)));
+        assert!(window.contains(local(8, 0, 0)));
+        assert!(window.contains(local(9, 23, 59)));
+        assert!(!window.contains(local(10, 12, 0)));
+        assert!(window.contains(local(15, 12, 0)));
+        assert!(!window.contains(local(16, 12, 0)));
+    }
+
+    #[test]
+    pub fn evaluates_windows_in_timezone() {
+        let schedule = Schedule {
+            zone: Zone::Named(Berlin),
+            windows: vec![TimeWindow {
+                start: time(8, 0),
+                end: time(9, 0),
+                ..Default::default()
+            }],
+        };
+        // summer time, UTC+2
+        let at = |hour| Utc.from_utc_datetime(&local(3, hour, 30));
+
+        assert!(!schedule.is_open(at(8)));
+        assert!(schedule.is_open(at(6)));
+        assert_eq!(
+            schedule.until_midnight(at(21)),
+            chrono::Duration::minutes(30)
+        );
+        assert!(Schedule::default().is_open(at(8)));
+    }
+
+    #[test]
+    pub fn parses_windows() {
+        let window = serde_yaml::from_str::<TimeWindow>(
+            "days: [mon, Tue, wednesday]\nstart: \"07:30\"\nend: 17:00:00\nfrom: 2024-05-01",
+        )
+        .unwrap();
+
+        assert_eq!(window.days, vec![Weekday::Mon, Weekday::Tue, Weekday::Wed]);
+        assert_eq!(window.start, time(7, 30));
+        assert_eq!(window.end, time(17, 0));
+        assert_eq!(window.from, NaiveDate::from_ymd_opt(2024, 5, 1));
+        assert_eq!(window.until, None);
+    }
+}
@@ -0,0 +1,235 @@
+use chrono::{DateTime, Utc};
+
+use super::{Controller, Load};
+
+/// Switches loads on by priority as long as the surplus covers their nominal power
+pub(crate) struct Greedy {
+    /// surplus kept back from the loads
+    margin_watts: f64,
+}
+
+impl Greedy {
+    pub(crate) fn new(margin_watts: f64) -> Self {
+        Self { margin_watts }
+    }
+}
+
+impl Controller for Greedy {
+    fn control(&mut self, power: i32, _now: DateTime<Utc>, loads: &[Load]) -> Vec<f64> {
+        allocate(power as f64 + self.margin_watts, loads)
+    }
+}
+
+/// Choose the power of the loads on the measured power (negative values are fed into the
+/// grid)
+///
+/// The surplus without the loads which are already running is distributed in order of
+/// priority as long as it covers the nominal consumption of the next load, so the loads
+/// with the lowest priority are the first to be switched off. Returns the power for each
+/// load in the order given.
+pub(crate) fn allocate(power: f64, loads: &[Load]) -> Vec<f64> {
+    let running = loads.iter().map(Load::consumption).sum::<f64>();
+    distribute(running - power, loads)
+}
+
+/// Distribute the given power among the loads in order of priority
+///
+/// Loads forced on take their share first, the nominal power of switched loads and at
+/// least the minimum of adjustable ones. The others are switched on as long as the rest
+/// covers their nominal power. Adjustable loads take what is left up to their nominal
+/// power, as long as it is at least their minimum.
+pub(crate) fn distribute(mut available: f64, loads: &[Load]) -> Vec<f64> {
+    let mut result = loads
+        .iter()
+        .map(|load| load.full(load.forced.unwrap_or(load.on)))
+        .collect::<Vec<_>>();
+
+    let mut forced = (0..loads.len())
+        .filter(|i| loads[*i].forced == Some(true))
+        .collect::<Vec<_>>();
+    // adjustable loads take what the switched ones leave
+    forced.sort_by_key(|i| (loads[*i].adjustable.is_some(), -(loads[*i].priority as i64)));
+    for i in forced {
+        result[i] = loads[i].forced_on(available);
+        available -= result[i];
+    }
+
+    let mut order = (0..loads.len())
+        .filter(|i| loads[*i].forced.is_none())
+        .collect::<Vec<_>>();
+    // stable sort, loads with equal priority keep the order of the configuration
+    order.sort_by_key(|i| -(loads[*i].priority as i64));
+    let mut fits = true;
+    for i in order {
+        let load = &loads[i];
+        let share = match load.adjustable {
+            Some(adjustable) => {
+                let share = available.min(load.nominal_watts as f64);
+                fits = fits && share >= adjustable.min_watts as f64 && share > 0.0;
+                share
+            }
+            None => {
+                let share = load.nominal_watts as f64;
+                fits = fits && share <= available;
+                share
+            }
+        };
+        result[i] = if fits { share } else { 0.0 };
+        if fits {
+            available -= share;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::actors::controller::Adjustable;
+
+    fn load(priority: i32, nominal_watts: u32, on: bool) -> Load {
+        Load {
+            priority,
+            nominal_watts,
+            on,
+            forced: None,
+            adjustable: None,
+        }
+    }
+
+    fn adjustable(priority: i32, min_watts: u32, max_watts: u32, setpoint: f64) -> Load {
+        Load {
+            adjustable: Some(Adjustable {
+                min_watts,
+                setpoint,
+            }),
+            ..load(priority, max_watts, setpoint > 0.0)
+        }
+    }
+
+    /// which loads are on
+    fn switched(power: i32, loads: &[Load]) -> Vec<bool> {
+        allocate(power as f64, loads)
+            .into_iter()
+            .map(|watts| watts > 0.0)
+            .collect()
+    }
+
+    #[test]
+    pub fn switches_on_in_order_of_priority() {
+        let loads = [
+            load(1, 300, false),
+            load(3, 500, false),
+            load(2, 400, false),
+        ];
+
+        assert_eq!(switched(-200, &loads), vec![false, false, false]);
+        assert_eq!(switched(-500, &loads), vec![false, true, false]);
+        assert_eq!(switched(-900, &loads), vec![false, true, true]);
+        assert_eq!(switched(-1200, &loads), vec![true, true, true]);
+    }
+
+    #[test]
+    pub fn lower_priorities_wait_for_higher_ones() {
+        let loads = [load(2, 1000, false), load(1, 200, false)];
+
+        assert_eq!(switched(-600, &loads), vec![false, false]);
+        assert_eq!(switched(-1200, &loads), vec![true, true]);
+    }
+
+    #[test]
+    pub fn accounts_for_running_loads() {
+        let loads = [load(2, 500, true), load(1, 300, false)];
+
+        // the running load is covered, the surplus on top is enough for the second one
+        assert_eq!(switched(-300, &loads), vec![true, true]);
+        assert_eq!(switched(-100, &loads), vec![true, false]);
+        // importing less than the running load draws keeps it running
+        assert_eq!(switched(0, &loads), vec![true, false]);
+    }
+
+    #[test]
+    pub fn sheds_lowest_priority_first() {
+        let loads = [load(1, 300, true), load(2, 500, true)];
+
+        assert_eq!(switched(0, &loads), vec![true, true]);
+        assert_eq!(switched(100, &loads), vec![false, true]);
+        assert_eq!(switched(400, &loads), vec![false, false]);
+    }
+
+    #[test]
+    pub fn keeps_forced_states() {
+        let forced_on = Load {
+            forced: Some(true),
+            ..load(1, 300, true)
+        };
+        let forced_off = Load {
+            forced: Some(false),
+            ..load(3, 100, false)
+        };
+        let loads = [forced_on, forced_off, load(2, 500, true)];
+
+        // the forced load keeps running although it has the lowest priority
+        assert_eq!(switched(100, &loads), vec![true, false, false]);
+        assert_eq!(switched(-300, &loads), vec![true, false, true]);
+
+        // a load forced on which is not running yet takes its share of the surplus
+        let starting = Load {
+            forced: Some(true),
+            ..load(1, 300, false)
+        };
+        assert_eq!(
+            switched(-600, &[starting, load(2, 500, false)]),
+            vec![true, false]
+        );
+    }
+
+    #[test]
+    pub fn adjustable_loads_absorb_surplus() {
+        let loads = [load(2, 500, false), adjustable(1, 200, 2000, 0.0)];
+
+        // the rest is below the minimum of the adjustable load
+        assert_eq!(allocate(-650.0, &loads), vec![500.0, 0.0]);
+        assert_eq!(allocate(-900.0, &loads), vec![500.0, 400.0]);
+        assert_eq!(allocate(-3000.0, &loads), vec![500.0, 2000.0]);
+    }
+
+    #[test]
+    pub fn forced_adjustable_loads_take_the_surplus() {
+        let forced = |setpoint: f64| Load {
+            forced: Some(true),
+            ..adjustable(1, 200, 2000, setpoint)
+        };
+
+        // at least the minimum, not the nominal power from the grid
+        assert_eq!(allocate(-500.0, &[forced(0.0)]), vec![500.0]);
+        assert_eq!(allocate(300.0, &[forced(0.0)]), vec![200.0]);
+        assert_eq!(allocate(-300.0, &[forced(800.0)]), vec![1100.0]);
+        assert_eq!(allocate(-5000.0, &[forced(800.0)]), vec![2000.0]);
+
+        // switched loads forced on come first
+        let switched_load = Load {
+            forced: Some(true),
+            ..load(0, 500, false)
+        };
+        assert_eq!(
+            allocate(-900.0, &[forced(0.0), switched_load]),
+            vec![400.0, 500.0]
+        );
+    }
+
+    #[test]
+    pub fn adjustable_loads_follow_surplus() {
+        let loads = [adjustable(1, 100, 2000, 800.0)];
+
+        // the current setpoint is part of the surplus
+        assert_eq!(allocate(-300.0, &loads), vec![1100.0]);
+        assert_eq!(allocate(500.0, &loads), vec![300.0]);
+        assert_eq!(allocate(750.0, &loads), vec![0.0]);
+        // margin kept back
+        assert_eq!(
+            Greedy::new(50.0).control(-300, Utc::now(), &loads),
+            vec![1050.0]
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use self::{greedy::Greedy, pid::Pid, threshold::Threshold};
+
+mod greedy;
+mod pid;
+mod threshold;
+
+/// A switchable load as seen by a controller
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Load {
+    /// loads with higher priority are switched on first and shed last
+    pub(crate) priority: i32,
+    /// consumption when switched on, the maximum of adjustable loads
+    pub(crate) nominal_watts: u32,
+    pub(crate) on: bool,
+    /// state the load has to be in regardless of the surplus, e.g. because it was
+    /// switched recently
+    pub(crate) forced: Option<bool>,
+    /// `None` for loads which can only be switched
+    pub(crate) adjustable: Option<Adjustable>,
+}
+
+/// A load taking any power between its minimum and nominal power
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Adjustable {
+    pub(crate) min_watts: u32,
+    /// power the load is currently set to
+    pub(crate) setpoint: f64,
+}
+
+impl Load {
+    /// Power drawn at the moment
+    pub(crate) fn consumption(&self) -> f64 {
+        match self.adjustable {
+            Some(adjustable) if self.on => adjustable.setpoint,
+            _ => self.full(self.on),
+        }
+    }
+
+    /// Nominal power if `on`, 0 otherwise
+    pub(crate) fn full(&self, on: bool) -> f64 {
+        if on {
+            self.nominal_watts as f64
+        } else {
+            0.0
+        }
+    }
+
+    /// Power of a load which has to run, adjustable loads take `available` within their
+    /// limits instead of their nominal power
+    pub(crate) fn forced_on(&self, available: f64) -> f64 {
+        match self.adjustable {
+            Some(adjustable) => available
+                .max(adjustable.min_watts as f64)
+                .min(self.nominal_watts as f64),
+            None => self.nominal_watts as f64,
+        }
+    }
+}
+
+/// Strategy deciding which loads of a group run
+pub(crate) trait Controller: Send {
+    /// Desired power of each load in the order given, for a filtered power reading
+    /// (negative values are fed into the grid)
+    ///
+    /// Loads which are only switched get either their nominal power or 0.
+    fn control(&mut self, power: i32, now: DateTime<Utc>, loads: &[Load]) -> Vec<f64>;
+}
+
+/// Controller of a group of actors, the `type` in the configuration
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ControllerConfiguration {
+    /// each load is switched on below `enable_threshold` and off above `disable_threshold`
+    Threshold {
+        enable_threshold: i32,
+        disable_threshold: i32,
+    },
+    /// keeps the power at `target_watts` by adjusting the consumption of the loads
+    Pid {
+        #[serde(default)]
+        target_watts: f64,
+        kp: f64,
+        #[serde(default)]
+        ki: f64,
+        #[serde(default)]
+        kd: f64,
+    },
+    /// switches loads on by priority as long as the surplus covers their nominal power,
+    /// adjustable loads absorb the surplus minus `margin_watts`
+    Greedy {
+        #[serde(default)]
+        margin_watts: f64,
+    },
+}
+
+impl Default for ControllerConfiguration {
+    fn default() -> Self {
+        ControllerConfiguration::Greedy { margin_watts: 0.0 }
+    }
+}
+
+pub(crate) fn create_controller(config: &ControllerConfiguration) -> Box<dyn Controller> {
+    match *config {
+        ControllerConfiguration::Threshold {
+            enable_threshold,
+            disable_threshold,
+        } => Box::new(Threshold::new(enable_threshold, disable_threshold)),
+        ControllerConfiguration::Pid {
+            target_watts,
+            kp,
+            ki,
+            kd,
+        } => Box::new(Pid::new(target_watts, kp, ki, kd)),
+        ControllerConfiguration::Greedy { margin_watts } => Box::new(Greedy::new(margin_watts)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn parses_controllers() {
+        let config = serde_yaml::from_str::<Vec<ControllerConfiguration>>(
+            "- type: threshold\n  enable_threshold: -100\n  disable_threshold: 100\n\
+             - type: pid\n  kp: 0.5\n  ki: 0.1\n\
+             - type: greedy\n\
+             - type: greedy\n  margin_watts: 50",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            config[0],
+            ControllerConfiguration::Threshold {
+                enable_threshold: -100,
+                disable_threshold: 100
+            }
+        ));
+        assert!(matches!(
+            config[1],
+            ControllerConfiguration::Pid { kd, target_watts, .. } if kd == 0.0 && target_watts == 0.0
+        ));
+        assert!(matches!(
+            config[2],
+            ControllerConfiguration::Greedy { margin_watts } if margin_watts == 0.0
+        ));
+        assert!(matches!(
+            config[3],
+            ControllerConfiguration::Greedy { margin_watts } if margin_watts == 50.0
+        ));
+    }
+}
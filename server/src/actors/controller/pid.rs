@@ -0,0 +1,124 @@
+use chrono::{DateTime, Utc};
+
+use super::{greedy::distribute, Controller, Load};
+
+/// Keeps the power at a target by adjusting the consumption of the loads
+///
+/// The output of the PID controller is the power the loads of the group may draw, which
+/// is distributed among them in order of priority. The integral is limited to the
+/// capacity of the loads, so it does not wind up while they cannot follow.
+pub(crate) struct Pid {
+    target_watts: f64,
+    kp: f64,
+    ki: f64,
+    kd: f64,
+    integral: f64,
+    last: Option<(DateTime<Utc>, f64)>,
+}
+
+impl Pid {
+    pub(crate) fn new(target_watts: f64, kp: f64, ki: f64, kd: f64) -> Self {
+        Self {
+            target_watts,
+            kp,
+            ki,
+            kd,
+            integral: 0.0,
+            last: None,
+        }
+    }
+
+    /// Power the loads may draw, between 0 and `capacity`
+    pub(crate) fn output(&mut self, power: f64, now: DateTime<Utc>, capacity: f64) -> f64 {
+        // positive if more power can be consumed
+        let error = self.target_watts - power;
+        let (seconds, derivative) = match self.last {
+            Some((time, last_error)) => {
+                let seconds = (now - time).num_milliseconds() as f64 / 1000.0;
+                if seconds > 0.0 {
+                    (seconds, (error - last_error) / seconds)
+                } else {
+                    (0.0, 0.0)
+                }
+            }
+            None => (0.0, 0.0),
+        };
+        self.last = Some((now, error));
+
+        if self.ki > 0.0 {
+            self.integral = (self.integral + error * seconds).clamp(0.0, capacity / self.ki);
+        }
+        (self.kp * error + self.ki * self.integral + self.kd * derivative).clamp(0.0, capacity)
+    }
+}
+
+impl Controller for Pid {
+    fn control(&mut self, power: i32, now: DateTime<Utc>, loads: &[Load]) -> Vec<f64> {
+        let capacity = loads
+            .iter()
+            .filter(|load| load.forced != Some(false))
+            .map(|load| load.nominal_watts as f64)
+            .sum::<f64>();
+        let budget = self.output(power as f64, now, capacity);
+        distribute(budget, loads)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::actors::testing::at_second;
+
+    use super::*;
+
+    #[test]
+    pub fn proportional_output() {
+        let mut pid = Pid::new(0.0, 0.5, 0.0, 0.0);
+
+        assert_eq!(pid.output(-400.0, at_second(0), 1000.0), 200.0);
+        assert_eq!(pid.output(400.0, at_second(1), 1000.0), 0.0);
+        assert_eq!(pid.output(-4000.0, at_second(2), 1000.0), 1000.0);
+    }
+
+    #[test]
+    pub fn settles_on_target() {
+        let mut pid = Pid::new(-50.0, 0.2, 0.1, 0.0);
+        // house draws 200 W, the panels produce 1000 W
+        let base = 200.0 - 1000.0;
+        let mut consumption = 0.0;
+        for second in 0..300 {
+            consumption = pid.output(base + consumption, at_second(second), 2000.0);
+        }
+
+        assert!((base + consumption + 50.0).abs() < 1.0);
+        assert!((consumption - 750.0).abs() < 1.0);
+    }
+
+    #[test]
+    pub fn limits_integral() {
+        let mut pid = Pid::new(0.0, 0.0, 1.0, 0.0);
+        for second in 0..100 {
+            pid.output(-1000.0, at_second(second), 500.0);
+        }
+
+        // the output drops as soon as power is drawn from the grid
+        assert_eq!(pid.output(-1000.0, at_second(100), 500.0), 500.0);
+        assert!(pid.output(100.0, at_second(101), 500.0) < 500.0);
+    }
+
+    #[test]
+    pub fn distributes_output_to_loads() {
+        let mut pid = Pid::new(0.0, 1.0, 0.0, 0.0);
+        let load = |priority, nominal_watts| Load {
+            priority,
+            nominal_watts,
+            on: false,
+            forced: None,
+            adjustable: None,
+        };
+
+        assert_eq!(
+            pid.control(-700, at_second(0), &[load(1, 300), load(2, 500)]),
+            vec![0.0, 500.0]
+        );
+    }
+}
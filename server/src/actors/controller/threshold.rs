@@ -0,0 +1,96 @@
+use chrono::{DateTime, Utc};
+
+use super::{Controller, Load};
+
+/// Hysteresis applied to every load on its own
+///
+/// A load is switched on when the power drops below `enable_threshold` and stays on as
+/// long as it does not exceed `disable_threshold`.
+pub(crate) struct Threshold {
+    enable_threshold: i32,
+    disable_threshold: i32,
+}
+
+impl Threshold {
+    pub(crate) fn new(enable_threshold: i32, disable_threshold: i32) -> Self {
+        Self {
+            enable_threshold,
+            disable_threshold,
+        }
+    }
+}
+
+impl Controller for Threshold {
+    fn control(&mut self, power: i32, _now: DateTime<Utc>, loads: &[Load]) -> Vec<f64> {
+        loads
+            .iter()
+            .map(|load| match load.forced {
+                // adjustable loads take the surplus
+                Some(true) => load.forced_on(load.consumption() - power as f64),
+                forced => load.full(forced.unwrap_or(if load.on {
+                    power <= self.disable_threshold
+                } else {
+                    power < self.enable_threshold
+                })),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::actors::controller::Adjustable;
+
+    fn load(on: bool) -> Load {
+        Load {
+            priority: 0,
+            nominal_watts: 100,
+            on,
+            forced: None,
+            adjustable: None,
+        }
+    }
+
+    #[test]
+    pub fn switches_with_hysteresis() {
+        let mut controller = Threshold::new(-100, 100);
+        let now = Utc::now();
+
+        assert_eq!(controller.control(-100, now, &[load(false)]), vec![0.0]);
+        assert_eq!(controller.control(-101, now, &[load(false)]), vec![100.0]);
+        assert_eq!(controller.control(100, now, &[load(true)]), vec![100.0]);
+        assert_eq!(controller.control(101, now, &[load(true)]), vec![0.0]);
+    }
+
+    #[test]
+    pub fn keeps_forced_states() {
+        let mut controller = Threshold::new(-100, 100);
+        let forced = Load {
+            forced: Some(true),
+            ..load(true)
+        };
+
+        assert_eq!(
+            controller.control(500, Utc::now(), &[forced, load(true)]),
+            vec![100.0, 0.0]
+        );
+    }
+
+    #[test]
+    pub fn limits_forced_adjustable_loads_to_the_surplus() {
+        let mut controller = Threshold::new(-100, 100);
+        let forced = Load {
+            nominal_watts: 2000,
+            forced: Some(true),
+            adjustable: Some(Adjustable {
+                min_watts: 300,
+                setpoint: 500.0,
+            }),
+            ..load(true)
+        };
+
+        assert_eq!(controller.control(-200, Utc::now(), &[forced]), vec![700.0]);
+        assert_eq!(controller.control(800, Utc::now(), &[forced]), vec![300.0]);
+    }
+}
@@ -0,0 +1,207 @@
+use chrono::{DateTime, Datelike, Duration, NaiveTime, Utc, Weekday};
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use super::calendar::{time_of_day, Schedule};
+
+/// Runtime an actor needs before a deadline, preferably on surplus
+///
+/// Runtime is counted from midnight, the actor is switched on from the grid late enough to
+/// reach it before the deadline.
+#[derive(Deserialize, Clone, Debug)]
+pub(crate) struct Job {
+    pub(crate) runtime_minutes: usize,
+    /// e.g. `"18:00"`
+    #[serde(deserialize_with = "deadline")]
+    pub(crate) deadline: NaiveTime,
+    /// days the job is due on, all days if empty
+    #[serde(default)]
+    pub(crate) days: Vec<Weekday>,
+}
+
+fn deadline<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+    time_of_day(deserializer)?.ok_or_else(|| de::Error::custom("deadline expected"))
+}
+
+/// Progress of a job on the current day
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct JobProgress {
+    pub(crate) deadline: NaiveTime,
+    /// whether the job is due today and the deadline has not passed
+    pub(crate) active: bool,
+    pub(crate) runtime_minutes: i64,
+    pub(crate) required_minutes: i64,
+    pub(crate) done: bool,
+    /// time the actor is switched on from the grid at the latest
+    pub(crate) latest_start: Option<NaiveTime>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct JobState {
+    job: Job,
+    runtime: Duration,
+}
+
+impl JobState {
+    pub(crate) fn new(job: Job) -> Self {
+        Self {
+            job,
+            runtime: Duration::zero(),
+        }
+    }
+
+    /// Add runtime, `new_day` starts counting again
+    pub(crate) fn account(
+        &mut self,
+        schedule: &Schedule,
+        now: DateTime<Utc>,
+        runtime: Duration,
+        new_day: bool,
+    ) {
+        if new_day {
+            self.runtime = Duration::zero();
+        } else if self
+            .deadline(schedule, now)
+            .is_some_and(|deadline| now <= deadline)
+        {
+            self.runtime = self.runtime + runtime;
+        }
+    }
+
+    /// Whether the actor has to run from now on to meet the deadline
+    pub(crate) fn is_urgent(&self, schedule: &Schedule, now: DateTime<Utc>) -> bool {
+        let missing = self.missing();
+        match self.deadline(schedule, now) {
+            Some(deadline) => {
+                missing > Duration::zero() && now < deadline && missing >= deadline - now
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn progress(&self, schedule: &Schedule, now: DateTime<Utc>) -> JobProgress {
+        let deadline = self.deadline(schedule, now);
+        let active = deadline.is_some_and(|deadline| now < deadline);
+        let done = self.missing() <= Duration::zero();
+        JobProgress {
+            deadline: self.job.deadline,
+            active,
+            runtime_minutes: self.runtime.num_minutes(),
+            required_minutes: self.job.runtime_minutes as i64,
+            done,
+            latest_start: match (active && !done, deadline) {
+                (true, Some(deadline)) => {
+                    Some(schedule.zone.local_time(deadline - self.missing()).time())
+                }
+                _ => None,
+            },
+        }
+    }
+
+    fn missing(&self) -> Duration {
+        Duration::minutes(self.job.runtime_minutes as i64) - self.runtime
+    }
+
+    /// Deadline on the current day, `None` if the job is not due today
+    fn deadline(&self, schedule: &Schedule, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let today = schedule.day(now);
+        if !self.job.days.is_empty() && !self.job.days.contains(&today.weekday()) {
+            return None;
+        }
+        schedule.zone.to_utc(&today.and_time(self.job.deadline))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono_tz::Europe::Berlin;
+
+    use crate::actors::{calendar::Zone, testing::at_minute};
+
+    use super::*;
+
+    fn schedule() -> Schedule {
+        Schedule {
+            zone: Zone::Named(Berlin),
+            windows: vec![],
+        }
+    }
+
+    fn job(days: Vec<Weekday>) -> JobState {
+        JobState::new(Job {
+            runtime_minutes: 120,
+            deadline: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+            days,
+        })
+    }
+
+    #[test]
+    pub fn becomes_urgent_before_deadline() {
+        let schedule = schedule();
+        let mut job = job(vec![]);
+
+        assert!(!job.is_urgent(&schedule, at_minute(15 * 60 + 59)));
+        assert!(job.is_urgent(&schedule, at_minute(16 * 60)));
+        assert_eq!(
+            job.progress(&schedule, at_minute(10 * 60)).latest_start,
+            NaiveTime::from_hms_opt(16, 0, 0)
+        );
+
+        // runtime on surplus postpones the latest start
+        job.account(&schedule, at_minute(12 * 60), Duration::minutes(45), false);
+        assert!(!job.is_urgent(&schedule, at_minute(16 * 60)));
+        assert!(job.is_urgent(&schedule, at_minute(16 * 60 + 45)));
+        assert_eq!(
+            job.progress(&schedule, at_minute(12 * 60)).latest_start,
+            NaiveTime::from_hms_opt(16, 45, 0)
+        );
+    }
+
+    #[test]
+    pub fn tracks_progress() {
+        let schedule = schedule();
+        let mut job = job(vec![]);
+        job.account(&schedule, at_minute(12 * 60), Duration::minutes(100), false);
+        job.account(&schedule, at_minute(17 * 60), Duration::minutes(20), false);
+
+        let progress = job.progress(&schedule, at_minute(17 * 60));
+        assert!(progress.done);
+        assert!(progress.active);
+        assert_eq!(progress.runtime_minutes, 120);
+        assert_eq!(progress.latest_start, None);
+        assert!(!job.is_urgent(&schedule, at_minute(17 * 60)));
+
+        // runtime after the deadline does not count
+        job.account(&schedule, at_minute(19 * 60), Duration::minutes(20), false);
+        assert_eq!(
+            job.progress(&schedule, at_minute(19 * 60)).runtime_minutes,
+            120
+        );
+        assert!(!job.progress(&schedule, at_minute(19 * 60)).active);
+
+        job.account(&schedule, at_minute(24 * 60), Duration::zero(), true);
+        assert_eq!(
+            job.progress(&schedule, at_minute(24 * 60)).runtime_minutes,
+            0
+        );
+    }
+
+    #[test]
+    pub fn is_due_on_given_days() {
+        let schedule = schedule();
+        let job = job(vec![Weekday::Tue]);
+
+        assert!(!job.is_urgent(&schedule, at_minute(17 * 60)));
+        assert!(!job.progress(&schedule, at_minute(17 * 60)).active);
+        assert!(job.is_urgent(&schedule, at_minute(24 * 60 + 17 * 60)));
+    }
+
+    #[test]
+    pub fn parses_jobs() {
+        let job =
+            serde_yaml::from_str::<Job>("runtime_minutes: 90\ndeadline: \"17:30\"\ndays: [sat]")
+                .unwrap();
+
+        assert_eq!(job.deadline, NaiveTime::from_hms_opt(17, 30, 0).unwrap());
+        assert!(serde_yaml::from_str::<Job>("runtime_minutes: 90").is_err());
+    }
+}
@@ -1,80 +1,364 @@
-use chrono::{DateTime, Duration, Utc};
-use tokio::sync::mpsc::Receiver;
-use tokio::task::spawn_blocking;
-use tplinker::capabilities::Switch;
-use tplinker::devices::HS100;
+use std::{collections::BTreeMap, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use tokio::sync::{mpsc::Receiver, Mutex};
 
 use crate::Configuration;
 
-struct ActorState {
+use self::{
+    backend::{create_backend, ActorBackend},
+    calendar::Schedule,
+    controller::{create_controller, Adjustable, Controller, Load},
+    jobs::JobState,
+    setpoint::Setpoint,
+    state::{ActorState, Timing},
+};
+
+pub(crate) use self::state::Quota;
+
+pub(crate) mod backend;
+pub(crate) mod calendar;
+pub(crate) mod controller;
+pub(crate) mod jobs;
+mod setpoint;
+mod state;
+#[cfg(test)]
+pub(crate) mod testing;
+
+struct Actor {
     address: String,
-    disable_threshold: isize,
-    enable_threshold: isize,
-    duration_minutes: usize,
-    last_set: Option<DateTime<Utc>>,
+    backend: Box<dyn ActorBackend>,
+    priority: i32,
+    nominal_watts: u32,
+    state: ActorState,
+    /// `None` for actors which are only switched
+    setpoint: Option<Setpoint>,
+    /// whether an adjustable actor is switched on and off besides its setpoint
+    switched: bool,
+}
+
+/// Runtime and limits of the actors in the order of the configuration
+pub(crate) type ActorQuotas = Arc<Mutex<Vec<Quota>>>;
+
+/// Actors sharing a controller
+struct Group {
+    controller: Box<dyn Controller>,
+    /// indices of the actors
+    members: Vec<usize>,
 }
 
-pub(crate) async fn control_actors(rx: &mut Receiver<i32>, config: &Configuration) {
-    let mut devs = config
+/// Desired power of each load
+///
+/// The groups decide one after the other, the group of the load with the highest priority
+/// first. Each group sees the power reading including the changes planned by the groups
+/// before it, so a surplus is not shared out twice.
+fn plan(groups: &mut [Group], loads: &[Load], received: i32, now: DateTime<Utc>) -> Vec<f64> {
+    let mut desired = vec![0.0; loads.len()];
+    let mut power = received as f64;
+    for group in groups.iter_mut() {
+        let members = group.members.iter().map(|i| loads[*i]).collect::<Vec<_>>();
+        let watts = group
+            .controller
+            .control(power.round() as i32, now, &members);
+        for (i, watts) in group.members.iter().zip(watts) {
+            power += watts - loads[*i].consumption();
+            desired[*i] = watts;
+        }
+    }
+    desired
+}
+
+/// Let the controller of each group decide about its actors on every power reading
+pub(crate) async fn control_actors(
+    rx: &mut Receiver<i32>,
+    config: &Configuration,
+    quotas: ActorQuotas,
+) {
+    let mut actors = config
         .actors
         .iter()
-        .map(|actor| ActorState {
+        .map(|actor| Actor {
             address: actor.address.clone(),
-            disable_threshold: actor.disable_threshold,
-            enable_threshold: actor.enable_threshold,
-            duration_minutes: actor.duration_minutes,
-            last_set: None,
+            backend: create_backend(actor),
+            priority: actor.priority,
+            nominal_watts: actor.load_watts(),
+            state: ActorState::new(
+                Timing::from(actor),
+                Schedule {
+                    zone: config.timezone.into(),
+                    windows: actor.windows.clone(),
+                },
+                actor.jobs.iter().cloned().map(JobState::new).collect(),
+            ),
+            setpoint: actor.is_adjustable().then(|| Setpoint::from(actor)),
+            switched: actor.is_switched(),
         })
         .collect::<Vec<_>>();
 
-    if devs.len() == 0 {
+    if actors.is_empty() {
         return;
     }
 
-    let mut on = false;
+    let mut members = BTreeMap::<&str, Vec<usize>>::new();
+    for (i, actor) in config.actors.iter().enumerate() {
+        members.entry(actor.group()).or_default().push(i);
+    }
+    let mut groups = members
+        .into_iter()
+        .map(|(name, members)| Group {
+            controller: create_controller(&config.groups.get(name).cloned().unwrap_or_default()),
+            members,
+        })
+        .collect::<Vec<_>>();
+    // stable sort, groups with equal priority keep the order of their names
+    groups.sort_by_key(|group| {
+        let priority = group.members.iter().map(|i| actors[*i].priority).max();
+        -(priority.unwrap_or_default() as i64)
+    });
 
     while let Some(received) = rx.recv().await {
-        let random_number = rand::random::<usize>() % devs.len();
-        let dev = devs.get_mut(random_number).unwrap();
-        let ActorState {
-            address,
-            disable_threshold,
-            enable_threshold,
-            duration_minutes,
-            last_set,
-        } = dev;
-
-        let should_be_on = if !on {
-            received < *enable_threshold as i32
-        } else {
-            !(received > *disable_threshold as i32)
-        };
-        if should_be_on != on {
-            let now = chrono::Utc::now();
-            if let Some(last_set_inner) = last_set {
-                let diff = now - *last_set_inner;
-                if diff > Duration::minutes(*duration_minutes as i64) {
-                    on = should_be_on;
-                    *last_set = Some(now.clone());
-                }
-            } else {
-                on = should_be_on;
-                *last_set = Some(chrono::Utc::now());
-            }
+        let now = chrono::Utc::now();
+        for actor in actors.iter_mut() {
+            actor.state.account(now);
+        }
+        let loads = actors
+            .iter()
+            .map(|actor| Load {
+                priority: actor.priority,
+                nominal_watts: actor.nominal_watts,
+                on: actor.state.on,
+                forced: actor.state.forced(now),
+                adjustable: actor.setpoint.as_ref().map(|setpoint| Adjustable {
+                    min_watts: setpoint.min_watts as u32,
+                    setpoint: setpoint.current,
+                }),
+            })
+            .collect::<Vec<_>>();
+        let desired = plan(&mut groups, &loads, received, now);
+
+        join_all(
+            actors
+                .iter_mut()
+                .zip(desired)
+                .map(|(actor, watts)| async move {
+                    let command = actor.state.request(watts > 0.0, now);
+                    match &mut actor.setpoint {
+                        Some(setpoint) => {
+                            // constraints may keep the actor on without a surplus
+                            let target = if actor.state.on {
+                                watts.max(setpoint.min_watts)
+                            } else {
+                                0.0
+                            };
+                            setpoint.update(target, now);
+                            let mut result = Ok(());
+                            // enable before the setpoint rises, disable after it dropped to 0
+                            if command == Some(true) && actor.switched {
+                                result = actor.backend.switch(true).await;
+                            }
+                            if result.is_ok() && (command.is_some() || setpoint.needs_update()) {
+                                result = actor.backend.set_power(setpoint.device_watts()).await;
+                                setpoint.confirm(result.is_ok());
+                            }
+                            if result.is_ok() && command == Some(false) && actor.switched {
+                                result = actor.backend.switch(false).await;
+                            }
+                            if let Err(e) = &result {
+                                eprintln!("could not control {}: {}", actor.address, e);
+                            }
+                            if command.is_some() {
+                                actor.state.confirm(result.is_ok());
+                            }
+                        }
+                        None => {
+                            if let Some(on) = command {
+                                let result = actor.backend.switch(on).await;
+                                if let Err(e) = &result {
+                                    eprintln!("could not switch {}: {}", actor.address, e);
+                                }
+                                actor.state.confirm(result.is_ok());
+                            }
+                        }
+                    }
+                }),
+        )
+        .await;
+
+        *quotas.lock().await = actors
+            .iter()
+            .map(|actor| Quota {
+                setpoint_watts: actor.setpoint.as_ref().map(Setpoint::device_watts),
+                ..actor.state.quota(now)
+            })
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::sync::mpsc;
+
+    use crate::{
+        actors::{controller::ControllerConfiguration, testing::at_second},
+        modbus::simulator::{simulator, Device},
+        ActorConfiguration,
+    };
+
+    use super::*;
+
+    fn load(priority: i32) -> Load {
+        Load {
+            priority,
+            nominal_watts: 1000,
+            on: false,
+            forced: None,
+            adjustable: None,
         }
-        let address = address.clone();
-
-        spawn_blocking(move || {
-            let dev = HS100::new(&address);
-            if let Ok(dev) = dev {
-                if on {
-                    let _ = dev.switch_on();
-                } else {
-                    let _ = dev.switch_off();
+    }
+
+    fn group(members: Vec<usize>) -> Group {
+        Group {
+            controller: create_controller(&ControllerConfiguration::default()),
+            members,
+        }
+    }
+
+    #[test]
+    pub fn groups_do_not_share_the_surplus_twice() {
+        let mut groups = vec![group(vec![0]), group(vec![1])];
+        let loads = [load(0), load(0)];
+
+        assert_eq!(
+            plan(&mut groups, &loads, -1500, at_second(0)),
+            [1000.0, 0.0]
+        );
+        assert_eq!(
+            plan(&mut groups, &loads, -2500, at_second(1)),
+            [1000.0, 1000.0]
+        );
+
+        // a running load of the first group keeps its share
+        let loads = [
+            Load {
+                on: true,
+                ..load(0)
+            },
+            load(0),
+        ];
+        assert_eq!(plan(&mut groups, &loads, -500, at_second(2)), [1000.0, 0.0]);
+        assert_eq!(
+            plan(&mut groups, &loads, -1000, at_second(3)),
+            [1000.0, 1000.0]
+        );
+    }
+
+    #[test]
+    pub fn inverters_curtail_the_surplus() {
+        let actor = serde_yaml::from_str::<ActorConfiguration>(
+            "type: open_dtu\naddress: dtu\nserial: \"1\"\nnominal_watts: 800\nmin_watts: 100",
+        )
+        .unwrap();
+        let mut setpoint = Setpoint::from(&actor);
+        let mut groups = vec![group(vec![0])];
+        let mut on = false;
+        // the inverter produces up to its limit, the house draws a constant power
+        let mut run =
+            |setpoint: &mut Setpoint, sun: f64, house: f64, seconds: std::ops::Range<i64>| {
+                for second in seconds {
+                    let grid = house - setpoint.device_watts().min(sun);
+                    let load = Load {
+                        on,
+                        adjustable: Some(Adjustable {
+                            min_watts: setpoint.min_watts as u32,
+                            setpoint: setpoint.current,
+                        }),
+                        nominal_watts: actor.load_watts(),
+                        ..load(0)
+                    };
+                    let watts = plan(&mut groups, &[load], grid as i32, at_second(second))[0];
+                    on = watts > 0.0;
+                    setpoint.update(if on { watts } else { 0.0 }, at_second(second));
                 }
+                house - setpoint.device_watts().min(sun)
+            };
+
+        assert_eq!(setpoint.device_watts(), 800.0);
+        // export is curtailed
+        assert_eq!(run(&mut setpoint, 800.0, 300.0, 0..5), 0.0);
+        assert_eq!(setpoint.device_watts(), 300.0);
+        // down to the minimum limit
+        assert_eq!(run(&mut setpoint, 800.0, 0.0, 5..10), -100.0);
+        assert_eq!(setpoint.device_watts(), 100.0);
+        // the limit is raised again when importing
+        assert_eq!(run(&mut setpoint, 200.0, 300.0, 10..20), 100.0);
+        assert_eq!(setpoint.device_watts(), 800.0);
+    }
+
+    #[test]
+    pub fn parses_adjustable_actors() {
+        let parse = |actor_type: &str| {
+            serde_yaml::from_str::<ActorConfiguration>(&format!(
+                "type: {}\naddress: dtu\nnominal_watts: 800\nmin_watts: 100",
+                actor_type
+            ))
+            .unwrap()
+        };
+
+        assert!(parse("my_pv").is_adjustable());
+        assert_eq!(parse("my_pv").load_watts(), 800);
+        assert!(!parse("kasa").is_adjustable());
+        for inverter in ["open_dtu", "ahoy_dtu"] {
+            assert!(parse(inverter).is_adjustable());
+            assert_eq!(parse(inverter).load_watts(), 700);
+        }
+    }
+
+    /// Waits until the device reaches the expected state
+    async fn wait_for(device: &std::sync::Mutex<Device>, expected: impl Fn(&Device) -> bool) {
+        for _ in 0..100 {
+            if expected(&device.lock().unwrap()) {
+                return;
             }
-        })
-        .await
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("unexpected state {:?}", device.lock().unwrap());
+    }
+
+    #[tokio::test]
+    pub async fn switches_adjustable_modbus_actors() {
+        let (address, device) = simulator(1).await;
+        let config = serde_yaml::from_str::<Configuration>(&format!(
+            "log_location: /tmp\n\
+             actors:\n\
+             - type: modbus\n  \
+               address: {}\n  \
+               nominal_watts: 3000\n  \
+               modbus:\n    \
+                 switch: {{type: coil, address: 0}}\n    \
+                 setpoint: {{address: 10}}",
+            address
+        ))
         .unwrap();
+        let (tx, mut rx) = mpsc::channel(1);
+        let quotas = ActorQuotas::default();
+        let control = tokio::spawn(async move { control_actors(&mut rx, &config, quotas).await });
+
+        tx.send(-2000).await.unwrap();
+        wait_for(&device, |device| {
+            device.coils.get(&0) == Some(&true) && device.holding.get(&10) == Some(&2000)
+        })
+        .await;
+
+        tx.send(5000).await.unwrap();
+        wait_for(&device, |device| {
+            device.coils.get(&0) == Some(&false) && device.holding.get(&10) == Some(&0)
+        })
+        .await;
+
+        drop(tx);
+        control.await.unwrap();
     }
 }
@@ -0,0 +1,170 @@
+use chrono::{DateTime, Utc};
+
+use crate::ActorConfiguration;
+
+/// Power setpoint of an adjustable actor
+///
+/// The setpoint follows the target of the controller between the minimum and maximum power,
+/// limited to a rate of change. Switching off takes effect at once, switching on starts at
+/// the minimum.
+///
+/// Inverters are treated as a load taking up the surplus: the setpoint is the power they
+/// are curtailed by, i.e. their nominal power minus the power limit sent to them.
+#[derive(Clone, Debug)]
+pub(crate) struct Setpoint {
+    pub(crate) min_watts: f64,
+    max_watts: f64,
+    /// watts per second, unlimited if `None`
+    max_change: Option<f64>,
+    pub(crate) current: f64,
+    last_update: Option<DateTime<Utc>>,
+    /// setpoint confirmed by the device, `None` if unknown
+    sent: Option<f64>,
+    /// nominal power of inverters
+    inverter_watts: Option<f64>,
+}
+
+impl From<&ActorConfiguration> for Setpoint {
+    fn from(config: &ActorConfiguration) -> Self {
+        let max_watts = config.load_watts() as f64;
+        let inverter = config.actor_type.is_inverter();
+        Self {
+            // curtailing inverters starts at 0
+            min_watts: if inverter {
+                0.0
+            } else {
+                (config.min_watts.unwrap_or(0) as f64).min(max_watts)
+            },
+            max_watts,
+            max_change: config.max_change_watts_per_second,
+            current: 0.0,
+            last_update: None,
+            sent: None,
+            inverter_watts: inverter.then_some(config.nominal_watts as f64),
+        }
+    }
+}
+
+impl Setpoint {
+    /// Move towards the target, 0 switches the actor off
+    pub(crate) fn update(&mut self, target: f64, now: DateTime<Utc>) -> f64 {
+        let seconds = self
+            .last_update
+            .map(|last| (now - last).num_milliseconds().max(0) as f64 / 1000.0);
+        self.last_update = Some(now);
+        if target <= 0.0 {
+            self.current = 0.0;
+            return self.current;
+        }
+        let target = target.clamp(self.min_watts, self.max_watts);
+        let start = self.current.max(self.min_watts);
+        self.current = match (self.max_change, seconds) {
+            (Some(rate), Some(seconds)) => {
+                let step = rate * seconds;
+                target.max(start - step).min(start + step)
+            }
+            (Some(_), None) => start,
+            (None, _) => target,
+        }
+        .clamp(self.min_watts, self.max_watts);
+        self.current
+    }
+
+    /// Power to send to the device, the power limit of inverters
+    pub(crate) fn device_watts(&self) -> f64 {
+        match self.inverter_watts {
+            Some(nominal) => nominal - self.current,
+            None => self.current,
+        }
+    }
+
+    /// Whether the device has to be sent the current setpoint
+    pub(crate) fn needs_update(&self) -> bool {
+        self.sent
+            .is_none_or(|sent| (sent - self.current).abs() >= 1.0)
+    }
+
+    /// Record the outcome of sending the current setpoint
+    pub(crate) fn confirm(&mut self, success: bool) {
+        self.sent = success.then_some(self.current);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::actors::testing::at_second;
+
+    use super::*;
+
+    fn setpoint(max_change: Option<f64>) -> Setpoint {
+        serde_yaml::from_str::<ActorConfiguration>(
+            "type: my_pv\naddress: elwa\nnominal_watts: 3000\nmin_watts: 200",
+        )
+        .map(|config| Setpoint {
+            max_change,
+            ..Setpoint::from(&config)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    pub fn follows_target_within_limits() {
+        let mut setpoint = setpoint(None);
+
+        assert_eq!(setpoint.update(1500.0, at_second(0)), 1500.0);
+        assert_eq!(setpoint.update(5000.0, at_second(1)), 3000.0);
+        assert_eq!(setpoint.update(50.0, at_second(2)), 200.0);
+        assert_eq!(setpoint.update(0.0, at_second(3)), 0.0);
+    }
+
+    #[test]
+    pub fn limits_rate_of_change() {
+        let mut setpoint = setpoint(Some(100.0));
+
+        // starts at the minimum
+        assert_eq!(setpoint.update(2000.0, at_second(0)), 200.0);
+        assert_eq!(setpoint.update(2000.0, at_second(2)), 400.0);
+        assert_eq!(setpoint.update(2000.0, at_second(12)), 1400.0);
+        assert_eq!(setpoint.update(1000.0, at_second(13)), 1300.0);
+        // switching off is not delayed
+        assert_eq!(setpoint.update(0.0, at_second(14)), 0.0);
+        assert_eq!(setpoint.update(2000.0, at_second(15)), 300.0);
+    }
+
+    #[test]
+    pub fn sends_changes_only() {
+        let mut setpoint = setpoint(None);
+        setpoint.update(1000.0, at_second(0));
+        assert!(setpoint.needs_update());
+
+        setpoint.confirm(true);
+        setpoint.update(1000.4, at_second(1));
+        assert!(!setpoint.needs_update());
+        setpoint.update(1010.0, at_second(2));
+        assert!(setpoint.needs_update());
+
+        setpoint.confirm(false);
+        setpoint.update(1010.0, at_second(3));
+        assert!(setpoint.needs_update());
+    }
+
+    #[test]
+    pub fn rejects_invalid_rates() {
+        let parse = |rate: &str| {
+            serde_yaml::from_str::<ActorConfiguration>(&format!(
+                "type: my_pv\naddress: elwa\nnominal_watts: 3000\nmax_change_watts_per_second: {}",
+                rate
+            ))
+        };
+
+        assert!(parse("0").is_ok());
+        assert!(parse("-100").is_err());
+        assert!(parse(".nan").is_err());
+        assert!(parse(".inf").is_err());
+
+        // a rate which got past the configuration does not panic
+        let mut setpoint = setpoint(Some(-100.0));
+        setpoint.update(2000.0, at_second(0));
+        assert_eq!(setpoint.update(2000.0, at_second(1)), 200.0);
+    }
+}
@@ -0,0 +1,554 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::Serialize;
+
+use crate::ActorConfiguration;
+
+use super::{
+    calendar::Schedule,
+    jobs::{JobProgress, JobState},
+};
+
+/// Timing constraints of an actor
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Timing {
+    pub(crate) min_on: Duration,
+    pub(crate) min_off: Duration,
+    /// longest time the actor may stay on without interruption
+    pub(crate) max_runtime: Option<Duration>,
+    pub(crate) max_daily: Option<Duration>,
+    /// the actor is switched on in time before midnight to reach this runtime
+    pub(crate) min_daily: Option<Duration>,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self {
+            min_on: Duration::zero(),
+            min_off: Duration::zero(),
+            max_runtime: None,
+            max_daily: None,
+            min_daily: None,
+        }
+    }
+}
+
+impl From<&ActorConfiguration> for Timing {
+    fn from(config: &ActorConfiguration) -> Self {
+        let minutes = |minutes: usize| Duration::minutes(minutes as i64);
+        Self {
+            min_on: minutes(config.min_on_minutes.unwrap_or(config.duration_minutes)),
+            min_off: minutes(config.min_off_minutes.unwrap_or(config.duration_minutes)),
+            max_runtime: config.max_runtime_minutes.map(minutes),
+            max_daily: config.max_daily_minutes.map(minutes),
+            min_daily: config.min_daily_minutes.map(minutes),
+        }
+    }
+}
+
+/// Switching state of a single actor
+#[derive(Clone, Debug)]
+pub(crate) struct ActorState {
+    timing: Timing,
+    schedule: Schedule,
+    jobs: Vec<JobState>,
+    /// state the actor should be in
+    pub(crate) on: bool,
+    /// time of the last change of `on`
+    pub(crate) since: Option<DateTime<Utc>>,
+    pub(crate) last_command: Option<Command>,
+    /// state reported by the device after the last command, `None` if unknown
+    pub(crate) last_confirmed: Option<bool>,
+    /// switched off after reaching `max_runtime`, the minimum off time applies even to
+    /// urgent jobs
+    resting: bool,
+    /// local day the runtime is counted for
+    day: Option<NaiveDate>,
+    runtime_today: Duration,
+    last_update: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Command {
+    pub(crate) on: bool,
+    pub(crate) at: DateTime<Utc>,
+}
+
+/// Runtime of an actor and what is left of its limits, in minutes
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub(crate) struct Quota {
+    pub(crate) on: bool,
+    /// whether one of the time windows is open
+    pub(crate) window_open: bool,
+    pub(crate) runtime_today: i64,
+    /// until `max_daily_minutes` is reached
+    pub(crate) remaining_today: Option<i64>,
+    /// until `min_daily_minutes` is reached
+    pub(crate) missing_today: Option<i64>,
+    /// until `max_runtime_minutes` is reached, while the actor is on
+    pub(crate) remaining_runtime: Option<i64>,
+    pub(crate) jobs: Vec<JobProgress>,
+    /// power of adjustable actors in watts
+    pub(crate) setpoint_watts: Option<f64>,
+}
+
+impl ActorState {
+    pub(crate) fn new(timing: Timing, schedule: Schedule, jobs: Vec<JobState>) -> Self {
+        Self {
+            timing,
+            schedule,
+            jobs,
+            on: false,
+            since: None,
+            last_command: None,
+            last_confirmed: None,
+            resting: false,
+            day: None,
+            runtime_today: Duration::zero(),
+            last_update: None,
+        }
+    }
+
+    /// Add the time since the last update to the runtime of the day and of the jobs
+    ///
+    /// An interval spanning midnight is split, the part before midnight is counted for the
+    /// previous day.
+    pub(crate) fn account(&mut self, now: DateTime<Utc>) {
+        let day = self.schedule.day(now);
+        let mut runtime = match (self.on, self.last_update) {
+            (true, Some(last_update)) => (now - last_update).max(Duration::zero()),
+            _ => Duration::zero(),
+        };
+        if self.day != Some(day) {
+            if let Some(last_update) = self.last_update {
+                let midnight = self.schedule.start_of_day(day).unwrap_or(now);
+                let today = (now - midnight).clamp(Duration::zero(), runtime);
+                self.credit(last_update, runtime - today);
+                runtime = today;
+            }
+            self.day = Some(day);
+            self.runtime_today = Duration::zero();
+            for job in self.jobs.iter_mut() {
+                job.account(&self.schedule, now, Duration::zero(), true);
+            }
+        }
+        self.credit(now, runtime);
+        self.last_update = Some(now);
+    }
+
+    fn credit(&mut self, at: DateTime<Utc>, runtime: Duration) {
+        self.runtime_today = self.runtime_today + runtime;
+        for job in self.jobs.iter_mut() {
+            job.account(&self.schedule, at, runtime, false);
+        }
+    }
+
+    /// The state the actor has to be in regardless of the surplus, if any
+    ///
+    /// Outside its time windows the actor is always off.
+    pub(crate) fn forced(&self, now: DateTime<Utc>) -> Option<bool> {
+        if !self.schedule.is_open(now) {
+            return Some(false);
+        }
+        let in_state = self.since.map(|since| now - since);
+        let exhausted = self
+            .timing
+            .max_daily
+            .is_some_and(|max| self.runtime_today >= max);
+        let missing = self.missing_today();
+        let urgent = !exhausted
+            && self
+                .jobs
+                .iter()
+                .any(|job| job.is_urgent(&self.schedule, now));
+        let needs_to_run = urgent
+            || (!exhausted
+                && missing > Duration::zero()
+                && missing >= self.schedule.until_midnight(now));
+        let min_off = in_state.is_some_and(|in_state| in_state < self.timing.min_off);
+        if self.on {
+            if exhausted || self.ran_too_long(now) {
+                Some(false)
+            } else if in_state.is_some_and(|in_state| in_state < self.timing.min_on) || needs_to_run
+            {
+                Some(true)
+            } else {
+                None
+            }
+        } else if exhausted {
+            Some(false)
+        } else if urgent && !(self.resting && min_off) {
+            // a deadline takes precedence over the minimum off time, but not over the
+            // break after `max_runtime`
+            Some(true)
+        } else if min_off {
+            Some(false)
+        } else if needs_to_run {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    /// Request a state for the actor, constraints take precedence
+    ///
+    /// Returns the state to switch the device to, if a command has to be sent. A command
+    /// is repeated as long as the device did not confirm it.
+    pub(crate) fn request(&mut self, on: bool, now: DateTime<Utc>) -> Option<bool> {
+        let on = self.forced(now).unwrap_or(on);
+        if on != self.on {
+            self.resting = !on && self.ran_too_long(now);
+            self.on = on;
+            self.since = Some(now);
+        }
+        if self.last_confirmed == Some(self.on) {
+            return None;
+        }
+        self.last_command = Some(Command {
+            on: self.on,
+            at: now,
+        });
+        Some(self.on)
+    }
+
+    /// Record the outcome of the last command
+    pub(crate) fn confirm(&mut self, success: bool) {
+        self.last_confirmed = match (success, self.last_command) {
+            (true, Some(command)) => Some(command.on),
+            _ => None,
+        };
+    }
+
+    pub(crate) fn quota(&self, now: DateTime<Utc>) -> Quota {
+        let remaining = |max: Duration, used: Duration| (max - used).max(Duration::zero());
+        Quota {
+            on: self.on,
+            window_open: self.schedule.is_open(now),
+            runtime_today: self.runtime_today.num_minutes(),
+            remaining_today: self
+                .timing
+                .max_daily
+                .map(|max| remaining(max, self.runtime_today).num_minutes()),
+            missing_today: self
+                .timing
+                .min_daily
+                .map(|_| self.missing_today().num_minutes()),
+            remaining_runtime: match (self.on, self.timing.max_runtime, self.since) {
+                (true, Some(max), Some(since)) => Some(remaining(max, now - since).num_minutes()),
+                _ => None,
+            },
+            jobs: self
+                .jobs
+                .iter()
+                .map(|job| job.progress(&self.schedule, now))
+                .collect(),
+            setpoint_watts: None,
+        }
+    }
+
+    fn ran_too_long(&self, now: DateTime<Utc>) -> bool {
+        self.on
+            && self
+                .timing
+                .max_runtime
+                .zip(self.since)
+                .is_some_and(|(max, since)| now - since >= max)
+    }
+
+    fn missing_today(&self) -> Duration {
+        match self.timing.min_daily {
+            Some(min) => (min - self.runtime_today).max(Duration::zero()),
+            None => Duration::zero(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveTime;
+    use chrono_tz::Europe::Berlin;
+
+    use crate::actors::{
+        calendar::{TimeWindow, Zone},
+        jobs::Job,
+        testing::at_minute,
+    };
+
+    use super::*;
+
+    fn actor(timing: Timing) -> ActorState {
+        ActorState::new(timing, schedule(vec![]), vec![])
+    }
+
+    fn schedule(windows: Vec<TimeWindow>) -> Schedule {
+        Schedule {
+            zone: Zone::Named(Berlin),
+            windows,
+        }
+    }
+
+    fn minutes(minutes: i64) -> Duration {
+        Duration::minutes(minutes)
+    }
+
+    /// update the actor every minute
+    fn run(actor: &mut ActorState, from: i64, to: i64, on: bool) {
+        for minute in from..to {
+            actor.account(at_minute(minute));
+            actor.request(on, at_minute(minute));
+            actor.confirm(true);
+        }
+    }
+
+    #[test]
+    pub fn sends_commands_on_changes() {
+        let mut actor = actor(Timing::default());
+
+        assert_eq!(actor.request(false, at_minute(0)), Some(false));
+        actor.confirm(true);
+        assert_eq!(actor.request(false, at_minute(1)), None);
+
+        assert_eq!(actor.request(true, at_minute(2)), Some(true));
+        actor.confirm(true);
+        assert_eq!(actor.since, Some(at_minute(2)));
+        assert_eq!(actor.request(true, at_minute(3)), None);
+
+        assert_eq!(actor.request(false, at_minute(4)), Some(false));
+        actor.confirm(true);
+        assert_eq!(
+            actor.last_command,
+            Some(Command {
+                on: false,
+                at: at_minute(4)
+            })
+        );
+        assert_eq!(actor.last_confirmed, Some(false));
+    }
+
+    #[test]
+    pub fn keeps_minimum_on_and_off_times() {
+        let mut actor = actor(Timing {
+            min_on: minutes(10),
+            min_off: minutes(5),
+            ..Default::default()
+        });
+        actor.request(true, at_minute(0));
+        actor.confirm(true);
+
+        assert_eq!(actor.forced(at_minute(9)), Some(true));
+        assert_eq!(actor.request(false, at_minute(9)), None);
+        assert_eq!(actor.forced(at_minute(10)), None);
+        assert_eq!(actor.request(false, at_minute(10)), Some(false));
+        actor.confirm(true);
+
+        assert_eq!(actor.forced(at_minute(14)), Some(false));
+        assert_eq!(actor.request(true, at_minute(14)), None);
+        assert_eq!(actor.request(true, at_minute(15)), Some(true));
+    }
+
+    #[test]
+    pub fn repeats_unconfirmed_commands() {
+        let mut actor = actor(Timing::default());
+        assert_eq!(actor.request(true, at_minute(0)), Some(true));
+        actor.confirm(false);
+        assert_eq!(actor.last_confirmed, None);
+
+        assert_eq!(actor.request(true, at_minute(1)), Some(true));
+        actor.confirm(true);
+        assert_eq!(actor.request(true, at_minute(2)), None);
+        // the first command decides about the minimum time, not the repetition
+        assert_eq!(actor.since, Some(at_minute(0)));
+    }
+
+    #[test]
+    pub fn limits_continuous_runtime() {
+        let mut actor = actor(Timing {
+            min_off: minutes(15),
+            max_runtime: Some(minutes(60)),
+            ..Default::default()
+        });
+        run(&mut actor, 600, 630, true);
+        assert_eq!(actor.quota(at_minute(630)).remaining_runtime, Some(30));
+
+        run(&mut actor, 630, 661, true);
+        assert!(!actor.on);
+        assert_eq!(actor.since, Some(at_minute(660)));
+        assert_eq!(actor.forced(at_minute(670)), Some(false));
+        assert_eq!(actor.quota(at_minute(670)).remaining_runtime, None);
+        assert_eq!(actor.forced(at_minute(675)), None);
+    }
+
+    #[test]
+    pub fn limits_daily_runtime() {
+        let mut actor = actor(Timing {
+            max_daily: Some(minutes(90)),
+            ..Default::default()
+        });
+        run(&mut actor, 600, 660, true);
+        run(&mut actor, 660, 700, false);
+        assert_eq!(actor.quota(at_minute(700)).remaining_today, Some(30));
+
+        run(&mut actor, 700, 800, true);
+        assert!(!actor.on);
+        assert_eq!(actor.quota(at_minute(800)).runtime_today, 90);
+        assert_eq!(actor.quota(at_minute(800)).remaining_today, Some(0));
+        assert_eq!(actor.forced(at_minute(800)), Some(false));
+
+        // the quota is restored on the next day
+        actor.account(at_minute(24 * 60 + 1));
+        assert_eq!(actor.forced(at_minute(24 * 60 + 1)), None);
+        assert_eq!(
+            actor.quota(at_minute(24 * 60 + 1)).remaining_today,
+            Some(90)
+        );
+    }
+
+    #[test]
+    pub fn reaches_minimum_daily_runtime() {
+        let mut actor = actor(Timing {
+            min_daily: Some(minutes(120)),
+            ..Default::default()
+        });
+        run(&mut actor, 600, 630, true);
+        run(&mut actor, 630, 1000, false);
+        assert_eq!(actor.quota(at_minute(1000)).missing_today, Some(90));
+
+        assert_eq!(actor.forced(at_minute(24 * 60 - 91)), None);
+        actor.account(at_minute(24 * 60 - 90));
+        assert_eq!(actor.forced(at_minute(24 * 60 - 90)), Some(true));
+
+        run(&mut actor, 24 * 60 - 90, 24 * 60 - 1, false);
+        assert!(actor.on);
+        assert_eq!(actor.quota(at_minute(24 * 60 - 1)).missing_today, Some(2));
+    }
+
+    #[test]
+    pub fn switches_off_when_window_closes() {
+        let mut actor = ActorState::new(
+            Timing {
+                min_on: minutes(60),
+                min_daily: Some(minutes(600)),
+                ..Default::default()
+            },
+            schedule(vec![TimeWindow {
+                start: NaiveTime::from_hms_opt(8, 0, 0),
+                end: NaiveTime::from_hms_opt(18, 0, 0),
+                ..Default::default()
+            }]),
+            vec![],
+        );
+
+        assert_eq!(actor.forced(at_minute(7 * 60)), Some(false));
+        assert!(!actor.quota(at_minute(7 * 60)).window_open);
+        run(&mut actor, 8 * 60, 17 * 60 + 30, true);
+        assert!(actor.on);
+
+        // the minimum on time and the missing runtime do not keep it running
+        assert_eq!(actor.request(true, at_minute(18 * 60)), Some(false));
+        assert!(!actor.on);
+    }
+
+    #[test]
+    pub fn runs_jobs_before_deadline() {
+        let job = Job {
+            runtime_minutes: 60,
+            deadline: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+            days: vec![],
+        };
+        let mut actor = ActorState::new(
+            Timing::default(),
+            schedule(vec![]),
+            vec![JobState::new(job)],
+        );
+
+        // surplus around noon
+        run(&mut actor, 12 * 60, 12 * 60 + 20, true);
+        run(&mut actor, 12 * 60 + 20, 17 * 60 - 20, false);
+        assert!(!actor.on);
+        assert_eq!(
+            actor.quota(at_minute(17 * 60 - 20)).jobs[0].runtime_minutes,
+            20
+        );
+
+        run(&mut actor, 17 * 60 - 20, 18 * 60, false);
+        assert!(actor.on);
+        run(&mut actor, 18 * 60, 18 * 60 + 1, false);
+        assert!(!actor.on);
+        assert!(actor.quota(at_minute(18 * 60 + 1)).jobs[0].done);
+    }
+
+    #[test]
+    pub fn urgent_jobs_override_minimum_off_time() {
+        let job = Job {
+            runtime_minutes: 60,
+            deadline: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+            days: vec![],
+        };
+        let mut actor = ActorState::new(
+            Timing {
+                min_off: minutes(30),
+                ..Default::default()
+            },
+            schedule(vec![]),
+            vec![JobState::new(job)],
+        );
+        run(&mut actor, 16 * 60 + 40, 16 * 60 + 50, true);
+        run(&mut actor, 16 * 60 + 50, 17 * 60 + 10, false);
+        assert!(!actor.on);
+
+        // switched off 20 minutes ago, but 50 minutes are missing until the deadline
+        assert_eq!(actor.forced(at_minute(17 * 60 + 10)), Some(true));
+        run(&mut actor, 17 * 60 + 10, 18 * 60 + 1, false);
+        assert!(actor.quota(at_minute(18 * 60 + 1)).jobs[0].done);
+    }
+
+    #[test]
+    pub fn urgent_jobs_keep_the_break_after_maximum_runtime() {
+        let job = Job {
+            runtime_minutes: 120,
+            deadline: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+            days: vec![],
+        };
+        let mut actor = ActorState::new(
+            Timing {
+                min_off: minutes(15),
+                max_runtime: Some(minutes(60)),
+                ..Default::default()
+            },
+            schedule(vec![]),
+            vec![JobState::new(job)],
+        );
+        run(&mut actor, 16 * 60, 17 * 60 + 1, false);
+        assert!(!actor.on);
+
+        // the job is urgent, but the actor rests for the minimum off time
+        assert_eq!(actor.forced(at_minute(17 * 60 + 1)), Some(false));
+        run(&mut actor, 17 * 60 + 1, 17 * 60 + 15, false);
+        assert!(!actor.on);
+        assert_eq!(actor.forced(at_minute(17 * 60 + 15)), Some(true));
+        run(&mut actor, 17 * 60 + 15, 17 * 60 + 16, false);
+        assert!(actor.on);
+    }
+
+    #[test]
+    pub fn splits_runtime_at_midnight() {
+        let job = Job {
+            runtime_minutes: 60,
+            deadline: NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+            days: vec![],
+        };
+        let mut actor = ActorState::new(
+            Timing::default(),
+            schedule(vec![]),
+            vec![JobState::new(job)],
+        );
+        run(&mut actor, 24 * 60 - 30, 24 * 60 - 10, true);
+        assert_eq!(actor.quota(at_minute(24 * 60 - 10)).runtime_today, 19);
+
+        // a single update after midnight
+        actor.account(at_minute(24 * 60 + 20));
+
+        let quota = actor.quota(at_minute(24 * 60 + 20));
+        assert_eq!(quota.runtime_today, 20);
+        assert_eq!(quota.jobs[0].runtime_minutes, 20);
+    }
+}
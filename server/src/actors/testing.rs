@@ -0,0 +1,21 @@
+//! Points in time for tests
+
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Europe::Berlin;
+
+/// `seconds` after an arbitrary point in time
+pub(crate) fn at_second(seconds: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(1_700_000_000, 0).unwrap() + Duration::seconds(seconds)
+}
+
+/// `minutes` after midnight in Berlin on monday, 2024-06-03
+pub(crate) fn at_minute(minutes: i64) -> DateTime<Utc> {
+    let midnight = NaiveDate::from_ymd_opt(2024, 6, 3)
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        .unwrap();
+    Berlin
+        .from_local_datetime(&midnight)
+        .unwrap()
+        .with_timezone(&Utc)
+        + Duration::minutes(minutes)
+}
@@ -0,0 +1,313 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+
+/// Stage of the filter between the meter and the control of the actors
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum FilterStage {
+    /// mean of the last readings
+    MovingAverage { samples: usize },
+    /// exponential moving average, `alpha` between 0 and 1 is the weight of a new reading
+    Ema { alpha: f64 },
+    /// median of the last readings
+    Median { samples: usize },
+    /// drop readings changing faster than the limit, unless the change persists for
+    /// `max_rejected` readings
+    RateOfChange {
+        max_watts_per_second: f64,
+        #[serde(default = "default_max_rejected")]
+        max_rejected: usize,
+    },
+    /// pass a crossing of the threshold only once the power stayed on the other side for
+    /// the given time, the last value is repeated until then
+    Sustained { threshold: f64, seconds: u64 },
+}
+
+fn default_max_rejected() -> usize {
+    3
+}
+
+enum Filter {
+    MovingAverage {
+        samples: usize,
+        window: VecDeque<f64>,
+    },
+    Ema {
+        alpha: f64,
+        value: Option<f64>,
+    },
+    Median {
+        samples: usize,
+        window: VecDeque<f64>,
+    },
+    RateOfChange {
+        max_watts_per_second: f64,
+        max_rejected: usize,
+        last: Option<(DateTime<Utc>, f64)>,
+        rejected: usize,
+    },
+    Sustained {
+        threshold: f64,
+        duration: Duration,
+        held: Option<f64>,
+        crossed_since: Option<DateTime<Utc>>,
+    },
+}
+
+impl From<&FilterStage> for Filter {
+    fn from(stage: &FilterStage) -> Self {
+        match *stage {
+            FilterStage::MovingAverage { samples } => Filter::MovingAverage {
+                samples: samples.max(1),
+                window: VecDeque::new(),
+            },
+            FilterStage::Ema { alpha } => Filter::Ema {
+                alpha: alpha.clamp(f64::EPSILON, 1.0),
+                value: None,
+            },
+            FilterStage::Median { samples } => Filter::Median {
+                samples: samples.max(1),
+                window: VecDeque::new(),
+            },
+            FilterStage::RateOfChange {
+                max_watts_per_second,
+                max_rejected,
+            } => Filter::RateOfChange {
+                max_watts_per_second,
+                max_rejected,
+                last: None,
+                rejected: 0,
+            },
+            FilterStage::Sustained { threshold, seconds } => Filter::Sustained {
+                threshold,
+                duration: Duration::seconds(seconds as i64),
+                held: None,
+                crossed_since: None,
+            },
+        }
+    }
+}
+
+impl Filter {
+    fn apply(&mut self, time: DateTime<Utc>, watts: f64) -> Option<f64> {
+        match self {
+            Filter::MovingAverage { samples, window } => {
+                push(window, *samples, watts);
+                Some(window.iter().sum::<f64>() / window.len() as f64)
+            }
+            Filter::Ema { alpha, value } => {
+                let next = match value {
+                    Some(value) => *alpha * watts + (1.0 - *alpha) * *value,
+                    None => watts,
+                };
+                *value = Some(next);
+                Some(next)
+            }
+            Filter::Median { samples, window } => {
+                push(window, *samples, watts);
+                let mut sorted = window.iter().copied().collect::<Vec<_>>();
+                sorted.sort_by(f64::total_cmp);
+                let middle = sorted.len() / 2;
+                Some(if sorted.len() % 2 == 0 {
+                    (sorted[middle - 1] + sorted[middle]) / 2.0
+                } else {
+                    sorted[middle]
+                })
+            }
+            Filter::RateOfChange {
+                max_watts_per_second,
+                max_rejected,
+                last,
+                rejected,
+            } => {
+                if let Some((last_time, last_watts)) = *last {
+                    let seconds = ((time - last_time).num_milliseconds() as f64 / 1000.0).max(1.0);
+                    let rate = (watts - last_watts).abs() / seconds;
+                    if rate > *max_watts_per_second && *rejected < *max_rejected {
+                        *rejected += 1;
+                        return None;
+                    }
+                }
+                *rejected = 0;
+                *last = Some((time, watts));
+                Some(watts)
+            }
+            Filter::Sustained {
+                threshold,
+                duration,
+                held,
+                crossed_since,
+            } => {
+                let Some(held_watts) = *held else {
+                    *held = Some(watts);
+                    return Some(watts);
+                };
+                if (watts > *threshold) == (held_watts > *threshold) {
+                    *crossed_since = None;
+                    *held = Some(watts);
+                    return Some(watts);
+                }
+                let since = *crossed_since.get_or_insert(time);
+                if time - since >= *duration {
+                    *crossed_since = None;
+                    *held = Some(watts);
+                    Some(watts)
+                } else {
+                    Some(held_watts)
+                }
+            }
+        }
+    }
+}
+
+fn push(window: &mut VecDeque<f64>, samples: usize, watts: f64) {
+    window.push_back(watts);
+    while window.len() > samples {
+        window.pop_front();
+    }
+}
+
+/// Stages applied one after another to the power readings
+pub(crate) struct PowerFilter {
+    filters: Vec<Filter>,
+}
+
+impl PowerFilter {
+    pub(crate) fn new(stages: &[FilterStage]) -> Self {
+        Self {
+            filters: stages.iter().map(Filter::from).collect(),
+        }
+    }
+
+    /// The filtered reading, `None` if it was dropped
+    pub(crate) fn apply(&mut self, time: DateTime<Utc>, watts: i32) -> Option<i32> {
+        let mut value = watts as f64;
+        for filter in self.filters.iter_mut() {
+            value = filter.apply(time, value)?;
+        }
+        Some(value.round() as i32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::actors::testing::at_second;
+
+    use super::*;
+
+    fn run(stages: &[FilterStage], readings: &[i32]) -> Vec<Option<i32>> {
+        let mut filter = PowerFilter::new(stages);
+        readings
+            .iter()
+            .enumerate()
+            .map(|(i, watts)| filter.apply(at_second(i as i64), *watts))
+            .collect()
+    }
+
+    #[test]
+    pub fn passes_readings_without_stages() {
+        assert_eq!(run(&[], &[1, -2]), vec![Some(1), Some(-2)]);
+    }
+
+    #[test]
+    pub fn averages_readings() {
+        let stages = [FilterStage::MovingAverage { samples: 3 }];
+
+        assert_eq!(
+            run(&stages, &[300, 600, 0, 1200]),
+            vec![Some(300), Some(450), Some(300), Some(600)]
+        );
+    }
+
+    #[test]
+    pub fn smoothes_exponentially() {
+        let stages = [FilterStage::Ema { alpha: 0.25 }];
+
+        assert_eq!(
+            run(&stages, &[100, 500, 500]),
+            vec![Some(100), Some(200), Some(275)]
+        );
+    }
+
+    #[test]
+    pub fn takes_median() {
+        let stages = [FilterStage::Median { samples: 3 }];
+
+        assert_eq!(
+            run(&stages, &[100, 3000, 120, 110, -50]),
+            vec![Some(100), Some(1550), Some(120), Some(120), Some(110)]
+        );
+    }
+
+    #[test]
+    pub fn rejects_spikes() {
+        let stages = [FilterStage::RateOfChange {
+            max_watts_per_second: 500.0,
+            max_rejected: 2,
+        }];
+
+        // a single spike is dropped
+        assert_eq!(
+            run(&stages, &[100, 2500, 150]),
+            vec![Some(100), None, Some(150)]
+        );
+        // a persisting change is accepted after two rejected readings
+        assert_eq!(
+            run(&stages, &[100, 2500, 2500, 2500, 2600]),
+            vec![Some(100), None, None, Some(2500), Some(2600)]
+        );
+    }
+
+    #[test]
+    pub fn holds_until_crossing_is_sustained() {
+        let stages = [FilterStage::Sustained {
+            threshold: 0.0,
+            seconds: 2,
+        }];
+
+        assert_eq!(
+            run(&stages, &[200, -100, 300, -100, -200, -300, -400]),
+            vec![
+                Some(200),
+                Some(200),
+                Some(300),
+                Some(300),
+                Some(300),
+                Some(-300),
+                Some(-400)
+            ]
+        );
+    }
+
+    #[test]
+    pub fn chains_stages() {
+        let stages = [
+            FilterStage::Median { samples: 3 },
+            FilterStage::MovingAverage { samples: 2 },
+        ];
+
+        assert_eq!(
+            run(&stages, &[100, 5000, 100, 100]),
+            vec![Some(100), Some(1325), Some(1325), Some(100)]
+        );
+    }
+
+    #[test]
+    pub fn parses_stages() {
+        let stages = serde_yaml::from_str::<Vec<FilterStage>>(
+            "- type: median\n  samples: 5\n- type: rate_of_change\n  max_watts_per_second: 1000",
+        )
+        .unwrap();
+
+        assert!(matches!(stages[0], FilterStage::Median { samples: 5 }));
+        assert!(matches!(
+            stages[1],
+            FilterStage::RateOfChange {
+                max_rejected: 3,
+                ..
+            }
+        ));
+    }
+}
@@ -6,41 +6,55 @@ use hackdose_sml_parser::application::{
 };
 use tokio::{
     io::AsyncWriteExt,
-    sync::{mpsc::Sender, Mutex},
+    sync::{mpsc::Sender, watch, Mutex},
 };
 use tokio_stream::Stream;
 use tokio_stream::StreamExt;
 
-use crate::{smart_meter::body::find_watts, Configuration};
+use crate::{emulation::MeterSnapshot, smart_meter::body::find_watts, Configuration};
+
+use self::filter::PowerFilter;
+
+pub(crate) mod filter;
 
 pub(crate) async fn handle_power_events(
     tx: &mut Sender<i32>,
     mutex: Arc<Mutex<HashMap<Obis, AnyValue>>>,
+    meter: &watch::Sender<MeterSnapshot>,
     config: &Configuration,
     mut power_events: impl Stream<Item = SmlMessages> + Unpin + Send + 'static,
 ) {
+    let mut filter = PowerFilter::new(&config.filters);
     while let Some(message) = power_events.next().await {
         let watts = find_watts(&message, mutex.clone()).await;
 
-        match watts {
-            Some(watts) => {
-                let time = chrono::Utc::now();
-                let f = time.format("%Y-%m-%d %H:%M:%S");
-                let log_line = format!("{};{}\n", f, watts);
-                let log = tokio::fs::OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(&config.log_location)
-                    .await;
-                match log {
-                    Ok(mut file) => {
-                        let _ = file.write_all(log_line.as_bytes()).await;
-                    }
-                    Err(_) => (),
-                }
-                tx.send(watts).await.unwrap();
+        if let Some(watts) = watts {
+            let time = chrono::Utc::now();
+            // emulated meters report the raw values
+            meter.send_replace(MeterSnapshot {
+                time: Some(time),
+                values: mutex.lock().await.clone(),
+            });
+            let filtered = filter.apply(time, watts);
+            let f = time.format("%Y-%m-%d %H:%M:%S");
+            // raw and filtered power, the latter is empty for dropped readings
+            let log_line = format!(
+                "{};{};{}\n",
+                f,
+                watts,
+                filtered.map(|w| w.to_string()).unwrap_or_default()
+            );
+            let log = tokio::fs::OpenOptions::new()
+                .write(true)
+                .append(true)
+                .open(&config.log_location)
+                .await;
+            if let Ok(mut file) = log {
+                let _ = file.write_all(log_line.as_bytes()).await;
+            }
+            if let Some(filtered) = filtered {
+                tx.send(filtered).await.unwrap();
             }
-            None => {}
         }
     }
 }
@@ -0,0 +1,58 @@
+//! Meters emulated for inverters and batteries which need to know the grid power
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use hackdose_sml_parser::application::{domain::AnyValue, obis::Obis};
+use tokio::sync::watch;
+
+pub(crate) mod speedwire;
+pub(crate) mod sunspec;
+
+/// Latest values of the smart meter and the time they were received
+#[derive(Clone, Default)]
+pub(crate) struct MeterSnapshot {
+    pub(crate) time: Option<DateTime<Utc>>,
+    pub(crate) values: HashMap<Obis, AnyValue>,
+}
+
+pub(crate) type MeterUpdates = watch::Receiver<MeterSnapshot>;
+
+impl MeterSnapshot {
+    pub(crate) fn value(&self, obis: Obis) -> Option<f64> {
+        match self.values.get(&obis)? {
+            AnyValue::Unsigned(value) => Some(*value as f64),
+            AnyValue::Signed(value) => Some(*value as f64),
+            AnyValue::String(_) => None,
+        }
+    }
+
+    /// Whether the values are older than `max_age` or missing
+    pub(crate) fn is_stale(&self, now: DateTime<Utc>, max_age: Duration) -> bool {
+        self.time.is_none_or(|time| now - time > max_age)
+    }
+
+    /// Power drawn from the grid in watts, negative when feeding in
+    pub(crate) fn power(&self) -> Option<f64> {
+        self.value(Obis::SumActiveInstantaneousPower)
+    }
+
+    /// Power of the phases L1 to L3, if the meter measures all of them
+    pub(crate) fn phase_powers(&self) -> Option<[f64; 3]> {
+        Some([
+            self.value(Obis::SumActiveInstantaneousPowerPhaseL1)?,
+            self.value(Obis::SumActiveInstantaneousPowerPhaseL2)?,
+            self.value(Obis::SumActiveInstantaneousPowerPhaseL3)?,
+        ])
+    }
+
+    /// Energy drawn from the grid in Wh
+    pub(crate) fn imported_energy(&self) -> Option<f64> {
+        self.value(Obis::PositiveActiveEnergyTotal)
+    }
+
+    /// Energy fed into the grid in Wh
+    pub(crate) fn exported_energy(&self) -> Option<f64> {
+        self.value(Obis::NegativeActiveEnergyTotal)
+    }
+}
@@ -0,0 +1,280 @@
+//! SMA Energy Meter protocol, UDP multicast packets in the Speedwire format
+//!
+//! A packet consists of the `SMA` header, the serial of the meter, a millisecond ticker and
+//! measurements identified by OBIS channel, index and type. Power is sent in 0.1 W, energy
+//! counters in Ws, import and export separately.
+
+use std::{net::SocketAddr, time::Duration as StdDuration};
+
+use chrono::{Duration, Utc};
+use hackdose_sml_parser::application::obis::Obis;
+use serde::Deserialize;
+use tokio::net::UdpSocket;
+
+use super::{MeterSnapshot, MeterUpdates};
+
+/// software version reported in the packets, 2.0.18.R
+const VERSION: [u8; 4] = [2, 0, 18, b'R'];
+
+/// Emitter of energy meter packets, the `speedwire` section of the configuration
+#[derive(Deserialize, Clone, Debug)]
+pub(crate) struct SpeedwireConfiguration {
+    /// serial number receivers are paired with
+    pub(crate) serial: u32,
+    /// device type, 270 is the SMA Energy Meter 1.0
+    #[serde(default = "default_susy_id")]
+    pub(crate) susy_id: u16,
+    /// the multicast group by default, a unicast address is possible as well
+    #[serde(default = "default_destination")]
+    pub(crate) destination: SocketAddr,
+    /// no packets are sent while the readings are older
+    #[serde(default = "default_stale_seconds")]
+    pub(crate) stale_seconds: u64,
+}
+
+fn default_susy_id() -> u16 {
+    270
+}
+
+fn default_destination() -> SocketAddr {
+    ([239, 12, 255, 254], 9522).into()
+}
+
+fn default_stale_seconds() -> u64 {
+    10
+}
+
+/// Send the current values once per second
+pub(crate) async fn emit_speedwire(config: SpeedwireConfiguration, updates: MeterUpdates) {
+    let socket = match UdpSocket::bind(("0.0.0.0", 0)).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("could not open socket for speedwire: {}", e);
+            return;
+        }
+    };
+    let start = tokio::time::Instant::now();
+    let mut interval = tokio::time::interval(StdDuration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let packet = {
+            let snapshot = updates.borrow();
+            let max_age = Duration::seconds(config.stale_seconds as i64);
+            if snapshot.is_stale(Utc::now(), max_age) {
+                continue;
+            }
+            // wraps after 49 days like the ticker of the meter
+            let ticker = start.elapsed().as_millis() as u32;
+            packet(&config, &snapshot, ticker)
+        };
+        if let Err(e) = socket.send_to(&packet, config.destination).await {
+            eprintln!("could not send speedwire packet: {}", e);
+        }
+    }
+}
+
+fn packet(config: &SpeedwireConfiguration, snapshot: &MeterSnapshot, ticker: u32) -> Vec<u8> {
+    let mut data = vec![];
+    data.extend(config.susy_id.to_be_bytes());
+    data.extend(config.serial.to_be_bytes());
+    data.extend(ticker.to_be_bytes());
+
+    let power = snapshot.power().unwrap_or(0.0);
+    measurements(
+        &mut data,
+        0,
+        power,
+        [snapshot.imported_energy(), snapshot.exported_energy()],
+    );
+    if let Some(phases) = snapshot.phase_powers() {
+        let energies = [
+            [
+                Obis::PositveActiveEnergyPhaseL1Total,
+                Obis::NegativeActiveEnergyPhaseL1Total,
+            ],
+            [
+                Obis::PositveActiveEnergyPhaseL2Total,
+                Obis::NegativeActiveEnergyPhaseL2Total,
+            ],
+            [
+                Obis::PositveActiveEnergyPhaseL3Total,
+                Obis::NegativeActiveEnergyPhaseL3Total,
+            ],
+        ];
+        for (i, (power, [imported, exported])) in phases.into_iter().zip(energies).enumerate() {
+            let energies = [snapshot.value(imported), snapshot.value(exported)];
+            measurements(&mut data, 20 * (i as u8 + 1), power, energies);
+        }
+    }
+    data.extend([0x90, 0, 0, 0]);
+    data.extend(VERSION);
+
+    let mut packet = b"SMA\0".to_vec();
+    // tag 0x02a0 with group 1
+    packet.extend([0, 4, 0x02, 0xa0, 0, 0, 0, 1]);
+    // length from the protocol id
+    packet.extend((data.len() as u16 + 2).to_be_bytes());
+    // tag 0x0010 (SMA Net 2) and protocol id 0x6069 (energy meter)
+    packet.extend([0x00, 0x10, 0x60, 0x69]);
+    packet.extend(data);
+    // end of data
+    packet.extend([0, 0, 0, 0]);
+    packet
+}
+
+/// Power and energy of import (index 1) and export (index 2), shifted by `offset` for the
+/// phases
+fn measurements(data: &mut Vec<u8>, offset: u8, power: f64, energies: [Option<f64>; 2]) {
+    for (index, (watts, energy)) in [1, 2]
+        .into_iter()
+        .zip([power, -power].into_iter().zip(energies))
+    {
+        let tenths = (watts.max(0.0) * 10.0).round() as u32;
+        data.extend([0, offset + index, 4, 0]);
+        data.extend(tenths.to_be_bytes());
+        if let Some(energy) = energy {
+            // Wh to Ws
+            let seconds = (energy.max(0.0) * 3600.0).round() as u64;
+            data.extend([0, offset + index, 8, 0]);
+            data.extend(seconds.to_be_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hackdose_sml_parser::application::domain::AnyValue;
+    use tokio::sync::watch;
+
+    use super::*;
+
+    fn config(destination: SocketAddr) -> SpeedwireConfiguration {
+        SpeedwireConfiguration {
+            serial: 1_900_123_456,
+            susy_id: 270,
+            destination,
+            stale_seconds: 10,
+        }
+    }
+
+    fn snapshot(values: Vec<(Obis, AnyValue)>) -> MeterSnapshot {
+        MeterSnapshot {
+            time: Some(Utc::now()),
+            values: values.into_iter().collect(),
+        }
+    }
+
+    /// measurements by channel, index and type
+    fn measurements(packet: &[u8]) -> Vec<([u8; 3], u64)> {
+        let mut result = vec![];
+        let mut data = &packet[28..packet.len() - 4];
+        while let [_, index, kind, _, rest @ ..] = data {
+            let length = if *kind == 8 { 8 } else { 4 };
+            let value = rest[..length]
+                .iter()
+                .fold(0u64, |value, byte| value << 8 | *byte as u64);
+            result.push(([*index, *kind, 0], value));
+            data = &rest[length..];
+        }
+        result
+    }
+
+    #[test]
+    pub fn encodes_header() {
+        let packet = packet(
+            &config(default_destination()),
+            &snapshot(vec![(
+                Obis::SumActiveInstantaneousPower,
+                AnyValue::Signed(500),
+            )]),
+            0x01020304,
+        );
+
+        assert_eq!(&packet[0..12], b"SMA\0\0\x04\x02\xa0\0\0\0\x01");
+        assert_eq!(
+            u16::from_be_bytes([packet[12], packet[13]]) as usize,
+            packet.len() - 20
+        );
+        assert_eq!(&packet[14..18], &[0x00, 0x10, 0x60, 0x69]);
+        assert_eq!(&packet[18..20], &270u16.to_be_bytes());
+        assert_eq!(&packet[20..24], &1_900_123_456u32.to_be_bytes());
+        assert_eq!(&packet[24..28], &[1, 2, 3, 4]);
+        assert_eq!(&packet[packet.len() - 4..], &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    pub fn splits_import_and_export() {
+        let packet = packet(
+            &config(default_destination()),
+            &snapshot(vec![
+                (Obis::SumActiveInstantaneousPower, AnyValue::Signed(-1234)),
+                (Obis::PositiveActiveEnergyTotal, AnyValue::Unsigned(1000)),
+                (Obis::NegativeActiveEnergyTotal, AnyValue::Unsigned(2)),
+            ]),
+            0,
+        );
+
+        assert_eq!(
+            measurements(&packet),
+            vec![
+                ([1, 4, 0], 0),
+                ([1, 8, 0], 3_600_000),
+                ([2, 4, 0], 12340),
+                ([2, 8, 0], 7200),
+                ([0, 0, 0], u32::from_be_bytes(VERSION) as u64),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn sends_phases() {
+        let packet = packet(
+            &config(default_destination()),
+            &snapshot(vec![
+                (Obis::SumActiveInstantaneousPower, AnyValue::Signed(100)),
+                (
+                    Obis::SumActiveInstantaneousPowerPhaseL1,
+                    AnyValue::Signed(300),
+                ),
+                (
+                    Obis::SumActiveInstantaneousPowerPhaseL2,
+                    AnyValue::Signed(-250),
+                ),
+                (
+                    Obis::SumActiveInstantaneousPowerPhaseL3,
+                    AnyValue::Signed(50),
+                ),
+                (
+                    Obis::PositveActiveEnergyPhaseL2Total,
+                    AnyValue::Unsigned(10),
+                ),
+            ]),
+            0,
+        );
+        let measurements = measurements(&packet);
+
+        assert!(measurements.contains(&([21, 4, 0], 3000)));
+        assert!(measurements.contains(&([42, 4, 0], 2500)));
+        assert!(measurements.contains(&([41, 8, 0], 36000)));
+        assert!(measurements.contains(&([61, 4, 0], 500)));
+        assert!(!measurements.iter().any(|(id, _)| *id == [21, 8, 0]));
+    }
+
+    #[tokio::test]
+    pub async fn emits_fresh_readings() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let (tx, updates) = watch::channel(MeterSnapshot::default());
+        tokio::spawn(emit_speedwire(
+            config(receiver.local_addr().unwrap()),
+            updates,
+        ));
+
+        tx.send_replace(snapshot(vec![(
+            Obis::SumActiveInstantaneousPower,
+            AnyValue::Signed(42),
+        )]));
+        let mut buf = [0; 1500];
+        let length = receiver.recv(&mut buf).await.unwrap();
+        assert_eq!(measurements(&buf[..length])[0], ([1, 4, 0], 420));
+    }
+}
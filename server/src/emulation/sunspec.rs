@@ -0,0 +1,442 @@
+//! SunSpec meter on Modbus TCP
+//!
+//! The registers start at 40000 with the `SunS` marker, followed by the common model 1 and
+//! the meter model 201 (single phase) or 203 (three phase wye), values are given as
+//! integers with scale factors.
+
+use std::net::SocketAddr;
+
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use tokio::net::TcpListener;
+
+use crate::modbus::server::{
+    read_request, registers_response, serve, Handler, GATEWAY_TARGET_FAILED, ILLEGAL_DATA_ADDRESS,
+    ILLEGAL_FUNCTION, SERVER_DEVICE_FAILURE,
+};
+
+use super::{MeterSnapshot, MeterUpdates};
+
+const BASE_ADDRESS: u16 = 40000;
+const COMMON_MODEL_LENGTH: u16 = 66;
+const METER_MODEL_LENGTH: u16 = 105;
+/// first register of the meter model, after the marker and the common model
+const METER_MODEL_ADDRESS: u16 = BASE_ADDRESS + 2 + 2 + COMMON_MODEL_LENGTH;
+
+/// value of int16 points which are not implemented
+const NOT_IMPLEMENTED: u16 = 0x8000;
+
+/// Modbus TCP server emulating a grid meter, the `sunspec` section of the configuration
+#[derive(Deserialize, Clone, Debug)]
+pub(crate) struct SunSpecConfiguration {
+    #[serde(default = "default_listen")]
+    pub(crate) listen: SocketAddr,
+    #[serde(default = "default_unit_id")]
+    pub(crate) unit_id: u8,
+    #[serde(default)]
+    pub(crate) model: MeterModel,
+    /// readings older than this are replaced according to `on_stale`
+    #[serde(default = "default_stale_seconds")]
+    pub(crate) stale_seconds: u64,
+    #[serde(default)]
+    pub(crate) on_stale: StaleFallback,
+    /// the meter does not measure voltage and frequency, these are reported instead
+    #[serde(default = "default_voltage")]
+    pub(crate) voltage: f64,
+    #[serde(default = "default_frequency")]
+    pub(crate) frequency: f64,
+}
+
+fn default_listen() -> SocketAddr {
+    ([0, 0, 0, 0], 502).into()
+}
+
+fn default_unit_id() -> u8 {
+    1
+}
+
+fn default_stale_seconds() -> u64 {
+    10
+}
+
+fn default_voltage() -> f64 {
+    230.0
+}
+
+fn default_frequency() -> f64 {
+    50.0
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(try_from = "u16")]
+pub(crate) enum MeterModel {
+    SinglePhase,
+    #[default]
+    ThreePhase,
+}
+
+impl TryFrom<u16> for MeterModel {
+    type Error = String;
+
+    fn try_from(model: u16) -> Result<Self, Self::Error> {
+        match model {
+            201 => Ok(MeterModel::SinglePhase),
+            203 => Ok(MeterModel::ThreePhase),
+            _ => Err(format!("unsupported meter model {}, use 201 or 203", model)),
+        }
+    }
+}
+
+impl MeterModel {
+    fn id(&self) -> u16 {
+        match self {
+            MeterModel::SinglePhase => 201,
+            MeterModel::ThreePhase => 203,
+        }
+    }
+}
+
+/// What the meter reports when the smart meter did not send values for a while
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum StaleFallback {
+    /// answer reads of the meter model with an exception, most inverters fall back to
+    /// their safe state
+    #[default]
+    Error,
+    /// report no power flow, energy counters are kept
+    Zero,
+}
+
+/// Serve the registers of the meter until the listener fails
+pub(crate) async fn serve_sunspec_meter(config: SunSpecConfiguration, updates: MeterUpdates) {
+    let listener = match TcpListener::bind(config.listen).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("could not listen on {} for SunSpec: {}", config.listen, e);
+            return;
+        }
+    };
+    serve(listener, SunSpecMeter { config, updates }).await;
+}
+
+struct SunSpecMeter {
+    config: SunSpecConfiguration,
+    updates: MeterUpdates,
+}
+
+impl Handler for SunSpecMeter {
+    fn handle(&self, unit_id: u8, pdu: &[u8]) -> Result<Vec<u8>, u8> {
+        if unit_id != self.config.unit_id {
+            return Err(GATEWAY_TARGET_FAILED);
+        }
+        if !matches!(pdu[0], 0x03 | 0x04) {
+            return Err(ILLEGAL_FUNCTION);
+        }
+        let (address, count) = read_request(pdu)?;
+        let registers = registers(&self.config, &self.updates.borrow());
+        let start = address
+            .checked_sub(BASE_ADDRESS)
+            .ok_or(ILLEGAL_DATA_ADDRESS)? as usize;
+        let words = registers
+            .get(start..start + count as usize)
+            .ok_or(ILLEGAL_DATA_ADDRESS)?;
+        let reads_meter = address + count > METER_MODEL_ADDRESS;
+        let max_age = Duration::seconds(self.config.stale_seconds as i64);
+        if reads_meter
+            && self.config.on_stale == StaleFallback::Error
+            && self.updates.borrow().is_stale(Utc::now(), max_age)
+        {
+            return Err(SERVER_DEVICE_FAILURE);
+        }
+        Ok(registers_response(words))
+    }
+}
+
+/// All registers from the base address
+fn registers(config: &SunSpecConfiguration, snapshot: &MeterSnapshot) -> Vec<u16> {
+    let stale = snapshot.is_stale(Utc::now(), Duration::seconds(config.stale_seconds as i64));
+    let mut registers = string("SunS", 2);
+
+    registers.extend([1, COMMON_MODEL_LENGTH]);
+    registers.extend(string("hackdose", 16));
+    registers.extend(string("SML meter", 16));
+    registers.extend(string("", 8));
+    registers.extend(string(env!("CARGO_PKG_VERSION"), 8));
+    registers.extend(string("", 16));
+    // device address and padding
+    registers.extend([config.unit_id as u16, NOT_IMPLEMENTED]);
+
+    registers.extend([config.model.id(), METER_MODEL_LENGTH]);
+    registers.extend(meter_model(config, snapshot, stale));
+
+    // end marker
+    registers.extend([0xffff, 0]);
+    registers
+}
+
+fn meter_model(config: &SunSpecConfiguration, snapshot: &MeterSnapshot, stale: bool) -> Vec<u16> {
+    let power = if stale { Some(0.0) } else { snapshot.power() };
+    let phases = match (config.model, stale) {
+        (MeterModel::SinglePhase, _) => power.map(|power| [power, 0.0, 0.0]),
+        (MeterModel::ThreePhase, true) => Some([0.0; 3]),
+        (MeterModel::ThreePhase, false) => snapshot.phase_powers(),
+    };
+    let phase_count = match config.model {
+        MeterModel::SinglePhase => 1,
+        MeterModel::ThreePhase => 3,
+    };
+    let phase = |i: usize, value: f64| {
+        if i < phase_count {
+            int16(value)
+        } else {
+            NOT_IMPLEMENTED
+        }
+    };
+
+    let mut model = Vec::with_capacity(METER_MODEL_LENGTH as usize);
+    // current in 0.01 A, derived from the power
+    let currents = phases.map(|phases| phases.map(|watts| watts.abs() / config.voltage * 100.0));
+    model.push(currents.map_or(NOT_IMPLEMENTED, |currents| {
+        int16(currents[..phase_count].iter().sum())
+    }));
+    for i in 0..3 {
+        model.push(currents.map_or(NOT_IMPLEMENTED, |currents| phase(i, currents[i])));
+    }
+    model.push(-2i16 as u16);
+    // voltage, line to neutral and line to line, in 0.1 V
+    let voltage = config.voltage * 10.0;
+    model.push(int16(voltage));
+    for i in 0..3 {
+        model.push(phase(i, voltage));
+    }
+    model.extend([NOT_IMPLEMENTED; 4]);
+    model.push(-1i16 as u16);
+    // frequency in 0.01 Hz
+    model.extend([int16(config.frequency * 100.0), -2i16 as u16]);
+    // real power in W
+    model.push(power.map_or(NOT_IMPLEMENTED, int16));
+    for i in 0..3 {
+        model.push(phases.map_or(NOT_IMPLEMENTED, |phases| phase(i, phases[i])));
+    }
+    model.push(0);
+    // apparent power, reactive power, power factor
+    model.extend([NOT_IMPLEMENTED; 15]);
+    // energy in Wh, exported then imported, each total and per phase
+    for energy in [snapshot.exported_energy(), snapshot.imported_energy()] {
+        model.extend(acc32(energy));
+        model.extend([0; 6]);
+    }
+    model.push(0);
+    // apparent and reactive energy
+    model.extend([0; 16]);
+    model.push(NOT_IMPLEMENTED);
+    model.extend([0; 32]);
+    model.push(NOT_IMPLEMENTED);
+    // events
+    model.extend([0, 0]);
+    model
+}
+
+fn int16(value: f64) -> u16 {
+    value.round().clamp(-32767.0, 32767.0) as i16 as u16
+}
+
+/// Accumulator, 0 if not implemented
+fn acc32(value: Option<f64>) -> [u16; 2] {
+    let value = value.map_or(0, |value| value.round().clamp(0.0, u32::MAX as f64) as u32);
+    [(value >> 16) as u16, value as u16]
+}
+
+/// String padded with zeros to `registers` registers
+fn string(value: &str, registers: usize) -> Vec<u16> {
+    let mut bytes = value.as_bytes().to_vec();
+    bytes.resize(2 * registers, 0);
+    bytes
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use hackdose_sml_parser::application::{domain::AnyValue, obis::Obis};
+    use tokio::sync::watch;
+
+    use super::*;
+    use crate::modbus::{Client, ModbusError, RegisterKind};
+
+    fn config(model: MeterModel, on_stale: StaleFallback) -> SunSpecConfiguration {
+        SunSpecConfiguration {
+            listen: ([127, 0, 0, 1], 0).into(),
+            unit_id: 1,
+            model,
+            stale_seconds: 10,
+            on_stale,
+            voltage: 230.0,
+            frequency: 50.0,
+        }
+    }
+
+    fn snapshot(age_seconds: i64) -> MeterSnapshot {
+        MeterSnapshot {
+            time: Some(Utc::now() - Duration::seconds(age_seconds)),
+            values: [
+                (Obis::SumActiveInstantaneousPower, AnyValue::Signed(-690)),
+                (
+                    Obis::SumActiveInstantaneousPowerPhaseL1,
+                    AnyValue::Signed(-1000),
+                ),
+                (
+                    Obis::SumActiveInstantaneousPowerPhaseL2,
+                    AnyValue::Signed(80),
+                ),
+                (
+                    Obis::SumActiveInstantaneousPowerPhaseL3,
+                    AnyValue::Signed(230),
+                ),
+                (
+                    Obis::PositiveActiveEnergyTotal,
+                    AnyValue::Unsigned(12_345_678),
+                ),
+                (Obis::NegativeActiveEnergyTotal, AnyValue::Unsigned(70_000)),
+            ]
+            .into_iter()
+            .collect(),
+        }
+    }
+
+    async fn meter(config: SunSpecConfiguration, snapshot: MeterSnapshot) -> Client {
+        let listener = TcpListener::bind(config.listen).await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (_, updates) = watch::channel(snapshot);
+        tokio::spawn(serve(listener, SunSpecMeter { config, updates }));
+        Client::new(&address, 1)
+    }
+
+    async fn read(client: &Client, address: u16, count: u16) -> Result<Vec<u16>, ModbusError> {
+        client
+            .read_registers(RegisterKind::Holding, address, count)
+            .await
+    }
+
+    #[test]
+    pub fn has_model_lengths() {
+        let registers = registers(
+            &config(MeterModel::ThreePhase, StaleFallback::Error),
+            &snapshot(0),
+        );
+
+        assert_eq!(registers.len(), 2 + 2 + 66 + 2 + 105 + 2);
+        assert_eq!(
+            registers[(METER_MODEL_ADDRESS - BASE_ADDRESS) as usize],
+            203
+        );
+        assert_eq!(&registers[registers.len() - 2..], &[0xffff, 0]);
+    }
+
+    #[tokio::test]
+    pub async fn serves_three_phase_meter() {
+        let client = meter(
+            config(MeterModel::ThreePhase, StaleFallback::Error),
+            snapshot(1),
+        )
+        .await;
+
+        assert_eq!(
+            read(&client, 40000, 4).await.unwrap(),
+            vec![0x5375, 0x6e53, 1, 66]
+        );
+        assert_eq!(
+            read(&client, 40004, 4).await.unwrap(),
+            string("hackdose", 4)
+        );
+        let model = read(&client, METER_MODEL_ADDRESS, 107).await.unwrap();
+        assert_eq!(&model[0..2], &[203, 105]);
+        // current in 0.01 A
+        assert_eq!(&model[2..7], &[570, 435, 35, 100, -2i16 as u16]);
+        // voltage in 0.1 V and frequency in 0.01 Hz
+        assert_eq!(model[7], 2300);
+        assert_eq!(&model[16..18], &[5000, -2i16 as u16]);
+        // power
+        assert_eq!(
+            &model[18..23],
+            &[-690i16 as u16, -1000i16 as u16, 80, 230, 0]
+        );
+        // exported and imported energy
+        assert_eq!(&model[38..40], &[1, 4464]);
+        assert_eq!(&model[46..48], &[188, 24910]);
+    }
+
+    #[tokio::test]
+    pub async fn serves_single_phase_meter() {
+        let client = meter(
+            config(MeterModel::SinglePhase, StaleFallback::Error),
+            snapshot(1),
+        )
+        .await;
+
+        let model = read(&client, METER_MODEL_ADDRESS, 23).await.unwrap();
+        assert_eq!(model[0], 201);
+        assert_eq!(
+            &model[18..22],
+            &[-690i16 as u16, -690i16 as u16, 0x8000, 0x8000]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn handles_stale_values() {
+        let failing = meter(
+            config(MeterModel::ThreePhase, StaleFallback::Error),
+            snapshot(11),
+        )
+        .await;
+        // the common model stays readable for the discovery
+        assert!(read(&failing, 40000, 70).await.is_ok());
+        assert!(matches!(
+            read(&failing, METER_MODEL_ADDRESS, 2).await,
+            Err(ModbusError::Exception(SERVER_DEVICE_FAILURE))
+        ));
+
+        let zero = meter(
+            config(MeterModel::ThreePhase, StaleFallback::Zero),
+            MeterSnapshot::default(),
+        )
+        .await;
+        let model = read(&zero, METER_MODEL_ADDRESS, 23).await.unwrap();
+        assert_eq!(&model[18..22], &[0, 0, 0, 0]);
+    }
+
+    #[tokio::test]
+    pub async fn rejects_invalid_requests() {
+        let client = meter(
+            config(MeterModel::ThreePhase, StaleFallback::Error),
+            snapshot(0),
+        )
+        .await;
+
+        assert!(matches!(
+            read(&client, 0, 2).await,
+            Err(ModbusError::Exception(ILLEGAL_DATA_ADDRESS))
+        ));
+        assert!(matches!(
+            read(&client, 40170, 10).await,
+            Err(ModbusError::Exception(ILLEGAL_DATA_ADDRESS))
+        ));
+        assert!(matches!(
+            client.write_registers(40000, &[1]).await,
+            Err(ModbusError::Exception(ILLEGAL_FUNCTION))
+        ));
+    }
+
+    #[test]
+    pub fn parses_configuration() {
+        let config =
+            serde_yaml::from_str::<SunSpecConfiguration>("model: 201\non_stale: zero").unwrap();
+
+        assert_eq!(config.model, MeterModel::SinglePhase);
+        assert_eq!(config.on_stale, StaleFallback::Zero);
+        assert_eq!(config.listen.port(), 502);
+        assert!(serde_yaml::from_str::<SunSpecConfiguration>("model: 202").is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! Zero-export limiter, adjusts the power limit of an inverter to the grid power
+
+use std::time::Duration as StdDuration;
+
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+
+use crate::{
+    actors::backend::{ahoydtu::AhoyDtu, opendtu::OpenDtu, ActorBackend},
+    emulation::MeterUpdates,
+};
+
+/// The `limiter` section of the configuration
+#[derive(Deserialize, Clone, Debug)]
+pub(crate) struct LimiterConfiguration {
+    #[serde(rename = "type")]
+    pub(crate) dtu: DtuType,
+    pub(crate) address: String,
+    /// serial number of the inverter for `open_dtu`
+    #[serde(default)]
+    pub(crate) serial: String,
+    /// index of the inverter for `ahoy_dtu`
+    #[serde(default)]
+    pub(crate) inverter_id: u8,
+    #[serde(default)]
+    pub(crate) password: String,
+    /// grid power to keep, positive values draw a little from the grid
+    #[serde(default)]
+    pub(crate) target_watts: f64,
+    #[serde(default)]
+    pub(crate) min_watts: f64,
+    pub(crate) max_watts: f64,
+    /// largest increase of the limit per update, it is lowered at once
+    #[serde(default = "default_step_watts")]
+    pub(crate) step_watts: f64,
+    #[serde(default = "default_interval_seconds")]
+    pub(crate) interval_seconds: u64,
+}
+
+fn default_step_watts() -> f64 {
+    100.0
+}
+
+fn default_interval_seconds() -> u64 {
+    5
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum DtuType {
+    OpenDtu,
+    AhoyDtu,
+}
+
+/// Power limit of the inverter
+struct Limiter {
+    config: LimiterConfiguration,
+    /// limit confirmed by the inverter, `None` if unknown
+    limit: Option<f64>,
+}
+
+impl Limiter {
+    fn new(config: LimiterConfiguration) -> Self {
+        Self {
+            config,
+            limit: None,
+        }
+    }
+
+    /// The limit for the grid power and the output of the inverter
+    ///
+    /// Without a grid reading the inverter falls back to its minimum.
+    fn next(&self, grid: Option<f64>, production: Option<f64>) -> f64 {
+        let (min, max) = (
+            self.config.min_watts,
+            self.config.max_watts.max(self.config.min_watts),
+        );
+        let Some(grid) = grid else {
+            return min;
+        };
+        // the inverter may produce less than its limit
+        let output = production.or(self.limit).unwrap_or(min);
+        let desired = (output + grid - self.config.target_watts).clamp(min, max);
+        match self.limit {
+            Some(limit) => desired.min(limit.max(min) + self.config.step_watts),
+            None => desired.min(min + self.config.step_watts),
+        }
+    }
+
+    fn needs_update(&self, limit: f64) -> bool {
+        self.limit
+            .is_none_or(|current| (current - limit).abs() >= 1.0)
+    }
+
+    /// Read the inverter and send a new limit if necessary
+    async fn update(&mut self, backend: &dyn ActorBackend, grid: Option<f64>) {
+        let production = match backend.power().await {
+            Ok(production) => production,
+            Err(e) => {
+                eprintln!("could not read inverter {}: {}", self.config.address, e);
+                None
+            }
+        };
+        let limit = self.next(grid, production);
+        if !self.needs_update(limit) {
+            return;
+        }
+        match backend.set_power(limit).await {
+            Ok(()) => self.limit = Some(limit),
+            Err(e) => {
+                eprintln!("could not limit inverter {}: {}", self.config.address, e);
+                self.limit = None;
+            }
+        }
+    }
+}
+
+fn create_backend(config: &LimiterConfiguration) -> Box<dyn ActorBackend> {
+    match config.dtu {
+        DtuType::OpenDtu => Box::new(OpenDtu::new(
+            &config.address,
+            &config.serial,
+            &config.password,
+        )),
+        DtuType::AhoyDtu => Box::new(AhoyDtu::new(
+            &config.address,
+            config.inverter_id,
+            &config.password,
+        )),
+    }
+}
+
+/// Adjust the limit of the inverter in the configured interval
+pub(crate) async fn limit_inverter(config: LimiterConfiguration, updates: MeterUpdates) {
+    let backend = create_backend(&config);
+    let interval = config.interval_seconds.max(1);
+    // readings are missing if they are older than two intervals
+    let max_age = Duration::seconds(2 * interval as i64);
+    let mut limiter = Limiter::new(config);
+    let mut ticks = tokio::time::interval(StdDuration::from_secs(interval));
+    loop {
+        ticks.tick().await;
+        let grid = {
+            let snapshot = updates.borrow();
+            if snapshot.is_stale(Utc::now(), max_age) {
+                None
+            } else {
+                snapshot.power()
+            }
+        };
+        limiter.update(backend.as_ref(), grid).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::actors::backend::mock::http_server;
+
+    fn config(address: &str) -> LimiterConfiguration {
+        serde_yaml::from_str(&format!(
+            "type: open_dtu\naddress: {}\nserial: \"116180400144\"\n\
+             target_watts: 20\nmin_watts: 50\nmax_watts: 800\nstep_watts: 200",
+            address
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    pub fn follows_grid_power() {
+        let mut limiter = Limiter::new(config("dtu"));
+
+        // starts from the minimum and rises in steps
+        assert_eq!(limiter.next(Some(300.0), None), 250.0);
+        limiter.limit = Some(250.0);
+        assert_eq!(limiter.next(Some(300.0), Some(250.0)), 450.0);
+        limiter.limit = Some(450.0);
+        assert_eq!(limiter.next(Some(120.0), Some(450.0)), 550.0);
+        // feeding in lowers the limit at once
+        limiter.limit = Some(550.0);
+        assert_eq!(limiter.next(Some(-380.0), Some(550.0)), 150.0);
+        // within bounds
+        assert_eq!(limiter.next(Some(-900.0), Some(550.0)), 50.0);
+        limiter.limit = Some(700.0);
+        assert_eq!(limiter.next(Some(500.0), Some(700.0)), 800.0);
+        // missing readings fall back to the minimum
+        assert_eq!(limiter.next(None, Some(700.0)), 50.0);
+    }
+
+    #[test]
+    pub fn uses_output_below_limit() {
+        let mut limiter = Limiter::new(config("dtu"));
+        limiter.limit = Some(800.0);
+
+        // little sun, the inverter produces 300 W and 100 W are fed in
+        assert_eq!(limiter.next(Some(-100.0), Some(300.0)), 180.0);
+    }
+
+    #[tokio::test]
+    pub async fn sends_changed_limits() {
+        let (address, requests) = http_server(vec![
+            ("/api/limit/config", r#"{"type": "success"}"#),
+            (
+                "/api/livedata/status",
+                r#"{"inverters": [{"serial": "116180400144", "producing": true,
+                    "AC": {"0": {"Power": {"v": 400}}}}]}"#,
+            ),
+        ])
+        .await;
+        let config = config(&address);
+        let backend = create_backend(&config);
+        let mut limiter = Limiter::new(config);
+        limiter.limit = Some(400.0);
+
+        limiter.update(backend.as_ref(), Some(-80.0)).await;
+        assert_eq!(limiter.limit, Some(300.0));
+        limiter.limit = Some(380.0);
+        limiter.update(backend.as_ref(), Some(0.0)).await;
+        assert_eq!(limiter.limit, Some(380.0));
+
+        let requests = requests.lock().unwrap();
+        let limits = requests
+            .iter()
+            .filter(|request| request.path == "/api/limit/config")
+            .map(|request| request.body.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(limits.len(), 1);
+        assert!(limits[0].contains("%22limit_value%22%3A300"));
+    }
+}
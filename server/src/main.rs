@@ -1,40 +1,154 @@
+use business::filter::FilterStage;
 use business::handle_power_events;
+use chrono_tz::Tz;
 use clap::Parser;
 use hackdose_sml_parser::application::domain::AnyValue;
 use hackdose_sml_parser::application::obis::Obis;
-use hackdose_sml_parser::message_stream::sml_message_stream;
-use serde::Deserialize;
-use smart_meter::uart_ir_sensor_data_stream;
+use serde::{de, Deserialize, Deserializer};
+use smart_meter::source::{Input, InputError};
+use smart_meter::supervision::{meter_message_stream, Supervision};
 use std::sync::Arc;
 use std::{collections::HashMap, path::PathBuf};
 use tokio::fs::File;
 use tokio::io::BufReader;
 
-use actors::control_actors;
+use actors::backend::{modbus::ModbusMap, ActorType};
+use actors::calendar::TimeWindow;
+use actors::controller::ControllerConfiguration;
+use actors::jobs::Job;
+use actors::{control_actors, ActorQuotas};
+use emulation::{
+    speedwire::{emit_speedwire, SpeedwireConfiguration},
+    sunspec::{serve_sunspec_meter, SunSpecConfiguration},
+    MeterSnapshot,
+};
 use gpio_cdev::{Chip, LineRequestFlags};
+use limiter::{limit_inverter, LimiterConfiguration};
+use modbus::sensor::{poll_sensors, SensorConfiguration, SensorReadings};
 use rest::serve_rest_endpoint;
 use tokio::io::AsyncReadExt;
 
 mod actors;
 mod business;
+mod emulation;
+mod limiter;
+mod modbus;
 mod rest;
 mod smart_meter;
 
 #[derive(Deserialize, Clone)]
-struct ActorConfiguration {
+pub(crate) struct ActorConfiguration {
+    #[serde(rename = "type", default)]
+    actor_type: ActorType,
     address: String,
-    disable_threshold: isize,
-    enable_threshold: isize,
+    /// relay of devices with several outputs
+    #[serde(default)]
+    channel: u8,
+    /// serial number of the inverter for `open_dtu`
+    serial: Option<String>,
+    /// password of the device's web interface
+    password: Option<String>,
+    /// register map of `modbus` devices
+    #[serde(default)]
+    modbus: ModbusMap,
+    /// actors of a group share a controller, see `groups`
+    group: Option<String>,
+    /// loads with higher priority are switched on first
+    #[serde(default)]
+    priority: i32,
+    /// power the load draws when switched on, the maximum setpoint of adjustable loads
+    nominal_watts: u32,
+    /// smallest setpoint of adjustable loads, below it they are switched off
+    min_watts: Option<u32>,
+    /// how fast the setpoint of adjustable loads may change
+    #[serde(default, deserialize_with = "change_rate")]
+    max_change_watts_per_second: Option<f64>,
+    /// default for `min_on_minutes` and `min_off_minutes`
+    #[serde(default)]
     duration_minutes: usize,
+    min_on_minutes: Option<usize>,
+    min_off_minutes: Option<usize>,
+    /// longest time the load may run without interruption
+    max_runtime_minutes: Option<usize>,
+    max_daily_minutes: Option<usize>,
+    /// the load is switched on in time before midnight to reach this runtime
+    min_daily_minutes: Option<usize>,
+    /// periods in which the load may run, any time if empty
+    #[serde(default)]
+    windows: Vec<TimeWindow>,
+    /// runtime needed before a deadline, e.g. for a dishwasher
+    #[serde(default)]
+    jobs: Vec<Job>,
+}
+
+impl ActorConfiguration {
+    fn group(&self) -> &str {
+        self.group.as_deref().unwrap_or("default")
+    }
+
+    /// Whether the actor receives a power setpoint instead of being switched
+    fn is_adjustable(&self) -> bool {
+        match self.actor_type {
+            ActorType::Modbus => self.modbus.setpoint.is_some(),
+            actor_type => actor_type.is_adjustable(),
+        }
+    }
+
+    /// Largest power the actor takes up, inverters take up the surplus by lowering their
+    /// power limit down to `min_watts`
+    fn load_watts(&self) -> u32 {
+        if self.actor_type.is_inverter() {
+            self.nominal_watts
+                .saturating_sub(self.min_watts.unwrap_or(0))
+        } else {
+            self.nominal_watts
+        }
+    }
+
+    /// Whether an adjustable actor has to be switched on and off besides its setpoint
+    fn is_switched(&self) -> bool {
+        self.actor_type == ActorType::Modbus && self.modbus.switch.is_some()
+    }
+}
+
+/// Rate of change of a setpoint, finite and not negative
+fn change_rate<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    match Option::<f64>::deserialize(deserializer)? {
+        Some(rate) if !(rate.is_finite() && rate >= 0.0) => Err(de::Error::custom(
+            "max_change_watts_per_second must be a number of at least 0",
+        )),
+        rate => Ok(rate),
+    }
 }
 
 #[derive(Deserialize, Clone)]
 pub(crate) struct Configuration {
     actors: Vec<ActorConfiguration>,
+    /// timezone of the time windows and days of the actors, e.g. `Europe/Berlin`,
+    /// defaults to the timezone of the system
+    timezone: Option<Tz>,
+    /// controller of each group of actors, groups not listed use `greedy`
+    #[serde(default)]
+    groups: HashMap<String, ControllerConfiguration>,
+    /// applied in order to the power readings before the actors are controlled
+    #[serde(default)]
+    filters: Vec<FilterStage>,
+    /// values polled from Modbus devices, e.g. the production of inverters
+    #[serde(default)]
+    sensors: Vec<SensorConfiguration>,
+    /// grid meter on Modbus TCP for inverters and batteries
+    sunspec: Option<SunSpecConfiguration>,
+    /// SMA Energy Meter packets for batteries and inverters
+    speedwire: Option<SpeedwireConfiguration>,
+    /// keeps the grid power near zero by limiting an inverter
+    limiter: Option<LimiterConfiguration>,
     log_location: PathBuf,
-    gpio_location: String,
-    ttys_location: String,
-    gpio_power_pin: u32,
+    input: Option<Input>,
+    gpio_location: Option<String>,
+    ttys_location: Option<String>,
+    gpio_power_pin: Option<u32>,
+    #[serde(default)]
+    supervision: Supervision,
 }
 
 #[derive(Parser, Debug)]
@@ -45,7 +159,7 @@ struct Args {
 }
 
 #[tokio::main(worker_threads = 2)]
-async fn main() {
+async fn main() -> Result<(), InputError> {
     let args = Args::parse();
 
     let config = File::open(args.config).await.unwrap();
@@ -56,27 +170,58 @@ async fn main() {
         .unwrap();
     let config = serde_yaml::from_str::<Configuration>(&config_file).unwrap();
 
-    let mut chip = Chip::new(&config.gpio_location).unwrap();
-    let output = chip.get_line(config.gpio_power_pin).unwrap();
-    let output_handle = output
-        .request(LineRequestFlags::OUTPUT, 0, "mirror-gpio")
-        .unwrap();
+    let output_handle = match (&config.gpio_location, config.gpio_power_pin) {
+        (Some(gpio_location), Some(gpio_power_pin)) => {
+            let mut chip = Chip::new(gpio_location).unwrap();
+            let output = chip.get_line(gpio_power_pin).unwrap();
+            let output_handle = output
+                .request(LineRequestFlags::OUTPUT, 0, "mirror-gpio")
+                .unwrap();
 
-    output_handle.set_value(1).unwrap();
+            output_handle.set_value(1).unwrap();
+            Some(output_handle)
+        }
+        _ => None,
+    };
 
-    let stream = uart_ir_sensor_data_stream(&config);
-    let power_events = sml_message_stream(stream);
+    let input = config.meter_input()?;
+    let power_events =
+        meter_message_stream(input, config.supervision.clone(), output_handle).await?;
 
     let (mut tx, mut rx) = tokio::sync::mpsc::channel::<i32>(100);
     let mutex = Arc::new(tokio::sync::Mutex::new(HashMap::<Obis, AnyValue>::new()));
 
     let mutex1 = mutex.clone();
     let mutex2 = mutex.clone();
+    let quotas = ActorQuotas::default();
+    let quotas2 = quotas.clone();
     let config2 = config.clone();
     let config3 = config.clone();
+    let config4 = config.clone();
+    let sensors = SensorReadings::default();
+    let sensors2 = sensors.clone();
+    let (meter_tx, meter_rx) = tokio::sync::watch::channel(MeterSnapshot::default());
+    if let Some(sunspec) = config.sunspec.clone() {
+        tokio::task::spawn(serve_sunspec_meter(sunspec, meter_rx.clone()));
+    }
+    if let Some(speedwire) = config.speedwire.clone() {
+        tokio::task::spawn(emit_speedwire(speedwire, meter_rx.clone()));
+    }
+    if let Some(limiter) = config.limiter.clone() {
+        tokio::task::spawn(limit_inverter(limiter, meter_rx.clone()));
+    }
     tokio::task::spawn(async move {
-        handle_power_events(&mut tx, mutex1.clone(), &config.clone(), power_events).await
+        handle_power_events(
+            &mut tx,
+            mutex1.clone(),
+            &meter_tx,
+            &config.clone(),
+            power_events,
+        )
+        .await
     });
-    tokio::task::spawn(async move { control_actors(&mut rx, &config2.clone()).await });
-    serve_rest_endpoint(mutex2.clone(), &config3.clone()).await;
+    tokio::task::spawn(async move { control_actors(&mut rx, &config2.clone(), quotas).await });
+    tokio::task::spawn(async move { poll_sensors(&config4.sensors, sensors).await });
+    serve_rest_endpoint(mutex2.clone(), quotas2, sensors2, &config3.clone()).await;
+    Ok(())
 }
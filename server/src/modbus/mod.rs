@@ -0,0 +1,366 @@
+//! Modbus TCP client for inverters, batteries and wallboxes
+
+use std::{
+    fmt, io,
+    sync::atomic::{AtomicU16, Ordering},
+    time::Duration,
+};
+
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::Mutex,
+};
+
+pub(crate) mod sensor;
+pub(crate) mod server;
+#[cfg(test)]
+pub(crate) mod simulator;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const READ_COILS: u8 = 0x01;
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+const WRITE_SINGLE_COIL: u8 = 0x05;
+const WRITE_SINGLE_REGISTER: u8 = 0x06;
+const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub(crate) enum ModbusError {
+    Io(io::Error),
+    Timeout,
+    /// exception code sent by the device, e.g. 2 for an illegal address
+    Exception(u8),
+    InvalidResponse,
+}
+
+impl fmt::Display for ModbusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModbusError::Io(e) => write!(f, "{}", e),
+            ModbusError::Timeout => write!(f, "no answer from device"),
+            ModbusError::Exception(code) => write!(f, "device answered with exception {}", code),
+            ModbusError::InvalidResponse => write!(f, "unexpected answer from device"),
+        }
+    }
+}
+
+impl From<io::Error> for ModbusError {
+    fn from(e: io::Error) -> Self {
+        ModbusError::Io(e)
+    }
+}
+
+/// Holding registers are writable, input registers read only
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RegisterKind {
+    #[default]
+    Holding,
+    Input,
+}
+
+/// Encoding of a value, values of 32 bits span two registers with the high word first
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum DataType {
+    #[default]
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+}
+
+impl DataType {
+    fn registers(&self) -> u16 {
+        match self {
+            DataType::U16 | DataType::I16 => 1,
+            DataType::U32 | DataType::I32 | DataType::F32 => 2,
+        }
+    }
+
+    fn decode(&self, words: &[u16]) -> f64 {
+        let long = || (words[0] as u32) << 16 | words[1] as u32;
+        match self {
+            DataType::U16 => words[0] as f64,
+            DataType::I16 => words[0] as i16 as f64,
+            DataType::U32 => long() as f64,
+            DataType::I32 => long() as i32 as f64,
+            DataType::F32 => f32::from_bits(long()) as f64,
+        }
+    }
+
+    fn encode(&self, value: f64) -> Vec<u16> {
+        let long = |bits: u32| vec![(bits >> 16) as u16, bits as u16];
+        match self {
+            DataType::U16 => vec![value.round() as u16],
+            DataType::I16 => vec![value.round() as i16 as u16],
+            DataType::U32 => long(value.round() as u32),
+            DataType::I32 => long(value.round() as i32 as u32),
+            DataType::F32 => long((value as f32).to_bits()),
+        }
+    }
+}
+
+/// A value in the register map of a device
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct Register {
+    /// zero-based address of the (first) register
+    pub(crate) address: u16,
+    #[serde(default)]
+    pub(crate) kind: RegisterKind,
+    #[serde(default)]
+    pub(crate) data_type: DataType,
+    /// factor from the register value to watts, e.g. `0.1`
+    #[serde(default = "default_scale")]
+    pub(crate) scale: f64,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+/// Connection to a Modbus TCP device, reopened after errors
+pub(crate) struct Client {
+    address: String,
+    unit_id: u8,
+    connection: Mutex<Option<TcpStream>>,
+    transaction: AtomicU16,
+}
+
+impl Client {
+    pub(crate) fn new(address: &str, unit_id: u8) -> Self {
+        Self {
+            address: address.to_string(),
+            unit_id,
+            connection: Mutex::new(None),
+            transaction: AtomicU16::new(0),
+        }
+    }
+
+    pub(crate) async fn read_coil(&self, address: u16) -> Result<bool, ModbusError> {
+        let response = self
+            .request(
+                READ_COILS,
+                &[address.to_be_bytes(), 1u16.to_be_bytes()].concat(),
+            )
+            .await?;
+        match response.as_slice() {
+            [1, bits] => Ok(bits & 1 == 1),
+            _ => Err(ModbusError::InvalidResponse),
+        }
+    }
+
+    pub(crate) async fn write_coil(&self, address: u16, on: bool) -> Result<(), ModbusError> {
+        let value: u16 = if on { 0xff00 } else { 0x0000 };
+        let request = [address.to_be_bytes(), value.to_be_bytes()].concat();
+        let response = self.request(WRITE_SINGLE_COIL, &request).await?;
+        if response != request {
+            return Err(ModbusError::InvalidResponse);
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn read_registers(
+        &self,
+        kind: RegisterKind,
+        address: u16,
+        count: u16,
+    ) -> Result<Vec<u16>, ModbusError> {
+        let function = match kind {
+            RegisterKind::Holding => READ_HOLDING_REGISTERS,
+            RegisterKind::Input => READ_INPUT_REGISTERS,
+        };
+        let response = self
+            .request(
+                function,
+                &[address.to_be_bytes(), count.to_be_bytes()].concat(),
+            )
+            .await?;
+        match response.split_first() {
+            Some((&length, data))
+                if length as usize == data.len() && data.len() == 2 * count as usize =>
+            {
+                Ok(data
+                    .chunks(2)
+                    .map(|word| u16::from_be_bytes([word[0], word[1]]))
+                    .collect())
+            }
+            _ => Err(ModbusError::InvalidResponse),
+        }
+    }
+
+    pub(crate) async fn write_registers(
+        &self,
+        address: u16,
+        words: &[u16],
+    ) -> Result<(), ModbusError> {
+        let (function, request, expected) = match words {
+            [word] => {
+                let request = [address.to_be_bytes(), word.to_be_bytes()].concat();
+                (WRITE_SINGLE_REGISTER, request.clone(), request)
+            }
+            _ => {
+                let count = words.len() as u16;
+                let header = [address.to_be_bytes(), count.to_be_bytes()].concat();
+                let mut request = header.clone();
+                request.push(2 * words.len() as u8);
+                request.extend(words.iter().flat_map(|word| word.to_be_bytes()));
+                (WRITE_MULTIPLE_REGISTERS, request, header)
+            }
+        };
+        let response = self.request(function, &request).await?;
+        if response != expected {
+            return Err(ModbusError::InvalidResponse);
+        }
+        Ok(())
+    }
+
+    /// Value of a register, scaled
+    pub(crate) async fn read(&self, register: &Register) -> Result<f64, ModbusError> {
+        let words = self
+            .read_registers(
+                register.kind,
+                register.address,
+                register.data_type.registers(),
+            )
+            .await?;
+        Ok(register.data_type.decode(&words) * register.scale)
+    }
+
+    /// Write a value to a holding register, scaled
+    pub(crate) async fn write(&self, register: &Register, value: f64) -> Result<(), ModbusError> {
+        let words = register.data_type.encode(value / register.scale);
+        self.write_registers(register.address, &words).await
+    }
+
+    /// Send a request and return the data of the response after the function code
+    async fn request(&self, function: u8, data: &[u8]) -> Result<Vec<u8>, ModbusError> {
+        let transaction = self.transaction.fetch_add(1, Ordering::Relaxed);
+        let mut frame = Vec::with_capacity(8 + data.len());
+        frame.extend(transaction.to_be_bytes());
+        // protocol identifier
+        frame.extend([0, 0]);
+        frame.extend((2 + data.len() as u16).to_be_bytes());
+        frame.extend([self.unit_id, function]);
+        frame.extend(data);
+
+        let mut connection = self.connection.lock().await;
+        let exchange = async {
+            if connection.is_none() {
+                *connection = Some(TcpStream::connect(&self.address).await?);
+            }
+            let stream = connection.as_mut().ok_or(ModbusError::InvalidResponse)?;
+            stream.write_all(&frame).await?;
+            let mut header = [0; 7];
+            stream.read_exact(&mut header).await?;
+            let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+            if length < 2 {
+                return Err(ModbusError::InvalidResponse);
+            }
+            let mut pdu = vec![0; length - 1];
+            stream.read_exact(&mut pdu).await?;
+            Ok((header, pdu))
+        };
+        let result = tokio::time::timeout(REQUEST_TIMEOUT, exchange)
+            .await
+            .unwrap_or(Err(ModbusError::Timeout));
+        if result.is_err() {
+            // the stream may contain the rest of a response
+            *connection = None;
+        }
+        let (header, pdu) = result?;
+        if header[0..2] != transaction.to_be_bytes() {
+            *connection = None;
+            return Err(ModbusError::InvalidResponse);
+        }
+        match pdu.split_first() {
+            Some((&code, data)) if code == function => Ok(data.to_vec()),
+            Some((&code, [exception])) if code == function | 0x80 => {
+                Err(ModbusError::Exception(*exception))
+            }
+            _ => Err(ModbusError::InvalidResponse),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::modbus::simulator::simulator;
+
+    #[test]
+    pub fn converts_data_types() {
+        for (data_type, value) in [
+            (DataType::U16, 65000.0),
+            (DataType::I16, -1500.0),
+            (DataType::U32, 4_000_000_000.0),
+            (DataType::I32, -100_000.0),
+            (DataType::F32, 1234.5),
+        ] {
+            let words = data_type.encode(value);
+            assert_eq!(words.len(), data_type.registers() as usize);
+            assert_eq!(data_type.decode(&words), value);
+        }
+        assert_eq!(DataType::I32.encode(-2.0), vec![0xffff, 0xfffe]);
+    }
+
+    #[tokio::test]
+    pub async fn reads_and_writes() {
+        let (address, device) = simulator(1).await;
+        device.lock().unwrap().input.insert(30775, 0xffff);
+        device.lock().unwrap().input.insert(30776, 0xfc18);
+        let client = Client::new(&address, 1);
+
+        client.write_coil(3, true).await.unwrap();
+        assert!(client.read_coil(3).await.unwrap());
+        assert!(!client.read_coil(4).await.unwrap());
+
+        let register = Register {
+            address: 40100,
+            kind: RegisterKind::Holding,
+            data_type: DataType::U32,
+            scale: 0.1,
+        };
+        client.write(&register, 1500.0).await.unwrap();
+        assert_eq!(device.lock().unwrap().holding[&40101], 15000);
+        assert_eq!(client.read(&register).await.unwrap(), 1500.0);
+
+        let power = Register {
+            address: 30775,
+            kind: RegisterKind::Input,
+            data_type: DataType::I32,
+            scale: 1.0,
+        };
+        assert_eq!(client.read(&power).await.unwrap(), -1000.0);
+    }
+
+    #[tokio::test]
+    pub async fn reports_exceptions() {
+        let (address, _) = simulator(1).await;
+
+        let other_unit = Client::new(&address, 2);
+        assert!(matches!(
+            other_unit.read_coil(0).await,
+            Err(ModbusError::Exception(0x0b))
+        ));
+
+        let client = Client::new(&address, 1);
+        assert!(matches!(
+            client.read_registers(RegisterKind::Input, 0, 200).await,
+            Err(ModbusError::Exception(0x03))
+        ));
+        // the connection stays usable
+        assert_eq!(
+            client
+                .read_registers(RegisterKind::Holding, 0, 2)
+                .await
+                .unwrap(),
+            vec![0, 0]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::{Client, Register};
+
+/// A value polled from a Modbus device, e.g. the production of an inverter
+#[derive(Deserialize, Clone, Debug)]
+pub(crate) struct SensorConfiguration {
+    pub(crate) name: String,
+    /// `host:port`, Modbus TCP uses port 502
+    pub(crate) address: String,
+    #[serde(default = "default_unit_id")]
+    pub(crate) unit_id: u8,
+    pub(crate) register: Register,
+    #[serde(default = "default_interval")]
+    pub(crate) interval_seconds: u64,
+}
+
+fn default_unit_id() -> u8 {
+    1
+}
+
+fn default_interval() -> u64 {
+    10
+}
+
+/// Last value of a sensor
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub(crate) struct SensorReading {
+    pub(crate) value: Option<f64>,
+    /// time of the last successful reading
+    pub(crate) time: Option<DateTime<Utc>>,
+    /// error of the last attempt, if it failed
+    pub(crate) error: Option<String>,
+}
+
+/// Readings by name of the sensor
+pub(crate) type SensorReadings = Arc<Mutex<BTreeMap<String, SensorReading>>>;
+
+/// Read all sensors in their intervals
+pub(crate) async fn poll_sensors(sensors: &[SensorConfiguration], readings: SensorReadings) {
+    join_all(sensors.iter().map(|sensor| {
+        let readings = readings.clone();
+        async move {
+            let client = Client::new(&sensor.address, sensor.unit_id);
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(sensor.interval_seconds.max(1)));
+            loop {
+                interval.tick().await;
+                read_sensor(&client, sensor, &readings, Utc::now()).await;
+            }
+        }
+    }))
+    .await;
+}
+
+async fn read_sensor(
+    client: &Client,
+    sensor: &SensorConfiguration,
+    readings: &SensorReadings,
+    now: DateTime<Utc>,
+) {
+    let result = client.read(&sensor.register).await;
+    let mut readings = readings.lock().await;
+    let reading = readings.entry(sensor.name.clone()).or_default();
+    match result {
+        Ok(value) => {
+            *reading = SensorReading {
+                value: Some(value),
+                time: Some(now),
+                error: None,
+            }
+        }
+        Err(e) => {
+            eprintln!("could not read sensor {}: {}", sensor.name, e);
+            // the last value is kept along with its time
+            reading.error = Some(e.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::modbus::simulator::simulator;
+
+    #[tokio::test]
+    pub async fn reads_sensor() {
+        let (address, device) = simulator(3).await;
+        device.lock().unwrap().input.insert(30775, 4200);
+        let sensor = serde_yaml::from_str::<SensorConfiguration>(&format!(
+            "name: pv\naddress: {}\nunit_id: 3\n\
+             register: {{address: 30775, kind: input, scale: 0.5}}",
+            address
+        ))
+        .unwrap();
+        let readings = SensorReadings::default();
+        let now = Utc::now();
+
+        read_sensor(&Client::new(&address, 3), &sensor, &readings, now).await;
+        assert_eq!(
+            readings.lock().await["pv"],
+            SensorReading {
+                value: Some(2100.0),
+                time: Some(now),
+                error: None
+            }
+        );
+
+        let wrong_unit = Client::new(&address, 4);
+        read_sensor(&wrong_unit, &sensor, &readings, Utc::now()).await;
+        let reading = readings.lock().await["pv"].clone();
+        assert_eq!((reading.value, reading.time), (Some(2100.0), Some(now)));
+        assert!(reading.error.is_some());
+    }
+}
@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Exception codes of responses
+pub(crate) const ILLEGAL_FUNCTION: u8 = 0x01;
+pub(crate) const ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+pub(crate) const ILLEGAL_DATA_VALUE: u8 = 0x03;
+pub(crate) const SERVER_DEVICE_FAILURE: u8 = 0x04;
+pub(crate) const GATEWAY_TARGET_FAILED: u8 = 0x0b;
+
+/// Answers a request, gets the unit id and the PDU (function code and data)
+///
+/// Returns the data of the response after the function code or an exception code.
+pub(crate) trait Handler: Send + Sync + 'static {
+    fn handle(&self, unit_id: u8, pdu: &[u8]) -> Result<Vec<u8>, u8>;
+}
+
+/// Serve Modbus TCP requests of all clients connecting to `listener`
+pub(crate) async fn serve(listener: TcpListener, handler: impl Handler) {
+    let handler = Arc::new(handler);
+    while let Ok((socket, _)) = listener.accept().await {
+        let handler = handler.clone();
+        tokio::spawn(async move { serve_client(socket, handler.as_ref()).await });
+    }
+}
+
+async fn serve_client(mut socket: TcpStream, handler: &impl Handler) {
+    let mut header = [0u8; 7];
+    while socket.read_exact(&mut header).await.is_ok() {
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+        let mut pdu = vec![0; length.saturating_sub(1)];
+        if socket.read_exact(&mut pdu).await.is_err() || pdu.is_empty() {
+            break;
+        }
+        let response = match handler.handle(header[6], &pdu) {
+            Ok(data) => [vec![pdu[0]], data].concat(),
+            Err(exception) => vec![pdu[0] | 0x80, exception],
+        };
+        let mut frame = header[0..4].to_vec();
+        frame.extend((response.len() as u16 + 1).to_be_bytes());
+        frame.push(header[6]);
+        frame.extend(response);
+        if socket.write_all(&frame).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Start address and count of a read request
+pub(crate) fn read_request(pdu: &[u8]) -> Result<(u16, u16), u8> {
+    match pdu {
+        [_, a, b, c, d] => {
+            let count = u16::from_be_bytes([*c, *d]);
+            if count == 0 || count > 125 {
+                return Err(ILLEGAL_DATA_VALUE);
+            }
+            Ok((u16::from_be_bytes([*a, *b]), count))
+        }
+        _ => Err(ILLEGAL_DATA_VALUE),
+    }
+}
+
+/// Response data to a register read
+pub(crate) fn registers_response(words: &[u16]) -> Vec<u8> {
+    let mut data = vec![2 * words.len() as u8];
+    data.extend(words.iter().flat_map(|word| word.to_be_bytes()));
+    data
+}
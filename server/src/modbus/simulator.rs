@@ -0,0 +1,99 @@
+//! Modbus TCP device for tests, unset coils and registers are 0
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::net::TcpListener;
+
+use super::server::{
+    read_request, registers_response, serve, Handler, GATEWAY_TARGET_FAILED, ILLEGAL_DATA_VALUE,
+    ILLEGAL_FUNCTION,
+};
+
+#[derive(Default, Debug)]
+pub(crate) struct Device {
+    pub(crate) coils: HashMap<u16, bool>,
+    pub(crate) holding: HashMap<u16, u16>,
+    pub(crate) input: HashMap<u16, u16>,
+}
+
+impl Device {
+    fn handle(&mut self, pdu: &[u8]) -> Result<Vec<u8>, u8> {
+        let word = |i: usize| {
+            pdu.get(i..i + 2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                .ok_or(ILLEGAL_DATA_VALUE)
+        };
+        match pdu[0] {
+            0x01 => {
+                let (address, count) = read_request(pdu)?;
+                let mut bytes = vec![0u8; (count as usize).div_ceil(8)];
+                for i in 0..count {
+                    if self.coils.get(&(address + i)) == Some(&true) {
+                        bytes[i as usize / 8] |= 1 << (i % 8);
+                    }
+                }
+                Ok([vec![bytes.len() as u8], bytes].concat())
+            }
+            function @ (0x03 | 0x04) => {
+                let (address, count) = read_request(pdu)?;
+                let registers = if function == 0x03 {
+                    &self.holding
+                } else {
+                    &self.input
+                };
+                let words = (0..count)
+                    .map(|i| registers.get(&(address + i)).copied().unwrap_or(0))
+                    .collect::<Vec<_>>();
+                Ok(registers_response(&words))
+            }
+            0x05 => {
+                self.coils.insert(word(1)?, word(3)? == 0xff00);
+                Ok(pdu[1..5].to_vec())
+            }
+            0x06 => {
+                self.holding.insert(word(1)?, word(3)?);
+                Ok(pdu[1..5].to_vec())
+            }
+            0x10 => {
+                let (address, count) = (word(1)?, word(3)?);
+                for i in 0..count {
+                    self.holding.insert(address + i, word(6 + 2 * i as usize)?);
+                }
+                Ok(pdu[1..5].to_vec())
+            }
+            _ => Err(ILLEGAL_FUNCTION),
+        }
+    }
+}
+
+struct Simulator {
+    unit_id: u8,
+    device: Arc<Mutex<Device>>,
+}
+
+impl Handler for Simulator {
+    fn handle(&self, unit_id: u8, pdu: &[u8]) -> Result<Vec<u8>, u8> {
+        if unit_id != self.unit_id {
+            return Err(GATEWAY_TARGET_FAILED);
+        }
+        self.device.lock().unwrap().handle(pdu)
+    }
+}
+
+/// Device answering requests for `unit_id`, returns its address and state
+pub(crate) async fn simulator(unit_id: u8) -> (String, Arc<Mutex<Device>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let device = Arc::new(Mutex::new(Device::default()));
+    tokio::spawn(serve(
+        listener,
+        Simulator {
+            unit_id,
+            device: device.clone(),
+        },
+    ));
+    (address, device)
+}
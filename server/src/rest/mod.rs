@@ -1,10 +1,16 @@
 use std::{collections::HashMap, sync::Arc};
 
+use futures::future::join_all;
 use hackdose_sml_parser::application::{domain::AnyValue, obis::Obis};
+use serde::Serialize;
 use tokio::sync::Mutex;
 use warp::Filter;
 
-use crate::Configuration;
+use crate::{
+    actors::{backend::create_backend, ActorQuotas, Quota},
+    modbus::sensor::SensorReadings,
+    ActorConfiguration, Configuration,
+};
 
 use self::visualisation::render_image;
 
@@ -12,16 +18,26 @@ mod visualisation;
 
 pub(crate) async fn serve_rest_endpoint(
     mutex: Arc<Mutex<HashMap<Obis, AnyValue>>>,
+    quotas: ActorQuotas,
+    sensors: SensorReadings,
     config: &Configuration,
 ) {
     let owned_config = config.clone();
+    let actors_config = config.actors.clone();
     let energy = warp::path("energy")
         .map(move || mutex.clone())
         .and_then(return_energy);
     let image = warp::path("day")
         .and(warp::any().map(move || owned_config.clone()))
         .and_then(image);
-    warp::serve(energy.or(image))
+    let actors = warp::path("actors")
+        .and(warp::any().map(move || actors_config.clone()))
+        .and(warp::any().map(move || quotas.clone()))
+        .and_then(actor_states);
+    let sensors = warp::path("sensors")
+        .map(move || sensors.clone())
+        .and_then(sensor_readings);
+    warp::serve(energy.or(image).or(actors).or(sensors))
         .run(([0, 0, 0, 0], 8080))
         .await;
 }
@@ -32,6 +48,10 @@ async fn return_energy(
     Ok(Box::new(warp::reply::json(&*m.lock().await)))
 }
 
+async fn sensor_readings(sensors: SensorReadings) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    Ok(Box::new(warp::reply::json(&*sensors.lock().await)))
+}
+
 async fn image(config: Configuration) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
     let svg_image = render_image(&config).await;
     Ok(Box::new(warp::reply::html(format!(
@@ -39,3 +59,37 @@ async fn image(config: Configuration) -> Result<Box<dyn warp::Reply>, warp::Reje
         svg_image
     ))))
 }
+
+#[derive(Serialize)]
+struct ActorStatus {
+    address: String,
+    on: Option<bool>,
+    /// watts, if the device measures its power draw
+    power: Option<f64>,
+    error: Option<String>,
+    /// runtime and limits in minutes, once the actor was evaluated
+    quota: Option<Quota>,
+}
+
+async fn actor_states(
+    actors: Vec<ActorConfiguration>,
+    quotas: ActorQuotas,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let quotas = quotas.lock().await.clone();
+    let states = join_all(actors.iter().enumerate().map(|(i, actor)| {
+        let quota = quotas.get(i).cloned();
+        async move {
+            let backend = create_backend(actor);
+            let (on, power) = (backend.is_on().await, backend.power().await);
+            ActorStatus {
+                address: actor.address.clone(),
+                on: on.as_ref().ok().copied(),
+                power: power.as_ref().ok().copied().flatten(),
+                error: on.err().or(power.err()).map(|e| e.to_string()),
+                quota,
+            }
+        }
+    }))
+    .await;
+    Ok(Box::new(warp::reply::json(&states)))
+}
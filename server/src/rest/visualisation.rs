@@ -20,15 +20,12 @@ async fn get_data(
             .split(";")
             .map(|x| x.to_string())
             .collect::<Vec<String>>();
-        match &l[..] {
-            [date, value, ..] => {
-                let date = Local.datetime_from_str(date, "%Y-%m-%d %H:%M:%S").unwrap();
-                let watts = i32::from_str_radix(value.as_str(), 10).unwrap();
-                if (date < to_date) && (date > from_date) {
-                    buf.push((date, watts))
-                }
+        if let [date, value, ..] = &l[..] {
+            let date = Local.datetime_from_str(date, "%Y-%m-%d %H:%M:%S").unwrap();
+            let watts = value.parse::<i32>().unwrap();
+            if (date < to_date) && (date > from_date) {
+                buf.push((date, watts))
             }
-            _ => (),
         }
     }
     buf
@@ -37,7 +34,7 @@ async fn get_data(
 pub(crate) async fn render_image(config: &Configuration) -> String {
     let (from_date, to_date) = (Local::now() - Duration::days(1), Local::now());
 
-    let data = get_data(&config.log_location.to_str().unwrap(), from_date, to_date).await;
+    let data = get_data(config.log_location.to_str().unwrap(), from_date, to_date).await;
     let mut out_string = String::new();
     {
         let root = SVGBackend::with_string(&mut out_string, (1024, 768)).into_drawing_area(); // (OUT_FILE_NA).into_drawing_area();
@@ -59,7 +56,7 @@ pub(crate) async fn render_image(config: &Configuration) -> String {
             .configure_mesh()
             .x_labels(6)
             .y_labels(20)
-            .light_line_style(&WHITE)
+            .light_line_style(WHITE)
             .x_label_formatter(&|v| format!("{:?}", v))
             .y_label_formatter(&|v| format!("{:?}", v))
             .draw()
@@ -1,8 +1,13 @@
 use std::{collections::HashMap, sync::Arc};
 
-use hackdose_sml_parser::application::{
-    domain::{AnyValue, Scale, SmlMessageEnvelope, SmlMessages},
-    obis::Obis,
+use hackdose_sml_parser::{
+    application::{
+        domain::{
+            AnyValue, GetListResponseBody, Scale, SmlMessageBody, SmlMessageEnvelope, SmlMessages,
+        },
+        obis::Obis,
+    },
+    dsmr::DsmrTelegram,
 };
 use tokio::sync::Mutex;
 
@@ -11,15 +16,15 @@ pub async fn find_watts(
     mutex: Arc<Mutex<HashMap<Obis, AnyValue>>>,
 ) -> Option<i32> {
     for list in &messages.messages {
-        match list {
-            SmlMessageEnvelope::GetOpenResponse(_) => continue,
-            SmlMessageEnvelope::GetListResponse(body) => {
+        match &list.body {
+            SmlMessageBody::GetOpenResponse(_) => continue,
+            SmlMessageBody::GetListResponse(body) => {
                 let values = &body.value_list;
                 let identified = values
                     .iter()
                     .flat_map(|value| {
                         Obis::from_number(&value.object_name)
-                            .map(|x| (x, value.value.clone(), value.scaler.clone()))
+                            .map(|x| (x, value.value.clone(), value.scaler))
                     })
                     .collect::<Vec<_>>();
 
@@ -33,14 +38,35 @@ pub async fn find_watts(
                     .find(|(o, _, _)| o == &Obis::SumActiveInstantaneousPower)
                     .map(|(_, v, scaler)| v.scale(scaler.unwrap_or(0)));
 
-                if let Some(usage) = usage {
-                    if let AnyValue::Signed(value) = usage {
-                        return Some(value as i32);
-                    }
+                if let Some(AnyValue::Signed(value)) = usage {
+                    return Some(value as i32);
                 }
             }
-            SmlMessageEnvelope::GetCloseResponse => continue,
+            SmlMessageBody::GetCloseResponse(_) => continue,
+            SmlMessageBody::GetProcParameterResponse(_) => continue,
+            SmlMessageBody::AttentionResponse(_) => continue,
         }
     }
-    return None;
+    None
+}
+
+/// The readings of a DSMR telegram as a list response of the meter
+pub(crate) fn telegram_messages(telegram: DsmrTelegram) -> SmlMessages {
+    SmlMessages {
+        messages: vec![SmlMessageEnvelope {
+            transaction_id: vec![],
+            group_no: 0,
+            abort_on_error: 0,
+            body: SmlMessageBody::GetListResponse(GetListResponseBody {
+                client_id: None,
+                server_id: telegram.identification.as_bytes().to_vec(),
+                list_name: vec![],
+                act_sensor_time: None,
+                value_list: telegram.list_entries(),
+                list_signature: None,
+                act_gateway_time: None,
+            }),
+            crc: 0,
+        }],
+    }
 }
@@ -1,12 +1,29 @@
-use tokio::io::AsyncRead;
-use tokio_serial::SerialStream;
-
 use crate::Configuration;
 
+use self::source::{Input, InputError, InputSource};
+
 pub(crate) mod body;
+pub(crate) mod source;
+pub(crate) mod supervision;
 
-pub(crate) fn uart_ir_sensor_data_stream(config: &Configuration) -> impl AsyncRead {
-    let serial = tokio_serial::new(&config.ttys_location, 9600);
-    let stream = SerialStream::open(&serial).unwrap();
-    stream
+impl Configuration {
+    /// The configured input, falling back to the SML IR reader on `ttys_location` of older
+    /// configurations
+    pub(crate) fn meter_input(&self) -> Result<Input, InputError> {
+        self.input
+            .clone()
+            .or_else(|| {
+                self.ttys_location.as_ref().map(|path| Input {
+                    source: InputSource::Serial {
+                        path: path.clone(),
+                        baud_rate: 9600,
+                        parity: Default::default(),
+                        data_bits: 8,
+                        stop_bits: 1,
+                    },
+                    protocol: Default::default(),
+                })
+            })
+            .ok_or(InputError::NotConfigured)
+    }
 }
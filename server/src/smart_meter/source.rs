@@ -0,0 +1,394 @@
+use std::{fmt, io, path::PathBuf};
+
+use hackdose_sml_parser::capture::{replay_capture, ReplaySpeed};
+use serde::{de, Deserialize, Deserializer};
+use tokio::{fs::File, io::AsyncRead, net::TcpStream};
+use tokio_serial::{DataBits, Parity, SerialStream, StopBits};
+
+/// The `input` section of the configuration
+#[derive(Deserialize, Clone, Debug)]
+pub(crate) struct Input {
+    #[serde(flatten)]
+    pub(crate) source: InputSource,
+    #[serde(default)]
+    pub(crate) protocol: Protocol,
+}
+
+/// What the meter sends
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Protocol {
+    /// Smart Message Language, e.g. German meters
+    #[default]
+    Sml,
+    /// P1 telegrams of Dutch, Belgian and Luxembourgian meters
+    Dsmr,
+}
+
+/// Where the raw bytes of the meter come from
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum InputSource {
+    /// IR reader attached to a serial port or USB
+    Serial {
+        path: String,
+        #[serde(default = "default_baud_rate")]
+        baud_rate: u32,
+        #[serde(default)]
+        parity: SerialParity,
+        #[serde(default = "default_data_bits")]
+        data_bits: u8,
+        #[serde(default = "default_stop_bits")]
+        stop_bits: u8,
+    },
+    /// IR reader on the network, e.g. Tasmota or ser2net forwarding the serial port
+    Tcp {
+        address: String,
+    },
+    /// raw bytes as read from the meter
+    File {
+        path: PathBuf,
+    },
+    /// capture recorded by `hackdose_sml_parser::capture`
+    Capture {
+        path: PathBuf,
+        #[serde(default)]
+        speed: CaptureSpeed,
+    },
+    Stdin,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SerialParity {
+    #[default]
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CaptureSpeed {
+    /// keep the timing of the recording
+    #[default]
+    Original,
+    /// as fast as the data is consumed
+    Unthrottled,
+    /// faster by the given factor
+    Accelerated(#[serde(deserialize_with = "speed_factor")] f64),
+}
+
+/// Factor of an accelerated replay, finite and positive
+fn speed_factor<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let factor = f64::deserialize(deserializer)?;
+    match ReplaySpeed::accelerated(factor) {
+        Some(_) => Ok(factor),
+        None => Err(de::Error::custom(
+            "the speed factor must be a positive number",
+        )),
+    }
+}
+
+fn default_baud_rate() -> u32 {
+    9600
+}
+
+fn default_data_bits() -> u8 {
+    8
+}
+
+fn default_stop_bits() -> u8 {
+    1
+}
+
+pub(crate) type InputStream = Box<dyn AsyncRead + Unpin + Send>;
+
+impl InputSource {
+    pub(crate) async fn open(&self) -> io::Result<InputStream> {
+        match self {
+            InputSource::Serial {
+                path,
+                baud_rate,
+                parity,
+                data_bits,
+                stop_bits,
+            } => {
+                let serial = tokio_serial::new(path, *baud_rate)
+                    .parity(match parity {
+                        SerialParity::None => Parity::None,
+                        SerialParity::Odd => Parity::Odd,
+                        SerialParity::Even => Parity::Even,
+                    })
+                    .data_bits(match data_bits {
+                        5 => DataBits::Five,
+                        6 => DataBits::Six,
+                        7 => DataBits::Seven,
+                        8 => DataBits::Eight,
+                        _ => return Err(invalid_setting("data bits must be between 5 and 8")),
+                    })
+                    .stop_bits(match stop_bits {
+                        1 => StopBits::One,
+                        2 => StopBits::Two,
+                        _ => return Err(invalid_setting("stop bits must be 1 or 2")),
+                    });
+                Ok(Box::new(SerialStream::open(&serial)?))
+            }
+            InputSource::Tcp { address } => Ok(Box::new(TcpStream::connect(address).await?)),
+            InputSource::File { path } => Ok(Box::new(File::open(path).await?)),
+            InputSource::Capture { path, speed } => {
+                let speed = match speed {
+                    CaptureSpeed::Original => ReplaySpeed::Original,
+                    CaptureSpeed::Unthrottled => ReplaySpeed::Unthrottled,
+                    CaptureSpeed::Accelerated(factor) => ReplaySpeed::Accelerated(*factor),
+                };
+                let capture = File::open(path).await?;
+                Ok(Box::new(replay_capture(capture, speed)))
+            }
+            InputSource::Stdin => Ok(Box::new(tokio::io::stdin())),
+        }
+    }
+
+    /// Whether the input can be opened again after it failed, i.e. it is a device
+    pub(crate) fn is_reconnectable(&self) -> bool {
+        matches!(self, InputSource::Serial { .. } | InputSource::Tcp { .. })
+    }
+
+    /// Replace the path of a serial port by its link in `/dev/serial/by-id`
+    ///
+    /// Names like `/dev/ttyUSB0` may change when the IR reader is plugged in again, the
+    /// link keeps pointing to the same device.
+    pub(crate) async fn use_stable_device_path(&mut self) {
+        let InputSource::Serial { path, .. } = self else {
+            return;
+        };
+        if path.starts_with(SERIAL_BY_ID) {
+            return;
+        }
+        let Ok(device) = tokio::fs::canonicalize(&path).await else {
+            return;
+        };
+        let Ok(mut links) = tokio::fs::read_dir(SERIAL_BY_ID).await else {
+            return;
+        };
+        while let Ok(Some(link)) = links.next_entry().await {
+            if tokio::fs::canonicalize(link.path()).await.ok().as_ref() == Some(&device) {
+                let stable = link.path().to_string_lossy().to_string();
+                eprintln!("using {} for {}", stable, path);
+                *path = stable;
+                return;
+            }
+        }
+    }
+}
+
+const SERIAL_BY_ID: &str = "/dev/serial/by-id";
+
+/// The meter input cannot be used at startup
+///
+/// `Debug` shows the message, as `main` returns this error.
+#[non_exhaustive]
+pub(crate) enum InputError {
+    /// neither `input` nor `ttys_location` is configured
+    NotConfigured,
+    Open(InputSource, io::Error),
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::NotConfigured => {
+                write!(f, "no input configured, set either input or ttys_location")
+            }
+            InputError::Open(input, e) => write!(f, "could not open input {:?}: {}", input, e),
+        }
+    }
+}
+
+impl fmt::Debug for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+fn invalid_setting(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod test {
+    use hackdose_sml_parser::capture::CaptureWriter;
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    fn parse(yaml: &str) -> Result<InputSource, serde_yaml::Error> {
+        serde_yaml::from_str::<InputSource>(yaml)
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("hackdose-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    pub fn parses_serial_with_defaults() {
+        assert!(matches!(
+            parse("type: serial\npath: /dev/ttyUSB0"),
+            Ok(InputSource::Serial {
+                path,
+                baud_rate: 9600,
+                parity: SerialParity::None,
+                data_bits: 8,
+                stop_bits: 1,
+            }) if path == "/dev/ttyUSB0"
+        ));
+        assert!(matches!(
+            parse("type: serial\npath: /dev/ttyUSB0\nbaud_rate: 300\nparity: even\ndata_bits: 7"),
+            Ok(InputSource::Serial {
+                baud_rate: 300,
+                parity: SerialParity::Even,
+                data_bits: 7,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    pub fn parses_other_sources() {
+        assert!(matches!(
+            parse("type: tcp\naddress: 192.168.1.20:8888"),
+            Ok(InputSource::Tcp { address }) if address == "192.168.1.20:8888"
+        ));
+        assert!(matches!(
+            parse("type: file\npath: meter.bin"),
+            Ok(InputSource::File { path }) if path.to_str() == Some("meter.bin")
+        ));
+        assert!(matches!(
+            parse("type: capture\npath: meter.cap"),
+            Ok(InputSource::Capture {
+                speed: CaptureSpeed::Original,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse("type: capture\npath: meter.cap\nspeed: unthrottled"),
+            Ok(InputSource::Capture {
+                speed: CaptureSpeed::Unthrottled,
+                ..
+            })
+        ));
+        assert!(matches!(parse("type: stdin"), Ok(InputSource::Stdin)));
+        assert!(parse("type: usb").is_err());
+        assert!(parse("type: tcp").is_err());
+    }
+
+    #[test]
+    pub fn parses_protocols() {
+        let input = |yaml: &str| serde_yaml::from_str::<Input>(yaml);
+
+        assert!(matches!(
+            input("type: serial\npath: /dev/ttyUSB0"),
+            Ok(Input {
+                source: InputSource::Serial { .. },
+                protocol: Protocol::Sml,
+            })
+        ));
+        assert!(matches!(
+            input("type: serial\npath: /dev/ttyUSB0\nbaud_rate: 115200\nprotocol: dsmr"),
+            Ok(Input {
+                source: InputSource::Serial {
+                    baud_rate: 115200,
+                    ..
+                },
+                protocol: Protocol::Dsmr,
+            })
+        ));
+        assert!(input("type: stdin\nprotocol: d0").is_err());
+    }
+
+    #[test]
+    pub fn rejects_invalid_speed_factors() {
+        assert!(matches!(
+            parse("type: capture\npath: meter.cap\nspeed:\n  accelerated: 10"),
+            Ok(InputSource::Capture {
+                speed: CaptureSpeed::Accelerated(factor),
+                ..
+            }) if factor == 10.0
+        ));
+        for factor in ["0", "-2", ".nan", ".inf"] {
+            let yaml = format!(
+                "type: capture\npath: meter.cap\nspeed:\n  accelerated: {}",
+                factor
+            );
+            assert!(parse(&yaml).is_err());
+        }
+    }
+
+    #[tokio::test]
+    pub async fn opens_files() {
+        let path = temp_path("meter.bin");
+        std::fs::write(&path, [0x1b, 0x1b, 0x1b, 0x1b]).unwrap();
+
+        let mut stream = InputSource::File { path: path.clone() }
+            .open()
+            .await
+            .unwrap();
+        let mut data = vec![];
+        stream.read_to_end(&mut data).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(data, [0x1b, 0x1b, 0x1b, 0x1b]);
+    }
+
+    #[tokio::test]
+    pub async fn opens_captures() {
+        let path = temp_path("meter.cap");
+        let mut capture = vec![];
+        let mut writer = CaptureWriter::new(&mut capture).await.unwrap();
+        writer.record(&[0x01, 0x02]).await.unwrap();
+        writer.record(&[0x03]).await.unwrap();
+        std::fs::write(&path, &capture).unwrap();
+
+        let source = InputSource::Capture {
+            path: path.clone(),
+            speed: CaptureSpeed::Unthrottled,
+        };
+        let mut stream = source.open().await.unwrap();
+        let mut data = vec![];
+        stream.read_to_end(&mut data).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(data, [0x01, 0x02, 0x03]);
+    }
+
+    #[tokio::test]
+    pub async fn opens_stdin() {
+        assert!(InputSource::Stdin.open().await.is_ok());
+    }
+
+    #[tokio::test]
+    pub async fn reports_missing_files() {
+        let source = InputSource::File {
+            path: temp_path("missing.bin"),
+        };
+
+        let error = source.open().await.err().unwrap();
+
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    pub async fn rejects_invalid_serial_settings() {
+        let source = InputSource::Serial {
+            path: "/dev/null".to_string(),
+            baud_rate: 9600,
+            parity: SerialParity::None,
+            data_bits: 9,
+            stop_bits: 1,
+        };
+
+        let error = source.open().await.err().unwrap();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,398 @@
+use std::{fmt, io, pin::Pin, time::Duration};
+
+use async_trait::async_trait;
+use gpio_cdev::LineHandle;
+use hackdose_sml_parser::{
+    application::domain::SmlMessages, dsmr::dsmr_telegram_stream,
+    message_stream::sml_message_stream,
+};
+use serde::Deserialize;
+use tokio::sync::mpsc::{self, Sender};
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+
+use super::{
+    body::telegram_messages,
+    source::{Input, InputError, InputSource, InputStream, Protocol},
+};
+
+/// Recovery of serial and network inputs which stop delivering data
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub(crate) struct Supervision {
+    /// seconds without a valid frame after which the input is reopened, at least 1
+    pub(crate) silence_timeout_seconds: u64,
+    /// delay before the first attempt to reopen, doubled for each further attempt, at least 1
+    pub(crate) min_backoff_seconds: u64,
+    pub(crate) max_backoff_seconds: u64,
+    /// failed attempts after which the IR reader is power-cycled (requires `gpio_power_pin`)
+    pub(crate) power_cycle_after: u32,
+    pub(crate) power_off_seconds: u64,
+}
+
+impl Default for Supervision {
+    fn default() -> Self {
+        Self {
+            silence_timeout_seconds: 30,
+            min_backoff_seconds: 1,
+            max_backoff_seconds: 60,
+            power_cycle_after: 3,
+            power_off_seconds: 5,
+        }
+    }
+}
+
+impl Supervision {
+    fn silence_timeout(&self) -> Duration {
+        Duration::from_secs(self.silence_timeout_seconds.max(1))
+    }
+
+    fn backoff(&self, failures: u32) -> Duration {
+        let min_backoff = self.min_backoff_seconds.max(1);
+        let factor = 2u64.saturating_pow(failures.saturating_sub(1));
+        Duration::from_secs(
+            min_backoff
+                .saturating_mul(factor)
+                .min(self.max_backoff_seconds)
+                .max(min_backoff),
+        )
+    }
+
+    fn power_cycle_due(&self, failures: u32) -> bool {
+        self.power_cycle_after > 0 && failures.is_multiple_of(self.power_cycle_after)
+    }
+}
+
+/// An input which is opened again after it failed
+#[async_trait]
+pub(crate) trait Reopen: fmt::Debug + Send + 'static {
+    async fn reopen(&mut self) -> io::Result<InputStream>;
+}
+
+#[async_trait]
+impl Reopen for InputSource {
+    async fn reopen(&mut self) -> io::Result<InputStream> {
+        let stream = self.open().await?;
+        self.use_stable_device_path().await;
+        Ok(stream)
+    }
+}
+
+/// Power supply of the IR reader
+pub(crate) trait PowerSupply: Send + 'static {
+    fn set_powered(&self, on: bool) -> Result<(), String>;
+}
+
+impl PowerSupply for LineHandle {
+    fn set_powered(&self, on: bool) -> Result<(), String> {
+        self.set_value(u8::from(on)).map_err(|e| e.to_string())
+    }
+}
+
+/// Messages decoded according to the protocol of the meter
+///
+/// A DSMR telegram becomes a single list response holding its readings.
+fn message_stream(
+    stream: InputStream,
+    protocol: Protocol,
+) -> Pin<Box<dyn Stream<Item = SmlMessages> + Send>> {
+    match protocol {
+        Protocol::Sml => Box::pin(sml_message_stream(stream)),
+        Protocol::Dsmr => Box::pin(dsmr_telegram_stream(stream).map(telegram_messages)),
+    }
+}
+
+/// Messages read from the input
+///
+/// Serial and network inputs are reopened when they fail or stay silent, other inputs
+/// are read once and have to be available right away.
+pub(crate) async fn meter_message_stream(
+    input: Input,
+    supervision: Supervision,
+    power: Option<LineHandle>,
+) -> Result<ReceiverStream<SmlMessages>, InputError> {
+    let (tx, rx) = mpsc::channel::<SmlMessages>(256);
+    let Input { source, protocol } = input;
+    if source.is_reconnectable() {
+        tokio::spawn(supervise(source, protocol, supervision, power, tx));
+    } else {
+        let stream = match source.open().await {
+            Ok(stream) => stream,
+            Err(e) => return Err(InputError::Open(source, e)),
+        };
+        let mut messages = message_stream(stream, protocol);
+        tokio::spawn(async move {
+            while let Some(message) = messages.next().await {
+                if tx.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    Ok(ReceiverStream::new(rx))
+}
+
+async fn supervise(
+    mut input: impl Reopen,
+    protocol: Protocol,
+    supervision: Supervision,
+    power: Option<impl PowerSupply>,
+    tx: Sender<SmlMessages>,
+) {
+    let mut failures = 0;
+    loop {
+        match input.reopen().await {
+            Ok(stream) => {
+                let mut messages = message_stream(stream, protocol);
+                loop {
+                    match tokio::time::timeout(supervision.silence_timeout(), messages.next()).await
+                    {
+                        Ok(Some(message)) => {
+                            failures = 0;
+                            if tx.send(message).await.is_err() {
+                                return;
+                            }
+                        }
+                        Ok(None) => {
+                            eprintln!("input {:?} closed", input);
+                            break;
+                        }
+                        Err(_) => {
+                            eprintln!(
+                                "no data from input {:?} for {} seconds",
+                                input,
+                                supervision.silence_timeout().as_secs()
+                            );
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => eprintln!("could not open input {:?}: {}", input, e),
+        }
+
+        failures += 1;
+        if let Some(power) = &power {
+            if supervision.power_cycle_due(failures) {
+                power_cycle(power, Duration::from_secs(supervision.power_off_seconds)).await;
+            }
+        }
+        tokio::time::sleep(supervision.backoff(failures)).await;
+    }
+}
+
+/// Switch the IR reader off and on again
+async fn power_cycle(power: &impl PowerSupply, power_off: Duration) {
+    eprintln!("power-cycling the IR reader");
+    if let Err(e) = power.set_powered(false) {
+        eprintln!("could not switch off the IR reader: {}", e);
+        return;
+    }
+    tokio::time::sleep(power_off).await;
+    if let Err(e) = power.set_powered(true) {
+        eprintln!("could not switch on the IR reader: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::{
+        io::DuplexStream,
+        sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        time::Instant,
+    };
+
+    use hackdose_sml_parser::application::{domain::AnyValue, obis::Obis};
+
+    use super::*;
+    use crate::smart_meter::body::find_watts;
+
+    /// Telegram of a Landis+Gyr E360 on the P1 port
+    const P1_TELEGRAM: &[u8] = b"/ISk5\\2MT382-1000\r\n\r\n\
+        1-3:0.2.8(50)\r\n\
+        0-0:1.0.0(170108161107W)\r\n\
+        0-0:96.1.1(4530303336303000000000000000000040)\r\n\
+        1-0:1.8.1(002074.842*kWh)\r\n\
+        1-0:1.8.2(000881.383*kWh)\r\n\
+        1-0:2.8.1(000010.981*kWh)\r\n\
+        1-0:2.8.2(000028.031*kWh)\r\n\
+        0-0:96.14.0(0001)\r\n\
+        1-0:1.7.0(00.494*kW)\r\n\
+        1-0:2.7.0(00.000*kW)\r\n\
+        0-0:96.7.21(00004)\r\n\
+        0-0:96.7.9(00003)\r\n\
+        1-0:32.7.0(234.0*V)\r\n\
+        1-0:31.7.0(002*A)\r\n\
+        1-0:21.7.0(00.494*kW)\r\n\
+        1-0:22.7.0(00.000*kW)\r\n\
+        0-1:24.1.0(003)\r\n\
+        0-1:96.1.0(4730303339303031363532303530323136)\r\n\
+        0-1:24.2.1(170108160000W)(00041.138*m3)\r\n\
+        !38EA\r\n";
+
+    /// Input which fails to open, or opens without ever sending data
+    #[derive(Debug)]
+    struct TestInput {
+        silent: bool,
+        opened: UnboundedSender<Instant>,
+        streams: Vec<DuplexStream>,
+    }
+
+    #[async_trait]
+    impl Reopen for TestInput {
+        async fn reopen(&mut self) -> io::Result<InputStream> {
+            let _ = self.opened.send(Instant::now());
+            if !self.silent {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "unplugged"));
+            }
+            let (stream, other_end) = tokio::io::duplex(64);
+            self.streams.push(other_end);
+            Ok(Box::new(stream))
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct TestPower(Arc<Mutex<Vec<(Instant, bool)>>>);
+
+    impl PowerSupply for TestPower {
+        fn set_powered(&self, on: bool) -> Result<(), String> {
+            self.0.lock().unwrap().push((Instant::now(), on));
+            Ok(())
+        }
+    }
+
+    fn start(
+        silent: bool,
+        supervision: Supervision,
+        power: Option<TestPower>,
+    ) -> UnboundedReceiver<Instant> {
+        let (opened, rx) = unbounded_channel();
+        let input = TestInput {
+            silent,
+            opened,
+            streams: vec![],
+        };
+        let (tx, messages) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let _messages = messages;
+            supervise(input, Protocol::Sml, supervision, power, tx).await
+        });
+        rx
+    }
+
+    async fn seconds_of_opens(opened: &mut UnboundedReceiver<Instant>, count: usize) -> Vec<u64> {
+        let start = Instant::now();
+        let mut seconds = vec![];
+        for _ in 0..count {
+            let time = opened.recv().await.unwrap();
+            seconds.push((time - start).as_secs());
+        }
+        seconds
+    }
+
+    #[test]
+    pub fn clamps_durations_to_a_second() {
+        let supervision = Supervision {
+            silence_timeout_seconds: 0,
+            min_backoff_seconds: 0,
+            max_backoff_seconds: 0,
+            ..Default::default()
+        };
+
+        assert_eq!(supervision.silence_timeout(), Duration::from_secs(1));
+        assert_eq!(supervision.backoff(1), Duration::from_secs(1));
+        assert_eq!(supervision.backoff(10), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    pub async fn doubles_backoff_up_to_maximum() {
+        let supervision = Supervision {
+            min_backoff_seconds: 1,
+            max_backoff_seconds: 4,
+            ..Default::default()
+        };
+
+        let mut opened = start(false, supervision, None);
+
+        assert_eq!(seconds_of_opens(&mut opened, 6).await, [0, 1, 3, 7, 11, 15]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    pub async fn reopens_silent_inputs() {
+        let supervision = Supervision {
+            silence_timeout_seconds: 30,
+            min_backoff_seconds: 1,
+            ..Default::default()
+        };
+
+        let mut opened = start(true, supervision, None);
+
+        assert_eq!(seconds_of_opens(&mut opened, 3).await, [0, 31, 63]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    pub async fn clamps_zero_timeouts() {
+        let supervision = Supervision {
+            silence_timeout_seconds: 0,
+            min_backoff_seconds: 0,
+            ..Default::default()
+        };
+
+        let mut opened = start(true, supervision, None);
+
+        assert_eq!(seconds_of_opens(&mut opened, 3).await, [0, 2, 5]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    pub async fn power_cycles_after_failed_attempts() {
+        let supervision = Supervision {
+            min_backoff_seconds: 1,
+            max_backoff_seconds: 60,
+            power_cycle_after: 2,
+            power_off_seconds: 5,
+            ..Default::default()
+        };
+        let power = TestPower::default();
+        let start_time = Instant::now();
+
+        let mut opened = start(false, supervision, Some(power.clone()));
+
+        assert_eq!(seconds_of_opens(&mut opened, 5).await, [0, 1, 8, 12, 25]);
+        let switched = power
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(time, on)| ((*time - start_time).as_secs(), *on))
+            .collect::<Vec<_>>();
+        assert_eq!(switched, [(1, false), (6, true), (12, false), (17, true)]);
+    }
+
+    #[tokio::test]
+    pub async fn reads_power_from_dsmr_telegrams() {
+        let path = std::env::temp_dir().join(format!("hackdose-{}-p1.txt", std::process::id()));
+        std::fs::write(&path, P1_TELEGRAM).unwrap();
+        let input = Input {
+            source: InputSource::File { path: path.clone() },
+            protocol: Protocol::Dsmr,
+        };
+
+        let mut messages = meter_message_stream(input, Supervision::default(), None)
+            .await
+            .unwrap();
+        let message = messages.next().await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let values = Default::default();
+        assert_eq!(find_watts(&message, Arc::clone(&values)).await, Some(494));
+        assert_eq!(
+            values
+                .lock()
+                .await
+                .get(&Obis::SumActiveInstantaneousPowerPhaseL1),
+            Some(&AnyValue::Signed(494))
+        );
+        assert!(messages.next().await.is_none());
+    }
+}
@@ -0,0 +1,355 @@
+//! Python bindings of the hackdose sml parser
+//!
+//! Messages are converted into dicts. List entries carry their OBIS number and name
+//! as well as the value after applying the scaler and the unit symbol.
+//!
+//! Build and install into the current virtualenv with `maturin develop`.
+
+use hackdose_sml_parser::{
+    application::{
+        domain::{AnyValue, SmlListEntry, SmlMessageBody, SmlMessages, SmlProcParValue, SmlTree},
+        obis::{format_object_name, Obis},
+        parser::{self, ParseError},
+        units::unit_symbol,
+    },
+    d0::parser::parse_object_name,
+    transport::SMLMessageBuilder,
+};
+use pyo3::{
+    exceptions::PyValueError,
+    prelude::*,
+    types::{PyBytes, PyDict, PyList},
+};
+
+/// Parse the body of an SML message (omitting header and footer) into a list of messages
+#[pyfunction]
+fn parse_body<'py>(py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyList>> {
+    let messages = parser::parse_body(data).map_err(parse_error)?;
+    messages_to_py(py, &messages)
+}
+
+/// Parse a whole SML message including header and footer into a list of messages
+#[pyfunction]
+fn parse_message<'py>(py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyList>> {
+    let messages = parser::parse_message(data).map_err(parse_error)?;
+    messages_to_py(py, &messages)
+}
+
+/// Look up an OBIS number given as `1-0:1.8.0*255`, `1.8.0` or six bytes
+///
+/// Returns `None` for unknown numbers.
+#[pyfunction]
+fn lookup_obis<'py>(
+    py: Python<'py>,
+    object_name: ObjectName,
+) -> PyResult<Option<Bound<'py, PyDict>>> {
+    let number = match object_name {
+        ObjectName::Text(text) => parse_object_name(&text).map_err(parse_error)?,
+        ObjectName::Bytes(bytes) => bytes,
+    };
+    let Some(obis) = Obis::from_number(&number) else {
+        return Ok(None);
+    };
+    let dict = PyDict::new(py);
+    dict.set_item("obis", format_object_name(&number))?;
+    dict.set_item("name", obis.name())?;
+    dict.set_item("unit", obis.unit())?;
+    dict.set_item("description", obis.description())?;
+    Ok(Some(dict))
+}
+
+#[derive(FromPyObject)]
+enum ObjectName {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+/// Decoder for a raw byte stream, e.g. read from a serial port or a capture
+///
+/// ```python
+/// stream = MessageStream()
+/// for chunk in iter(lambda: port.read(512), b""):
+///     for message in stream.feed(chunk):
+///         ...
+/// ```
+#[pyclass]
+struct MessageStream {
+    builder: SMLMessageBuilder,
+}
+
+#[pymethods]
+impl MessageStream {
+    #[new]
+    fn new() -> Self {
+        Self {
+            builder: SMLMessageBuilder::Empty,
+        }
+    }
+
+    /// Record the bytes and return the messages of all frames completed by them
+    ///
+    /// Frames which cannot be parsed are skipped.
+    fn feed<'py>(&mut self, py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyList>> {
+        let result = PyList::empty(py);
+        let mut to_process = data.to_vec();
+        while !to_process.is_empty() {
+            self.builder.record(&to_process);
+            to_process = vec![];
+
+            if let SMLMessageBuilder::Complete { data, rest } = &self.builder {
+                if let Ok(messages) = parser::parse_body(data) {
+                    for message in messages_to_py(py, &messages)? {
+                        result.append(message)?;
+                    }
+                }
+                to_process = rest.clone();
+                self.builder = SMLMessageBuilder::Empty;
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn parse_error(error: ParseError) -> PyErr {
+    PyValueError::new_err(format!("invalid SML data: {:?}", error))
+}
+
+fn messages_to_py<'py>(py: Python<'py>, messages: &SmlMessages) -> PyResult<Bound<'py, PyList>> {
+    let result = PyList::empty(py);
+    for message in &messages.messages {
+        let dict = PyDict::new(py);
+        dict.set_item("transaction_id", bytes(py, &message.transaction_id))?;
+        dict.set_item("group_no", message.group_no)?;
+        dict.set_item("abort_on_error", message.abort_on_error)?;
+        dict.set_item("crc", message.crc)?;
+        body_to_py(&dict, &message.body)?;
+        result.append(dict)?;
+    }
+    Ok(result)
+}
+
+fn body_to_py(dict: &Bound<PyDict>, body: &SmlMessageBody) -> PyResult<()> {
+    let py = dict.py();
+    match body {
+        SmlMessageBody::GetOpenResponse(body) => {
+            dict.set_item("type", "open_response")?;
+            dict.set_item("codepage", optional_bytes(py, &body.codepage))?;
+            dict.set_item("client_id", optional_bytes(py, &body.client_id))?;
+            dict.set_item("server_id", bytes(py, &body.server_id))?;
+            dict.set_item("req_file_id", bytes(py, &body.req_file_id))?;
+            dict.set_item("ref_time", body.ref_time)?;
+            dict.set_item("sml_version", body.sml_version)?;
+        }
+        SmlMessageBody::GetListResponse(body) => {
+            dict.set_item("type", "list_response")?;
+            dict.set_item("client_id", optional_bytes(py, &body.client_id))?;
+            dict.set_item("server_id", bytes(py, &body.server_id))?;
+            dict.set_item("list_name", bytes(py, &body.list_name))?;
+            dict.set_item("act_sensor_time", body.act_sensor_time)?;
+            let value_list = PyList::empty(py);
+            for entry in &body.value_list {
+                value_list.append(entry_to_py(py, entry)?)?;
+            }
+            dict.set_item("value_list", value_list)?;
+            dict.set_item("list_signature", optional_bytes(py, &body.list_signature))?;
+            dict.set_item("act_gateway_time", body.act_gateway_time)?;
+        }
+        SmlMessageBody::GetCloseResponse(body) => {
+            dict.set_item("type", "close_response")?;
+            dict.set_item(
+                "global_signature",
+                optional_bytes(py, &body.global_signature),
+            )?;
+        }
+        SmlMessageBody::GetProcParameterResponse(body) => {
+            dict.set_item("type", "proc_parameter_response")?;
+            dict.set_item("server_id", bytes(py, &body.server_id))?;
+            let path = PyList::empty(py);
+            for name in &body.parameter_tree_path {
+                path.append(bytes(py, name))?;
+            }
+            dict.set_item("parameter_tree_path", path)?;
+            dict.set_item("parameter_tree", tree_to_py(py, &body.parameter_tree)?)?;
+        }
+        SmlMessageBody::AttentionResponse(body) => {
+            dict.set_item("type", "attention_response")?;
+            dict.set_item("server_id", bytes(py, &body.server_id))?;
+            dict.set_item("attention_number", bytes(py, &body.attention_number))?;
+            dict.set_item("attention_message", bytes(py, &body.attention_message))?;
+        }
+    }
+    Ok(())
+}
+
+fn entry_to_py<'py>(py: Python<'py>, entry: &SmlListEntry) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    let obis = Obis::from_number(&entry.object_name);
+    dict.set_item("obis", format_object_name(&entry.object_name))?;
+    dict.set_item("name", obis.as_ref().map(Obis::name))?;
+    dict.set_item("object_name", bytes(py, &entry.object_name))?;
+    dict.set_item("status", entry.status)?;
+    dict.set_item("value_time", bytes(py, &entry.value_time))?;
+    dict.set_item("unit", entry.unit.and_then(unit_symbol))?;
+    dict.set_item("unit_code", entry.unit)?;
+    dict.set_item("scaler", entry.scaler)?;
+    dict.set_item("raw_value", value_to_py(py, &entry.value)?)?;
+    let value = match entry.scaled_value() {
+        Some(value) => value.into_pyobject(py)?.into_any(),
+        None => value_to_py(py, &entry.value)?,
+    };
+    dict.set_item("value", value)?;
+    Ok(dict)
+}
+
+fn tree_to_py<'py>(py: Python<'py>, tree: &SmlTree) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    dict.set_item("parameter_name", bytes(py, &tree.parameter_name))?;
+    let (value, time) = match &tree.parameter_value {
+        Some(SmlProcParValue::Value(value)) => (Some(value_to_py(py, value)?), None),
+        Some(SmlProcParValue::Time(time)) => (None, Some(*time)),
+        None => (None, None),
+    };
+    dict.set_item("parameter_value", value)?;
+    dict.set_item("parameter_time", time)?;
+    let child_list = PyList::empty(py);
+    for child in &tree.child_list {
+        child_list.append(tree_to_py(py, child)?)?;
+    }
+    dict.set_item("child_list", child_list)?;
+    Ok(dict)
+}
+
+fn value_to_py<'py>(py: Python<'py>, value: &AnyValue) -> PyResult<Bound<'py, PyAny>> {
+    Ok(match value {
+        AnyValue::Unsigned(v) => v.into_pyobject(py)?.into_any(),
+        AnyValue::Signed(v) => v.into_pyobject(py)?.into_any(),
+        AnyValue::String(v) => bytes(py, v).into_any(),
+    })
+}
+
+fn bytes<'py>(py: Python<'py>, data: &[u8]) -> Bound<'py, PyBytes> {
+    PyBytes::new(py, data)
+}
+
+fn optional_bytes<'py>(py: Python<'py>, data: &Option<Vec<u8>>) -> Option<Bound<'py, PyBytes>> {
+    data.as_ref().map(|data| bytes(py, data))
+}
+
+#[pymodule]
+fn hackdose_sml(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(parse_body, module)?)?;
+    module.add_function(wrap_pyfunction!(parse_message, module)?)?;
+    module.add_function(wrap_pyfunction!(lookup_obis, module)?)?;
+    module.add_class::<MessageStream>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LIST_RESPONSE: &[u8] = &[
+        0x76, 0x05, 0x01, 0xd3, 0xd7, 0xbb, 0x62, 0x00, 0x62, 0x00, 0x72, 0x63, 0x07, 0x01, 0x77,
+        0x01, 0x0b, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x07, 0x01, 0x00,
+        0x62, 0x0a, 0xff, 0xff, 0x72, 0x62, 0x01, 0x65, 0x01, 0x8a, 0x4d, 0x15, 0x72, 0x77, 0x07,
+        0x81, 0x81, 0xc7, 0x82, 0x03, 0xff, 0x01, 0x01, 0x01, 0x01, 0x04, 0x49, 0x53, 0x4b, 0x01,
+        0x77, 0x07, 0x01, 0x00, 0x10, 0x07, 0x00, 0xff, 0x65, 0x00, 0x00, 0x01, 0x82, 0x01, 0x62,
+        0x1b, 0x52, 0xff, 0x59, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0xd2, 0x01, 0x01, 0x01,
+        0x63, 0xc6, 0x12, 0x00,
+    ];
+
+    const CLOSE_MESSAGE: &[u8] = &[
+        0x1b, 0x1b, 0x1b, 0x1b, 0x01, 0x01, 0x01, 0x01, 0x76, 0x05, 0x03, 0x2b, 0x18, 0x11, 0x62,
+        0x00, 0x62, 0x00, 0x72, 0x63, 0x02, 0x01, 0x71, 0x01, 0x63, 0xfa, 0x36, 0x00, 0x1b, 0x1b,
+        0x1b, 0x1b, 0x1a, 0x00, 0x70, 0xb2,
+    ];
+
+    #[test]
+    pub fn converts_list_entries() {
+        Python::with_gil(|py| {
+            let messages = parse_body(py, LIST_RESPONSE).unwrap();
+            let message = messages.get_item(0).unwrap();
+            assert_eq!(
+                message
+                    .get_item("type")
+                    .unwrap()
+                    .extract::<String>()
+                    .unwrap(),
+                "list_response"
+            );
+
+            let entry = message.get_item("value_list").unwrap().get_item(1).unwrap();
+            let item = |key: &str| entry.get_item(key).unwrap();
+            assert_eq!(item("obis").extract::<String>().unwrap(), "1-0:16.7.0*255");
+            assert_eq!(
+                item("name").extract::<String>().unwrap(),
+                "Sum active instantaneous power (A+ - A-)"
+            );
+            assert_eq!(item("unit").extract::<String>().unwrap(), "W");
+            assert_eq!(item("raw_value").extract::<i64>().unwrap(), 1234);
+            assert!((item("value").extract::<f64>().unwrap() - 123.4).abs() < 1e-9);
+        });
+    }
+
+    #[test]
+    pub fn rejects_invalid_data() {
+        Python::with_gil(|py| {
+            let error = parse_body(py, &LIST_RESPONSE[..20]).unwrap_err();
+            assert!(error.is_instance_of::<PyValueError>(py));
+        });
+    }
+
+    #[test]
+    pub fn decodes_stream_in_chunks() {
+        Python::with_gil(|py| {
+            let mut stream = MessageStream::new();
+            let (first, second) = CLOSE_MESSAGE.split_at(13);
+
+            assert!(stream.feed(py, first).unwrap().is_empty());
+            let messages = stream.feed(py, second).unwrap();
+
+            assert_eq!(messages.len(), 1);
+            assert_eq!(
+                messages
+                    .get_item(0)
+                    .unwrap()
+                    .get_item("type")
+                    .unwrap()
+                    .extract::<String>()
+                    .unwrap(),
+                "close_response"
+            );
+        });
+    }
+
+    #[test]
+    pub fn looks_up_obis_numbers() {
+        Python::with_gil(|py| {
+            let obis = lookup_obis(py, ObjectName::Text("1.8.0".to_string()))
+                .unwrap()
+                .unwrap();
+            assert_eq!(
+                obis.get_item("obis")
+                    .unwrap()
+                    .unwrap()
+                    .extract::<String>()
+                    .unwrap(),
+                "1-0:1.8.0*255"
+            );
+            assert_eq!(
+                obis.get_item("unit")
+                    .unwrap()
+                    .unwrap()
+                    .extract::<String>()
+                    .unwrap(),
+                "Wh"
+            );
+
+            assert!(
+                lookup_obis(py, ObjectName::Bytes(vec![1, 0, 99, 99, 0, 255]))
+                    .unwrap()
+                    .is_none()
+            );
+        });
+    }
+}
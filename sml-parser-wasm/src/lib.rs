@@ -0,0 +1,284 @@
+//! WebAssembly bindings of the hackdose sml parser
+//!
+//! Decodes SML frames in the browser and returns them as JSON. Build with
+//! `wasm-pack build --target web`, the page in `www/` uses the generated package.
+
+use hackdose_sml_parser::{
+    application::{
+        domain::{AnyValue, SmlListEntry, SmlMessageBody, SmlProcParValue, SmlTree},
+        obis::{format_object_name, Obis},
+        parser::parse_body,
+        units::unit_symbol,
+    },
+    capture::parse_capture,
+    d0::parser::parse_object_name,
+    transport::SMLMessageBuilder,
+};
+use serde_json::{json, Value};
+use wasm_bindgen::prelude::*;
+
+/// Decode frames given as hex, e.g. `1b 1b 1b 1b 01 01 01 01 76 ...`
+///
+/// Spaces, line breaks, commas and `0x` prefixes are ignored.
+#[wasm_bindgen]
+pub fn decode_hex(hex: &str) -> Result<String, JsError> {
+    let data = parse_hex(hex).map_err(|e| JsError::new(&e))?;
+    Ok(decode(&data).to_string())
+}
+
+/// Decode a raw byte dump or a hackdose capture
+#[wasm_bindgen]
+pub fn decode_bytes(data: &[u8]) -> Result<String, JsError> {
+    let data = unpack_capture(data).map_err(|e| JsError::new(&e))?;
+    Ok(decode(&data).to_string())
+}
+
+/// Name, unit and description of an OBIS number like `1-0:1.8.0*255` as JSON, `undefined`
+/// for unknown numbers
+#[wasm_bindgen]
+pub fn lookup_obis(object_name: &str) -> Option<String> {
+    lookup(object_name).map(|obis| obis.to_string())
+}
+
+/// All frames found in the data as JSON array
+///
+/// Data without the start sequence of a frame is decoded as a single message body, a
+/// frame without end sequence is reported as incomplete.
+fn decode(data: &[u8]) -> Value {
+    let mut frames = vec![];
+    let mut builder = SMLMessageBuilder::Empty;
+    let mut to_process = data.to_vec();
+    while !to_process.is_empty() {
+        builder.record(&to_process);
+        to_process = vec![];
+
+        if let SMLMessageBuilder::Complete { data, rest } = &builder {
+            frames.push(decode_body(data));
+            to_process = rest.clone();
+            builder = SMLMessageBuilder::Empty;
+        }
+    }
+    match builder {
+        SMLMessageBuilder::Recording(partial) => frames.push(json!({
+            "body": hex(&partial),
+            "error": "incomplete frame",
+        })),
+        _ if frames.is_empty() => frames.push(decode_body(data)),
+        _ => (),
+    }
+    Value::Array(frames)
+}
+
+fn decode_body(body: &[u8]) -> Value {
+    match parse_body(body) {
+        Ok(messages) => json!({
+            "body": hex(body),
+            "messages": messages.messages.iter().map(|message| {
+                let mut value = message_body(&message.body);
+                value["transaction_id"] = hex(&message.transaction_id).into();
+                value["group_no"] = message.group_no.into();
+                value["abort_on_error"] = message.abort_on_error.into();
+                value["crc"] = message.crc.into();
+                value
+            }).collect::<Vec<_>>(),
+        }),
+        Err(error) => json!({
+            "body": hex(body),
+            "error": format!("invalid SML data: {:?}", error),
+        }),
+    }
+}
+
+fn message_body(body: &SmlMessageBody) -> Value {
+    match body {
+        SmlMessageBody::GetOpenResponse(body) => json!({
+            "type": "open_response",
+            "codepage": body.codepage.as_deref().map(hex),
+            "client_id": body.client_id.as_deref().map(hex),
+            "server_id": hex(&body.server_id),
+            "req_file_id": hex(&body.req_file_id),
+            "ref_time": body.ref_time,
+            "sml_version": body.sml_version,
+        }),
+        SmlMessageBody::GetListResponse(body) => json!({
+            "type": "list_response",
+            "client_id": body.client_id.as_deref().map(hex),
+            "server_id": hex(&body.server_id),
+            "list_name": hex(&body.list_name),
+            "act_sensor_time": body.act_sensor_time,
+            "value_list": body.value_list.iter().map(list_entry).collect::<Vec<_>>(),
+            "list_signature": body.list_signature.as_deref().map(hex),
+            "act_gateway_time": body.act_gateway_time,
+        }),
+        SmlMessageBody::GetCloseResponse(body) => json!({
+            "type": "close_response",
+            "global_signature": body.global_signature.as_deref().map(hex),
+        }),
+        SmlMessageBody::GetProcParameterResponse(body) => json!({
+            "type": "proc_parameter_response",
+            "server_id": hex(&body.server_id),
+            "parameter_tree_path": body.parameter_tree_path.iter().map(|name| hex(name)).collect::<Vec<_>>(),
+            "parameter_tree": tree(&body.parameter_tree),
+        }),
+        SmlMessageBody::AttentionResponse(body) => json!({
+            "type": "attention_response",
+            "server_id": hex(&body.server_id),
+            "attention_number": hex(&body.attention_number),
+            "attention_message": hex(&body.attention_message),
+        }),
+    }
+}
+
+fn list_entry(entry: &SmlListEntry) -> Value {
+    let value = match entry.scaled_value() {
+        Some(value) => value.into(),
+        None => any_value(&entry.value),
+    };
+    json!({
+        "obis": format_object_name(&entry.object_name),
+        "name": Obis::from_number(&entry.object_name).map(|obis| obis.name()),
+        "status": entry.status,
+        "value_time": hex(&entry.value_time),
+        "unit": entry.unit.and_then(unit_symbol),
+        "unit_code": entry.unit,
+        "scaler": entry.scaler,
+        "raw_value": any_value(&entry.value),
+        "value": value,
+    })
+}
+
+fn tree(tree: &SmlTree) -> Value {
+    let (value, time) = match &tree.parameter_value {
+        Some(SmlProcParValue::Value(value)) => (Some(any_value(value)), None),
+        Some(SmlProcParValue::Time(time)) => (None, Some(*time)),
+        None => (None, None),
+    };
+    json!({
+        "parameter_name": hex(&tree.parameter_name),
+        "parameter_value": value,
+        "parameter_time": time,
+        "child_list": tree.child_list.iter().map(self::tree).collect::<Vec<_>>(),
+    })
+}
+
+/// Numbers as JSON numbers, octet strings as hex
+fn any_value(value: &AnyValue) -> Value {
+    match value {
+        AnyValue::Unsigned(v) => (*v).into(),
+        AnyValue::Signed(v) => (*v).into(),
+        AnyValue::String(v) => hex(v).into(),
+    }
+}
+
+fn lookup(object_name: &str) -> Option<Value> {
+    let number = parse_object_name(object_name.trim()).ok()?;
+    let obis = Obis::from_number(&number)?;
+    Some(json!({
+        "obis": format_object_name(&number),
+        "name": obis.name(),
+        "unit": obis.unit(),
+        "description": obis.description(),
+    }))
+}
+
+fn unpack_capture(data: &[u8]) -> Result<Vec<u8>, String> {
+    if !data.starts_with(b"HDCAP") {
+        return Ok(data.to_vec());
+    }
+    let records = parse_capture(data).map_err(|e| format!("invalid capture: {}", e))?;
+    Ok(records.into_iter().flat_map(|record| record.data).collect())
+}
+
+fn parse_hex(input: &str) -> Result<Vec<u8>, String> {
+    let digits = input
+        .replace("0x", "")
+        .replace("0X", "")
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ',' && *c != ':')
+        .collect::<Vec<_>>();
+    if let Some(c) = digits.iter().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(format!("invalid hex digit '{}'", c));
+    }
+    if digits.len() % 2 != 0 {
+        return Err("odd number of hex digits".to_string());
+    }
+    Ok(digits
+        .chunks(2)
+        .map(|pair| {
+            let pair = pair.iter().collect::<String>();
+            u8::from_str_radix(&pair, 16).unwrap()
+        })
+        .collect())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CLOSE_MESSAGE: &str = "1b1b1b1b 01010101 76 05 032b1811 6200 6200 72 630201 71 01 \
+        63fa36 00 1b1b1b1b 1a0070b2";
+
+    #[test]
+    pub fn decodes_frames_from_hex() {
+        let data = parse_hex(&format!("{} {}", CLOSE_MESSAGE, CLOSE_MESSAGE)).unwrap();
+
+        let frames = decode(&data);
+
+        assert_eq!(frames.as_array().unwrap().len(), 2);
+        assert_eq!(frames[1]["messages"][0]["type"], "close_response");
+        assert_eq!(frames[1]["messages"][0]["transaction_id"], "032b1811");
+    }
+
+    #[test]
+    pub fn decodes_bare_body() {
+        let body = parse_hex(
+            "76 05 01d3d7bb 6200 6200 72 630701 77 01 0b 0102030405060708090a 07 0100620affff \
+             72 62 01 65 018a4d15 71 77 07 0100100700ff 01 01 62 1b 52 ff 55 000004d2 01 01 01 \
+             63 c612 00",
+        )
+        .unwrap();
+
+        let frames = decode(&body);
+
+        let entry = &frames[0]["messages"][0]["value_list"][0];
+        assert_eq!(entry["obis"], "1-0:16.7.0*255");
+        assert_eq!(entry["unit"], "W");
+        assert_eq!(entry["raw_value"], 1234);
+        assert!((entry["value"].as_f64().unwrap() - 123.4).abs() < 1e-9);
+    }
+
+    #[test]
+    pub fn reports_invalid_input() {
+        assert!(parse_hex("1b 1x").is_err());
+        assert!(parse_hex("1b 1").is_err());
+        assert!(decode(&[0x76, 0x05])[0]["error"].is_string());
+        let truncated = parse_hex(&CLOSE_MESSAGE[..40]).unwrap();
+        assert_eq!(decode(&truncated)[0]["error"], "incomplete frame");
+    }
+
+    #[test]
+    pub fn unpacks_captures() {
+        let mut capture = b"HDCAP\x01".to_vec();
+        for chunk in [&[0x1b, 0x1b][..], &[0x1b, 0x1b, 0x01]] {
+            capture.extend_from_slice(&0u64.to_be_bytes());
+            capture.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+            capture.extend_from_slice(chunk);
+        }
+
+        assert_eq!(
+            unpack_capture(&capture).unwrap(),
+            vec![0x1b, 0x1b, 0x1b, 0x1b, 0x01]
+        );
+        assert!(unpack_capture(&capture[..capture.len() - 1]).is_err());
+    }
+
+    #[test]
+    pub fn looks_up_obis_numbers() {
+        assert_eq!(lookup("1.8.0").unwrap()["unit"], "Wh");
+        assert!(lookup("1-0:99.99.0*255").is_none());
+    }
+}
@@ -0,0 +1,84 @@
+//! Compare the hand-written decoder with the `peg` grammar
+//!
+//! Run with `cargo bench -p hackdose-sml-parser`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use hackdose_sml_parser::application::parser::{parse_body, parse_body_peg};
+
+/// Open response, list response with a typical set of readings and close response
+fn body() -> Vec<u8> {
+    let mut body = vec![
+        0x76, 0x05, 0x03, 0x2b, 0x18, 0x0f, 0x62, 0x00, 0x62, 0x00, 0x72, 0x63, 0x01, 0x01, 0x76,
+        0x01, 0x01, 0x05, 0x04, 0x03, 0x02, 0x01, 0x0b, 0x0a, 0x01, 0x49, 0x53, 0x4b, 0x00, 0x04,
+        0x7a, 0x5e, 0x51, 0x01, 0x01, 0x63, 0x49, 0x00, 0x00,
+    ];
+    body.extend_from_slice(&[
+        0x76, 0x05, 0x03, 0x2b, 0x18, 0x10, 0x62, 0x00, 0x62, 0x00, 0x72, 0x63, 0x07, 0x01, 0x77,
+        0x01, 0x0b, 0x0a, 0x01, 0x49, 0x53, 0x4b, 0x00, 0x04, 0x7a, 0x5e, 0x51, 0x07, 0x01, 0x00,
+        0x62, 0x0a, 0xff, 0xff, 0x72, 0x62, 0x01, 0x65, 0x01, 0x8a, 0x4d, 0x15, 0x78,
+    ]);
+    let entries: [(&[u8], &[u8]); 8] = [
+        (
+            &[0x01, 0x00, 0x01, 0x08, 0x00, 0xff],
+            &[0x59, 0, 0, 0, 0, 0x01, 0x2a, 0x05, 0x39],
+        ),
+        (
+            &[0x01, 0x00, 0x01, 0x08, 0x01, 0xff],
+            &[0x59, 0, 0, 0, 0, 0x01, 0x2a, 0x05, 0x39],
+        ),
+        (
+            &[0x01, 0x00, 0x01, 0x08, 0x02, 0xff],
+            &[0x59, 0, 0, 0, 0, 0, 0, 0, 0],
+        ),
+        (
+            &[0x01, 0x00, 0x02, 0x08, 0x00, 0xff],
+            &[0x59, 0, 0, 0, 0, 0, 0x10, 0x00, 0x00],
+        ),
+        (
+            &[0x01, 0x00, 0x10, 0x07, 0x00, 0xff],
+            &[0x55, 0, 0, 0x01, 0x2c],
+        ),
+        (
+            &[0x01, 0x00, 0x24, 0x07, 0x00, 0xff],
+            &[0x55, 0, 0, 0, 0x64],
+        ),
+        (
+            &[0x01, 0x00, 0x38, 0x07, 0x00, 0xff],
+            &[0x55, 0, 0, 0, 0x64],
+        ),
+        (
+            &[0x01, 0x00, 0x4c, 0x07, 0x00, 0xff],
+            &[0x55, 0, 0, 0, 0x64],
+        ),
+    ];
+    for (object_name, value) in entries {
+        body.extend_from_slice(&[0x77, 0x07]);
+        body.extend_from_slice(object_name);
+        body.extend_from_slice(&[0x65, 0x00, 0x00, 0x01, 0x82, 0x01, 0x62, 0x1e, 0x52, 0xff]);
+        body.extend_from_slice(value);
+        body.push(0x01);
+    }
+    body.extend_from_slice(&[0x01, 0x01, 0x63, 0xc6, 0x12, 0x00]);
+    body.extend_from_slice(&[
+        0x76, 0x05, 0x03, 0x2b, 0x18, 0x11, 0x62, 0x00, 0x62, 0x00, 0x72, 0x63, 0x02, 0x01, 0x71,
+        0x01, 0x63, 0xfa, 0x36, 0x00,
+    ]);
+    body
+}
+
+fn parse(c: &mut Criterion) {
+    let body = body();
+    assert_eq!(
+        parse_body(&body).unwrap(),
+        parse_body_peg(&body).unwrap(),
+        "both parsers have to agree"
+    );
+
+    let mut group = c.benchmark_group("parse_body");
+    group.bench_function("decoder", |b| b.iter(|| parse_body(black_box(&body))));
+    group.bench_function("peg", |b| b.iter(|| parse_body_peg(black_box(&body))));
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -0,0 +1,539 @@
+//! Hand-written decoder for SML messages
+//!
+//! Every value is preceded by a type-length field (TL) which determines how to read it, so
+//! the decoder reads each TL once and never backtracks. It yields the same
+//! [SmlMessages] as the grammar in [crate::application::parser], is more lenient with
+//! integer widths and does not panic on arbitrary input.
+
+use crate::application::{
+    domain::{
+        AnyValue, AttentionResponseBody, GetCloseResponseBody, GetListResponseBody,
+        GetOpenResponseBody, GetProcParameterResponseBody, SmlListEntry, SmlMessageBody,
+        SmlMessageEnvelope, SmlMessages, SmlProcParValue, SmlTree,
+    },
+    parser::{ParseError, ParseResult},
+};
+
+const HEADER: [u8; 8] = [0x1b, 0x1b, 0x1b, 0x1b, 0x01, 0x01, 0x01, 0x01];
+const END_SEQUENCE: [u8; 5] = [0x1b, 0x1b, 0x1b, 0x1b, 0x1a];
+const END_OF_MESSAGE: u8 = 0x00;
+const NOT_SET: u8 = 0x01;
+
+/// Parse trees nested deeper than this are rejected instead of exhausting the stack
+const MAX_TREE_DEPTH: usize = 16;
+
+/// Decode the body of an SML message (omitting header and footer)
+pub fn decode_body(input: &[u8]) -> ParseResult<SmlMessages> {
+    let mut decoder = Decoder::new(input);
+    let messages = decoder.messages()?;
+    decoder.padding();
+    decoder.end()?;
+    Ok(messages)
+}
+
+/// Decode the whole SML message
+pub fn decode_message(input: &[u8]) -> ParseResult<SmlMessages> {
+    let mut decoder = Decoder::new(input);
+    decoder.expect(&HEADER)?;
+    let messages = decoder.messages()?;
+    decoder.padding();
+    decoder.expect(&END_SEQUENCE)?;
+    // number of padding bytes and CRC
+    decoder.take(3)?;
+    decoder.end()?;
+    Ok(messages)
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum TlType {
+    OctetString,
+    Boolean,
+    Integer,
+    Unsigned,
+    List,
+}
+
+/// Types by bits 4 to 6 of the TL field
+static TL_TYPES: [Option<TlType>; 8] = [
+    Some(TlType::OctetString),
+    None,
+    None,
+    None,
+    Some(TlType::Boolean),
+    Some(TlType::Integer),
+    Some(TlType::Unsigned),
+    Some(TlType::List),
+];
+
+type BodyDecoder = fn(&mut Decoder) -> ParseResult<SmlMessageBody>;
+
+/// Message tag, number of elements of the body and decoder of the supported responses
+static MESSAGE_BODIES: &[(u32, usize, BodyDecoder)] = &[
+    (0x0101, 6, |d| d.open_response()),
+    (0x0201, 1, |d| d.close_response()),
+    (0x0501, 3, |d| d.proc_parameter_response()),
+    (0x0701, 7, |d| d.list_response()),
+    (0xff01, 4, |d| d.attention_response()),
+];
+
+struct Tl {
+    tl_type: TlType,
+    /// number of bytes of the value without the TL field, number of elements for lists
+    length: usize,
+}
+
+struct Decoder<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        Self { input, position: 0 }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.position).copied()
+    }
+
+    fn take(&mut self, n: usize) -> ParseResult<&'a [u8]> {
+        let end = self.position.checked_add(n).ok_or(ParseError::Unknown)?;
+        let bytes = self
+            .input
+            .get(self.position..end)
+            .ok_or(ParseError::Unknown)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn expect(&mut self, expected: &[u8]) -> ParseResult<()> {
+        if self.take(expected.len())? == expected {
+            Ok(())
+        } else {
+            Err(ParseError::Unknown)
+        }
+    }
+
+    fn end(&self) -> ParseResult<()> {
+        if self.position == self.input.len() {
+            Ok(())
+        } else {
+            Err(ParseError::Unknown)
+        }
+    }
+
+    fn padding(&mut self) {
+        for _ in 0..3 {
+            if self.peek() != Some(0x00) {
+                return;
+            }
+            self.position += 1;
+        }
+    }
+
+    fn tl(&mut self) -> ParseResult<Tl> {
+        let mut byte = self.take(1)?[0];
+        let tl_type = TL_TYPES[((byte >> 4) & 0x07) as usize].ok_or(ParseError::Unknown)?;
+        let mut length = (byte & 0x0f) as usize;
+        let mut tl_length = 1;
+        while byte & 0x80 != 0 {
+            byte = self.take(1)?[0];
+            if byte & 0x70 != 0 || tl_length >= 4 {
+                return Err(ParseError::Unknown);
+            }
+            length = (length << 4) | (byte & 0x0f) as usize;
+            tl_length += 1;
+        }
+        if tl_type != TlType::List {
+            // the length of values includes the TL field
+            length = length.checked_sub(tl_length).ok_or(ParseError::Unknown)?;
+        }
+        Ok(Tl { tl_type, length })
+    }
+
+    /// Skip the marker of an optional value which is not set
+    fn not_set(&mut self) -> bool {
+        if self.peek() == Some(NOT_SET) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn optional<T>(&mut self, f: fn(&mut Self) -> ParseResult<T>) -> ParseResult<Option<T>> {
+        if self.not_set() {
+            Ok(None)
+        } else {
+            f(self).map(Some)
+        }
+    }
+
+    fn list(&mut self, expected: usize) -> ParseResult<()> {
+        if self.list_length()? == expected {
+            Ok(())
+        } else {
+            Err(ParseError::Unknown)
+        }
+    }
+
+    fn list_length(&mut self) -> ParseResult<usize> {
+        match self.tl()? {
+            Tl {
+                tl_type: TlType::List,
+                length,
+            } => Ok(length),
+            _ => Err(ParseError::Unknown),
+        }
+    }
+
+    fn string(&mut self) -> ParseResult<Vec<u8>> {
+        match self.tl()? {
+            Tl {
+                tl_type: TlType::OctetString,
+                length,
+            } => Ok(self.take(length)?.to_vec()),
+            _ => Err(ParseError::Unknown),
+        }
+    }
+
+    fn optional_string(&mut self) -> ParseResult<Option<Vec<u8>>> {
+        self.optional(Self::string)
+    }
+
+    /// An unsigned integer of at most `width` bytes
+    fn unsigned(&mut self, width: usize) -> ParseResult<u64> {
+        match self.tl()? {
+            Tl {
+                tl_type: TlType::Unsigned,
+                length,
+            } if (1..=width).contains(&length) => Ok(self
+                .take(length)?
+                .iter()
+                .fold(0, |value, byte| (value << 8) | *byte as u64)),
+            _ => Err(ParseError::Unknown),
+        }
+    }
+
+    fn unsigned_8(&mut self) -> ParseResult<u8> {
+        self.unsigned(1).map(|v| v as u8)
+    }
+
+    fn unsigned_16(&mut self) -> ParseResult<u16> {
+        self.unsigned(2).map(|v| v as u16)
+    }
+
+    fn unsigned_32(&mut self) -> ParseResult<u32> {
+        self.unsigned(4).map(|v| v as u32)
+    }
+
+    fn signed_8(&mut self) -> ParseResult<i8> {
+        match self.tl()? {
+            Tl {
+                tl_type: TlType::Integer,
+                length: 1,
+            } => Ok(self.take(1)?[0] as i8),
+            _ => Err(ParseError::Unknown),
+        }
+    }
+
+    fn value(&mut self) -> ParseResult<AnyValue> {
+        let Tl { tl_type, length } = self.tl()?;
+        if tl_type != TlType::OctetString && !(1..=8).contains(&length) {
+            return Err(ParseError::Unknown);
+        }
+        let bytes = self.take(length)?;
+        let unsigned = bytes
+            .iter()
+            .fold(0u64, |value, byte| (value << 8) | *byte as u64);
+        match tl_type {
+            TlType::OctetString => Ok(AnyValue::String(bytes.to_vec())),
+            TlType::Boolean => Ok(AnyValue::Unsigned((unsigned != 0) as usize)),
+            TlType::Unsigned => Ok(AnyValue::Unsigned(unsigned as usize)),
+            TlType::Integer => {
+                let shift = 64 - 8 * length as u32;
+                Ok(AnyValue::Signed(
+                    ((unsigned << shift) as i64 >> shift) as isize,
+                ))
+            }
+            TlType::List => Err(ParseError::Unknown),
+        }
+    }
+
+    /// SML_Time as seconds, either as an index or a timestamp
+    fn time(&mut self) -> ParseResult<u32> {
+        self.list(2)?;
+        match self.unsigned_8()? {
+            1 | 2 => self.unsigned_32(),
+            _ => Err(ParseError::Unknown),
+        }
+    }
+
+    fn optional_time(&mut self) -> ParseResult<Option<u32>> {
+        self.optional(Self::time)
+    }
+
+    fn messages(&mut self) -> ParseResult<SmlMessages> {
+        let mut messages = vec![];
+        while let Some(0x76) = self.peek() {
+            messages.push(self.envelope()?);
+        }
+        Ok(SmlMessages { messages })
+    }
+
+    fn envelope(&mut self) -> ParseResult<SmlMessageEnvelope> {
+        self.list(6)?;
+        let transaction_id = self.string()?;
+        let group_no = self.unsigned_8()?;
+        let abort_on_error = self.unsigned_8()?;
+
+        self.list(2)?;
+        let tag = self.unsigned_32()?;
+        let (_, length, body_decoder) = MESSAGE_BODIES
+            .iter()
+            .find(|(t, _, _)| *t == tag)
+            .ok_or(ParseError::Unknown)?;
+        self.list(*length)?;
+        let body = body_decoder(self)?;
+
+        let crc = self.unsigned_16()?.swap_bytes();
+        self.expect(&[END_OF_MESSAGE])?;
+        Ok(SmlMessageEnvelope {
+            transaction_id,
+            group_no,
+            abort_on_error,
+            body,
+            crc,
+        })
+    }
+
+    fn open_response(&mut self) -> ParseResult<SmlMessageBody> {
+        Ok(SmlMessageBody::GetOpenResponse(GetOpenResponseBody {
+            codepage: self.optional_string()?,
+            client_id: self.optional_string()?,
+            req_file_id: self.string()?,
+            server_id: self.string()?,
+            ref_time: self.optional_time()?,
+            sml_version: self.optional(Self::unsigned_8)?,
+        }))
+    }
+
+    fn close_response(&mut self) -> ParseResult<SmlMessageBody> {
+        Ok(SmlMessageBody::GetCloseResponse(GetCloseResponseBody {
+            global_signature: self.optional_string()?,
+        }))
+    }
+
+    fn list_response(&mut self) -> ParseResult<SmlMessageBody> {
+        let client_id = self.optional_string()?;
+        let server_id = self.string()?;
+        let list_name = self.string()?;
+        let act_sensor_time = self.optional_time()?;
+        let length = self.list_length()?;
+        let mut value_list = vec![];
+        for _ in 0..length {
+            value_list.push(self.list_entry()?);
+        }
+        let list_signature = self.optional_string()?;
+        // some meters omit the gateway time altogether
+        let act_gateway_time = match self.peek() {
+            Some(NOT_SET) | Some(0x72) => self.optional_time()?,
+            _ => None,
+        };
+        Ok(SmlMessageBody::GetListResponse(GetListResponseBody {
+            client_id,
+            server_id,
+            list_name,
+            act_sensor_time,
+            value_list,
+            list_signature,
+            act_gateway_time,
+        }))
+    }
+
+    fn list_entry(&mut self) -> ParseResult<SmlListEntry> {
+        self.list(7)?;
+        let entry = SmlListEntry {
+            object_name: self.string()?,
+            status: self.optional(Self::unsigned_32)?,
+            value_time: self.string()?,
+            unit: self.optional(Self::unsigned_8)?,
+            scaler: self.optional(Self::signed_8)?,
+            value: self.value()?,
+        };
+        // value signature
+        self.optional_string()?;
+        Ok(entry)
+    }
+
+    fn proc_parameter_response(&mut self) -> ParseResult<SmlMessageBody> {
+        let server_id = self.string()?;
+        let length = self.list_length()?;
+        let mut parameter_tree_path = vec![];
+        for _ in 0..length {
+            parameter_tree_path.push(self.string()?);
+        }
+        let parameter_tree = self.tree(0)?;
+        Ok(SmlMessageBody::GetProcParameterResponse(
+            GetProcParameterResponseBody {
+                server_id,
+                parameter_tree_path,
+                parameter_tree,
+            },
+        ))
+    }
+
+    fn tree(&mut self, depth: usize) -> ParseResult<SmlTree> {
+        if depth > MAX_TREE_DEPTH {
+            return Err(ParseError::Unknown);
+        }
+        self.list(3)?;
+        let parameter_name = self.string()?;
+        let parameter_value = self.optional(Self::proc_par_value)?;
+        let mut child_list = vec![];
+        if !self.not_set() {
+            for _ in 0..self.list_length()? {
+                child_list.push(self.tree(depth + 1)?);
+            }
+        }
+        Ok(SmlTree {
+            parameter_name,
+            parameter_value,
+            child_list,
+        })
+    }
+
+    fn proc_par_value(&mut self) -> ParseResult<SmlProcParValue> {
+        self.list(2)?;
+        match self.unsigned_8()? {
+            1 => self.value().map(SmlProcParValue::Value),
+            4 => self.time().map(SmlProcParValue::Time),
+            _ => Err(ParseError::Unknown),
+        }
+    }
+
+    fn attention_response(&mut self) -> ParseResult<SmlMessageBody> {
+        let server_id = self.string()?;
+        let attention_number = self.string()?;
+        let attention_message = self.string()?;
+        // attention details
+        if !self.not_set() {
+            self.tree(0)?;
+        }
+        Ok(SmlMessageBody::AttentionResponse(AttentionResponseBody {
+            server_id,
+            attention_number,
+            attention_message,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::application::parser::{parse_body_peg, parse_message_peg};
+
+    const LIST_RESPONSE: &[u8] = &[
+        0x76, 0x05, 0x01, 0xd3, 0xd7, 0xbb, 0x62, 0x00, 0x62, 0x00, 0x72, 0x63, 0x07, 0x01, 0x77,
+        0x01, 0x0b, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x07, 0x01, 0x00,
+        0x62, 0x0a, 0xff, 0xff, 0x72, 0x62, 0x01, 0x65, 0x01, 0x8a, 0x4d, 0x15, 0x72, 0x77, 0x07,
+        0x81, 0x81, 0xc7, 0x82, 0x03, 0xff, 0x01, 0x01, 0x01, 0x01, 0x04, 0x49, 0x53, 0x4b, 0x01,
+        0x77, 0x07, 0x01, 0x00, 0x10, 0x07, 0x00, 0xff, 0x65, 0x00, 0x00, 0x01, 0x82, 0x01, 0x62,
+        0x1b, 0x52, 0xff, 0x59, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0xd2, 0x01, 0x01, 0x01,
+        0x63, 0xc6, 0x12, 0x00,
+    ];
+
+    const PROC_PARAMETER_RESPONSE: &[u8] = &[
+        0x76, 0x02, 0x01, 0x62, 0x00, 0x62, 0x00, 0x72, 0x63, 0x05, 0x01, 0x73, 0x03, 0x01, 0x02,
+        0x71, 0x07, 0x01, 0x00, 0x00, 0x00, 0x09, 0xff, 0x73, 0x07, 0x01, 0x00, 0x00, 0x00, 0x09,
+        0xff, 0x01, 0x71, 0x73, 0x03, 0xaa, 0xbb, 0x72, 0x62, 0x01, 0x53, 0xff, 0x38, 0x01, 0x63,
+        0x12, 0x34, 0x00, 0x00,
+    ];
+
+    const CLOSE_MESSAGE: &[u8] = &[
+        0x1b, 0x1b, 0x1b, 0x1b, 0x01, 0x01, 0x01, 0x01, 0x76, 0x05, 0x03, 0x2b, 0x18, 0x11, 0x62,
+        0x00, 0x62, 0x00, 0x72, 0x63, 0x02, 0x01, 0x71, 0x01, 0x63, 0xfa, 0x36, 0x00, 0x1b, 0x1b,
+        0x1b, 0x1b, 0x1a, 0x00, 0x70, 0xb2,
+    ];
+
+    #[test]
+    pub fn decodes_like_grammar() {
+        for body in [LIST_RESPONSE, PROC_PARAMETER_RESPONSE] {
+            assert_eq!(decode_body(body).unwrap(), parse_body_peg(body).unwrap());
+        }
+        assert_eq!(
+            decode_message(CLOSE_MESSAGE).unwrap(),
+            parse_message_peg(CLOSE_MESSAGE).unwrap()
+        );
+    }
+
+    #[test]
+    pub fn decodes_list_entries() {
+        let messages = decode_body(LIST_RESPONSE).unwrap();
+
+        match &messages.messages[0].body {
+            SmlMessageBody::GetListResponse(body) => {
+                assert_eq!(body.value_list[1].value, AnyValue::Signed(1234));
+                assert_eq!(body.value_list[1].status, Some(386));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    pub fn accepts_narrow_integers() {
+        // status as unsigned 8 and value as unsigned 8, which the grammar rejects
+        let mut body = LIST_RESPONSE.to_vec();
+        let entry = [
+            0x77, 0x07, 0x01, 0x00, 0x10, 0x07, 0x00, 0xff, 0x62, 0x08, 0x01, 0x62, 0x1b, 0x52,
+            0x00, 0x62, 0x2a, 0x01,
+        ];
+        body.splice(60..88, entry);
+
+        let messages = decode_body(&body).unwrap();
+
+        match &messages.messages[0].body {
+            SmlMessageBody::GetListResponse(body) => {
+                assert_eq!(body.value_list[1].status, Some(8));
+                assert_eq!(body.value_list[1].value, AnyValue::Unsigned(42));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    pub fn rejects_truncated_input() {
+        for length in 0..LIST_RESPONSE.len() - 1 {
+            assert!(decode_body(&LIST_RESPONSE[..length]).is_err() || length == 0);
+        }
+        assert!(decode_message(&CLOSE_MESSAGE[..CLOSE_MESSAGE.len() - 1]).is_err());
+    }
+
+    #[test]
+    pub fn does_not_panic_on_garbage() {
+        // deterministic pseudo random input
+        let mut state: u32 = 0x1234_5678;
+        for _ in 0..2000 {
+            let input = (0..64)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    (state % 256) as u8
+                })
+                .collect::<Vec<_>>();
+            let _ = decode_body(&input);
+            let mut prefixed = LIST_RESPONSE[..40].to_vec();
+            prefixed.extend_from_slice(&input);
+            let _ = decode_body(&prefixed);
+        }
+
+        let mut nested = vec![];
+        for _ in 0..1000 {
+            nested.extend_from_slice(&[0x73, 0x01, 0x01, 0x71]);
+        }
+        let mut body = PROC_PARAMETER_RESPONSE[..23].to_vec();
+        body.extend_from_slice(&nested);
+        assert!(decode_body(&body).is_err());
+    }
+}
@@ -5,24 +5,84 @@ pub struct SmlMessages {
     pub messages: Vec<SmlMessageEnvelope>,
 }
 
+/// A single SML message including the metadata of its envelope
 #[derive(PartialEq, Debug, Clone)]
-pub enum SmlMessageEnvelope {
+pub struct SmlMessageEnvelope {
+    /// identifies the request a response belongs to
+    pub transaction_id: Vec<u8>,
+    pub group_no: u8,
+    pub abort_on_error: u8,
+    pub body: SmlMessageBody,
+    /// CRC-16 of the message as computed by [crate::transport::crc16]
+    ///
+    /// The checksum covers the message from its first byte up to the CRC field.
+    pub crc: u16,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum SmlMessageBody {
     GetOpenResponse(GetOpenResponseBody),
     GetListResponse(GetListResponseBody),
-    GetCloseResponse,
+    GetCloseResponse(GetCloseResponseBody),
+    GetProcParameterResponse(GetProcParameterResponseBody),
+    AttentionResponse(AttentionResponseBody),
 }
 
 #[derive(PartialEq, Debug, Clone)]
 pub struct GetOpenResponseBody {
+    pub codepage: Option<Vec<u8>>,
+    pub client_id: Option<Vec<u8>>,
     pub server_id: Vec<u8>,
     pub req_file_id: Vec<u8>,
+    pub ref_time: Option<u32>,
+    pub sml_version: Option<u8>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct GetCloseResponseBody {
+    pub global_signature: Option<Vec<u8>>,
 }
 
 #[derive(PartialEq, Debug, Clone)]
 pub struct GetListResponseBody {
+    pub client_id: Option<Vec<u8>>,
     pub server_id: Vec<u8>,
     pub list_name: Vec<u8>,
+    /// seconds, either as an index (uptime) or a unix timestamp
+    pub act_sensor_time: Option<u32>,
     pub value_list: Vec<SmlListEntry>,
+    pub list_signature: Option<Vec<u8>>,
+    pub act_gateway_time: Option<u32>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct GetProcParameterResponseBody {
+    pub server_id: Vec<u8>,
+    pub parameter_tree_path: Vec<Vec<u8>>,
+    pub parameter_tree: SmlTree,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct SmlTree {
+    pub parameter_name: Vec<u8>,
+    pub parameter_value: Option<SmlProcParValue>,
+    pub child_list: Vec<SmlTree>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum SmlProcParValue {
+    Value(AnyValue),
+    /// seconds, either as an index (uptime) or a unix timestamp
+    Time(u32),
+}
+
+/// Sent by the meter if a request could not be processed
+#[derive(PartialEq, Debug, Clone)]
+pub struct AttentionResponseBody {
+    pub server_id: Vec<u8>,
+    /// attention number, e.g. `[0x81, 0x81, 0xC7, 0xC7, 0xFE, 0x03]` for "unknown parameter"
+    pub attention_number: Vec<u8>,
+    pub attention_message: Vec<u8>,
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -35,6 +95,18 @@ pub struct SmlListEntry {
     pub value: AnyValue,
 }
 
+impl SmlListEntry {
+    /// Numeric value with the scaler applied, `None` for strings and entries without scaler
+    pub fn scaled_value(&self) -> Option<f64> {
+        let value = match self.value {
+            AnyValue::Unsigned(v) => v as f64,
+            AnyValue::Signed(v) => v as f64,
+            AnyValue::String(_) => return None,
+        };
+        Some(value * 10f64.powi(self.scaler? as i32))
+    }
+}
+
 #[derive(PartialEq, Debug, Clone, Serialize)]
 pub enum AnyValue {
     Unsigned(usize),
@@ -42,6 +114,41 @@ pub enum AnyValue {
     String(Vec<u8>),
 }
 
+/// Requests which can be sent to a meter or gateway
+#[derive(PartialEq, Debug, Clone)]
+pub enum SmlRequest {
+    OpenRequest(OpenRequestBody),
+    GetListRequest(GetListRequestBody),
+    GetProcParameterRequest(GetProcParameterRequestBody),
+    CloseRequest,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct OpenRequestBody {
+    pub client_id: Vec<u8>,
+    pub req_file_id: Vec<u8>,
+    pub server_id: Option<Vec<u8>>,
+    pub username: Option<Vec<u8>>,
+    pub password: Option<Vec<u8>>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct GetListRequestBody {
+    pub client_id: Vec<u8>,
+    pub server_id: Option<Vec<u8>>,
+    pub username: Option<Vec<u8>>,
+    pub password: Option<Vec<u8>>,
+    pub list_name: Option<Vec<u8>>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct GetProcParameterRequestBody {
+    pub server_id: Option<Vec<u8>>,
+    pub username: Option<Vec<u8>>,
+    pub password: Option<Vec<u8>>,
+    pub parameter_tree_path: Vec<Vec<u8>>,
+}
+
 /// Scale an SML value by the given scaler (base 10)
 pub trait Scale {
     fn scale(&self, scaler: i8) -> Self;
@@ -51,14 +158,43 @@ impl Scale for AnyValue {
     fn scale(&self, scaler: i8) -> Self {
         match self {
             AnyValue::Unsigned(v) => {
-                let scaler = scaler;
                 AnyValue::Unsigned((10f64.powf(scaler as f64) * *v as f64) as usize)
             }
             AnyValue::Signed(v) => {
-                let scaler = scaler;
                 AnyValue::Signed((10f64.powf(scaler as f64) * *v as f64) as isize)
             }
             AnyValue::String(v) => AnyValue::String(v.clone()),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn scales_numeric_values() {
+        let entry = |value, scaler| SmlListEntry {
+            object_name: vec![1, 0, 16, 7, 0, 255],
+            status: None,
+            value_time: vec![],
+            unit: None,
+            scaler,
+            value,
+        };
+
+        assert_eq!(
+            entry(AnyValue::Signed(-1234), Some(-1)).scaled_value(),
+            Some(-123.4)
+        );
+        assert_eq!(
+            entry(AnyValue::Unsigned(12), Some(3)).scaled_value(),
+            Some(12000.0)
+        );
+        assert_eq!(entry(AnyValue::Unsigned(12), None).scaled_value(), None);
+        assert_eq!(
+            entry(AnyValue::String(b"12".to_vec()), Some(0)).scaled_value(),
+            None
+        );
+    }
+}
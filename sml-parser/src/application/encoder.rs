@@ -0,0 +1,199 @@
+use crate::{
+    application::domain::{
+        GetListRequestBody, GetProcParameterRequestBody, OpenRequestBody, SmlRequest,
+    },
+    transport::crc16,
+};
+
+/// Encode a request as a single SML message (including its checksum)
+/// ```
+/// use hackdose_sml_parser::application::{domain::SmlRequest, encoder::encode_request};
+/// let message = encode_request(&[0x01, 0x02, 0x03, 0x04], &SmlRequest::CloseRequest);
+/// assert_eq!(message[..6], [0x76, 0x05, 0x01, 0x02, 0x03, 0x04]);
+/// ```
+pub fn encode_request(transaction_id: &[u8], request: &SmlRequest) -> Vec<u8> {
+    let mut message = vec![];
+    list(&mut message, 6);
+    string(&mut message, transaction_id);
+    unsigned_8(&mut message, 0); // groupNo
+    unsigned_8(&mut message, 0); // abortOnError: continue
+    list(&mut message, 2);
+    match request {
+        SmlRequest::OpenRequest(body) => open_request(&mut message, body),
+        SmlRequest::GetListRequest(body) => get_list_request(&mut message, body),
+        SmlRequest::GetProcParameterRequest(body) => get_proc_parameter_request(&mut message, body),
+        SmlRequest::CloseRequest => close_request(&mut message),
+    }
+    let crc = crc16(&message);
+    unsigned_16(&mut message, crc.swap_bytes());
+    message.push(0x00); // endOfSmlMsg
+    message
+}
+
+fn open_request(message: &mut Vec<u8>, body: &OpenRequestBody) {
+    unsigned_16(message, 0x0100);
+    list(message, 7);
+    none(message); // codepage
+    string(message, &body.client_id);
+    string(message, &body.req_file_id);
+    optional_string(message, &body.server_id);
+    optional_string(message, &body.username);
+    optional_string(message, &body.password);
+    none(message); // smlVersion
+}
+
+fn get_list_request(message: &mut Vec<u8>, body: &GetListRequestBody) {
+    unsigned_16(message, 0x0700);
+    list(message, 5);
+    string(message, &body.client_id);
+    optional_string(message, &body.server_id);
+    optional_string(message, &body.username);
+    optional_string(message, &body.password);
+    optional_string(message, &body.list_name);
+}
+
+fn get_proc_parameter_request(message: &mut Vec<u8>, body: &GetProcParameterRequestBody) {
+    unsigned_16(message, 0x0500);
+    list(message, 5);
+    optional_string(message, &body.server_id);
+    optional_string(message, &body.username);
+    optional_string(message, &body.password);
+    list(message, body.parameter_tree_path.len());
+    for entry in &body.parameter_tree_path {
+        string(message, entry);
+    }
+    none(message); // attribute
+}
+
+fn close_request(message: &mut Vec<u8>) {
+    unsigned_16(message, 0x0200);
+    list(message, 1);
+    none(message); // globalSignature
+}
+
+fn none(message: &mut Vec<u8>) {
+    message.push(0x01);
+}
+
+/// Writes a type-length field, using one byte per 4 bits of length
+fn type_length(message: &mut Vec<u8>, kind: u8, length: usize) {
+    let mut bytes = 1;
+    while length >> (4 * bytes) != 0 {
+        bytes += 1;
+    }
+    for i in (0..bytes).rev() {
+        let more = if i > 0 { 0x80 } else { 0x00 };
+        let kind = if i == bytes - 1 { kind } else { 0x00 };
+        message.push(more | kind | ((length >> (4 * i)) as u8 & 0x0f));
+    }
+}
+
+fn list(message: &mut Vec<u8>, length: usize) {
+    type_length(message, 0x70, length);
+}
+
+fn unsigned_8(message: &mut Vec<u8>, value: u8) {
+    message.push(0x62);
+    message.push(value);
+}
+
+fn unsigned_16(message: &mut Vec<u8>, value: u16) {
+    message.push(0x63);
+    message.extend_from_slice(&value.to_be_bytes());
+}
+
+fn string(message: &mut Vec<u8>, value: &[u8]) {
+    // the length in the type-length field includes the type-length field itself
+    let mut bytes = 1;
+    while (value.len() + bytes) >> (4 * bytes) != 0 {
+        bytes += 1;
+    }
+    type_length(message, 0x00, value.len() + bytes);
+    message.extend_from_slice(value);
+}
+
+fn optional_string(message: &mut Vec<u8>, value: &Option<Vec<u8>>) {
+    match value {
+        Some(value) => string(message, value),
+        None => none(message),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn encodes_close_request() {
+        let message = encode_request(&[0x03, 0x2b, 0x18, 0x11], &SmlRequest::CloseRequest);
+
+        assert_eq!(
+            message,
+            vec![
+                0x76, // list with 6 entries
+                0x05, 0x03, 0x2b, 0x18, 0x11, // transactionId
+                0x62, 0x00, // groupNo
+                0x62, 0x00, // abortOnError
+                0x72, // messageBody
+                0x63, 0x02, 0x00, // closeRequest
+                0x71, 0x01, // no signature
+                0x63, 0x26, 0x6c, // CRC
+                0x00, // end of message
+            ]
+        );
+    }
+
+    #[test]
+    pub fn encodes_long_strings() {
+        let mut message = vec![];
+        string(&mut message, &[0xaa; 17]);
+
+        assert_eq!(message[..2], [0x81, 0x03]);
+        assert_eq!(message.len(), 19);
+
+        let mut message = vec![];
+        string(&mut message, &[0xaa; 15]);
+
+        assert_eq!(message[..2], [0x81, 0x01]);
+        assert_eq!(message.len(), 17);
+
+        let mut message = vec![];
+        string(&mut message, &[0xaa; 300]);
+
+        assert_eq!(message[..3], [0x81, 0x82, 0x0f]);
+        assert_eq!(message.len(), 303);
+    }
+
+    #[test]
+    pub fn encodes_long_lists() {
+        let mut message = vec![];
+        list(&mut message, 15);
+        list(&mut message, 16);
+        list(&mut message, 300);
+
+        assert_eq!(message, [0x7f, 0xf1, 0x00, 0xf1, 0x82, 0x0c]);
+    }
+
+    #[test]
+    pub fn encodes_get_proc_parameter_request() {
+        let message = encode_request(
+            &[0x01],
+            &SmlRequest::GetProcParameterRequest(GetProcParameterRequestBody {
+                server_id: None,
+                username: None,
+                password: None,
+                parameter_tree_path: vec![vec![0x81, 0x81, 0xc7, 0x82, 0x01, 0xff]],
+            }),
+        );
+
+        assert_eq!(
+            message[..23],
+            [
+                0x76, 0x02, 0x01, 0x62, 0x00, 0x62, 0x00, 0x72, //
+                0x63, 0x05, 0x00, // getProcParameterRequest
+                0x75, 0x01, 0x01, 0x01, // serverId, username, password
+                0x71, 0x07, 0x81, 0x81, 0xc7, 0x82, 0x01, 0xff, // parameterTreePath
+            ]
+        );
+    }
+}
@@ -1,3 +1,6 @@
+pub mod decoder;
 pub mod domain;
+pub mod encoder;
 pub mod obis;
 pub mod parser;
+pub mod units;
@@ -4,6 +4,8 @@ use std::collections::HashMap;
 use enum_iterator::all;
 use lazy_static::lazy_static;
 
+use crate::application::units::{unit_from_symbol, unit_symbol};
+
 // HashMap<[u8;6], Obis>
 lazy_static! {
     static ref SOURCE: HashMap<&'static [u8], Obis> = {
@@ -36,8 +38,30 @@ macro_rules! generate_obis {
              }
 
              /// Find matching Obis number from six-digit number
+             ///
+             /// Numbers of other channels (value group B, e.g. the second gas meter
+             /// `7-2:3.0.0`) of abstract and M-Bus media (value group A 0 and 4 to 9)
+             /// are matched with channel 0 if there is no exact match. Electricity
+             /// numbers of other channels are not matched.
              pub fn from_number(number: &[u8]) -> Option<Self> {
-                SOURCE.get(number).map(|x| x.clone())
+                SOURCE
+                    .get(number)
+                    .or_else(|| match number {
+                        [a @ (0 | 4..=9), _, rest @ ..] => {
+                            SOURCE.get(&[&[*a, 0][..], rest].concat()[..])
+                        }
+                        _ => None,
+                    })
+                    .cloned()
+             }
+
+             /// Description including the unit, e.g. `Frequency [Hz]`
+             pub fn description(&self) -> &'static str {
+                 match self {
+                    $(
+                        Self:: $x => $l,
+                    )*
+                 }
              }
          }
     };
@@ -218,5 +242,106 @@ generate_obis! {
     (InstantaneousPowerFactorPhaseL1, &[1, 0, 33, 7, 0,255],"Instantaneous power factor in phase L1"),
     (InstantaneousPowerFactorPhaseL2, &[1, 0, 53, 7, 0,255],"Instantaneous power factor in phase L2"),
     (InstantaneousPowerFactorPhaseL3, &[1, 0, 73, 7, 0,255],"Instantaneous power factor in phase L3"),
-    (Frequency, &[1, 0, 14, 7, 0,255],"Frequency [Hz]")
+    (Frequency, &[1, 0, 14, 7, 0,255],"Frequency [Hz]"),
+    (FirmwareVersion, &[1, 0, 0, 2, 0,255],"Firmware version"),
+    (ServerId, &[1, 0, 0, 0, 9,255],"Server ID (device identification)"),
+    (ManufacturerIdentification, &[1, 0, 96, 50, 1,255],"Manufacturer identification"),
+    (ManufacturerSpecificIdentification, &[129, 129, 199, 130, 3,255],"Manufacturer identification (manufacturer specific)"),
+    (P1Version, &[1, 3, 0, 2, 8,255],"Version of the P1 output (DSMR)"),
+    (DateTime, &[0, 0, 1, 0, 0,255],"Date and time"),
+    (DeviceId, &[0, 0, 96, 1, 0,255],"Device ID"),
+    (EquipmentIdentifier, &[0, 0, 96, 1, 1,255],"Equipment identifier"),
+    (TariffIndicator, &[0, 0, 96, 14, 0,255],"Tariff indicator"),
+    (NumberOfPowerFailures, &[0, 0, 96, 7, 21,255],"Number of power failures in any phase"),
+    (NumberOfLongPowerFailures, &[0, 0, 96, 7, 9,255],"Number of long power failures in any phase"),
+    (TextMessage, &[0, 0, 96, 13, 0,255],"Text message"),
+    (MbusDeviceType, &[0, 0, 24, 1, 0,255],"Device type of the M-Bus device"),
+    (MbusMeterReading, &[0, 0, 24, 2, 1,255],"Last reading of the M-Bus device"),
+    (MbusMeterReadingUncorrected, &[0, 0, 24, 2, 3,255],"Last reading of the M-Bus device, not temperature corrected"),
+    (HeatCostAllocatorReading, &[4, 0, 1, 0, 0,255],"Heat cost allocator reading (unrated)"),
+    (CoolingEnergy, &[5, 0, 1, 0, 0,255],"Cooling energy [kWh]"),
+    (HeatEnergy, &[6, 0, 1, 0, 0,255],"Heat energy [kWh]"),
+    (HeatVolume, &[6, 0, 2, 0, 0,255],"Accumulated flow volume of the heat meter [m3]"),
+    (HeatPower, &[6, 0, 8, 0, 0,255],"Heat power [kW]"),
+    (HeatFlowRate, &[6, 0, 9, 0, 0,255],"Flow rate of the heat meter [m3/h]"),
+    (HeatFlowTemperature, &[6, 0, 10, 0, 0,255],"Flow temperature [°C]"),
+    (HeatReturnTemperature, &[6, 0, 11, 0, 0,255],"Return temperature [°C]"),
+    (HeatTemperatureDifference, &[6, 0, 12, 0, 0,255],"Temperature difference [K]"),
+    (GasVolume, &[7, 0, 3, 0, 0,255],"Gas volume at measuring conditions (meter reading) [m3]"),
+    (GasVolumeConverted, &[7, 0, 13, 0, 0,255],"Gas volume at base conditions [m3]"),
+    (GasPressure, &[7, 0, 42, 0, 0,255],"Absolute gas pressure [bar]"),
+    (GasFlowRate, &[7, 0, 43, 0, 0,255],"Gas flow rate at measuring conditions [m3/h]"),
+    (ColdWaterVolume, &[8, 0, 1, 0, 0,255],"Cold water volume [m3]"),
+    (ColdWaterFlowRate, &[8, 0, 2, 0, 0,255],"Cold water flow rate [m3/h]"),
+    (HotWaterVolume, &[9, 0, 1, 0, 0,255],"Hot water volume [m3]"),
+    (HotWaterFlowRate, &[9, 0, 2, 0, 0,255],"Hot water flow rate [m3/h]")
+}
+
+impl Obis {
+    /// Name without the unit, e.g. `Frequency`
+    pub fn name(&self) -> &'static str {
+        let description = self.description();
+        description
+            .rsplit_once(" [")
+            .map(|(name, _)| name)
+            .unwrap_or(description)
+    }
+
+    /// Unit symbol of the value after applying the scaler, e.g. `Wh`
+    ///
+    /// Meters send the value in the base unit, so this is `Wh` even though the
+    /// description names `kWh`. cf. [crate::application::units::unit_from_symbol]
+    pub fn unit(&self) -> Option<&'static str> {
+        let (_, unit) = self.description().rsplit_once(" [")?;
+        let (unit, _) = unit_from_symbol(unit.strip_suffix(']')?)?;
+        unit_symbol(unit)
+    }
+}
+
+/// Format six OBIS value groups as `A-B:C.D.E*F`, e.g. `1-0:1.8.0*255`
+///
+/// Object names of other lengths are formatted as hex.
+pub fn format_object_name(number: &[u8]) -> String {
+    match number {
+        [a, b, c, d, e, f] => format!("{}-{}:{}.{}.{}*{}", a, b, c, d, e, f),
+        _ => number.iter().map(|byte| format!("{:02x}", byte)).collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn finds_other_media() {
+        assert!(Obis::from_number(&[7, 0, 3, 0, 0, 255]) == Some(Obis::GasVolume));
+        assert!(Obis::from_number(&[8, 2, 1, 0, 0, 255]) == Some(Obis::ColdWaterVolume));
+        assert!(Obis::from_number(&[0, 1, 24, 2, 1, 255]) == Some(Obis::MbusMeterReading));
+        assert!(
+            Obis::from_number(&[129, 129, 199, 130, 3, 255])
+                == Some(Obis::ManufacturerSpecificIdentification)
+        );
+        assert!(Obis::from_number(&[7, 0, 99, 0, 0, 255]).is_none());
+        assert!(Obis::from_number(&[1, 1, 16, 7, 0, 255]).is_none());
+    }
+
+    #[test]
+    pub fn splits_name_and_unit() {
+        assert_eq!(Obis::HeatFlowTemperature.name(), "Flow temperature");
+        assert_eq!(Obis::HeatFlowTemperature.unit(), Some("°C"));
+        assert_eq!(
+            Obis::SumActiveInstantaneousPower.name(),
+            "Sum active instantaneous power (A+ - A-)"
+        );
+        assert_eq!(Obis::SumActiveInstantaneousPower.unit(), Some("W"));
+        assert_eq!(Obis::PositiveActiveEnergyTotal.unit(), Some("Wh"));
+        assert_eq!(Obis::HeatFlowRate.unit(), Some("m3/h"));
+        assert_eq!(Obis::DeviceId.unit(), None);
+    }
+
+    #[test]
+    pub fn formats_object_names() {
+        assert_eq!(format_object_name(&[1, 0, 16, 7, 0, 255]), "1-0:16.7.0*255");
+        assert_eq!(format_object_name(&[0x81, 0x81]), "8181");
+    }
 }
@@ -1,9 +1,11 @@
 use byteorder::{BigEndian, ReadBytesExt};
 use std::io::Cursor;
 
+use crate::application::decoder::{decode_body, decode_message};
 use crate::application::domain::{
-    AnyValue, GetListResponseBody, GetOpenResponseBody, SmlListEntry, SmlMessageEnvelope,
-    SmlMessages,
+    AnyValue, AttentionResponseBody, GetCloseResponseBody, GetListResponseBody,
+    GetOpenResponseBody, GetProcParameterResponseBody, SmlListEntry, SmlMessageBody,
+    SmlMessageEnvelope, SmlMessages, SmlProcParValue, SmlTree,
 };
 
 #[non_exhaustive]
@@ -16,11 +18,25 @@ pub type ParseResult<T> = Result<T, ParseError>;
 
 /// Parse the body of an SML message (omitting header and footer)
 pub fn parse_body(input: &[u8]) -> ParseResult<SmlMessages> {
-    sml_parser::sml_body(input).map_err(|_| ParseError::Unknown)
+    decode_body(input)
 }
 
 /// Parse the whole SML message
 pub fn parse_message(input: &[u8]) -> ParseResult<SmlMessages> {
+    decode_message(input)
+}
+
+/// Parse the body of an SML message with the `peg` grammar
+///
+/// Superseded by [parse_body], kept for comparison.
+pub fn parse_body_peg(input: &[u8]) -> ParseResult<SmlMessages> {
+    sml_parser::sml_body(input).map_err(|_| ParseError::Unknown)
+}
+
+/// Parse the whole SML message with the `peg` grammar
+///
+/// Superseded by [parse_message], kept for comparison.
+pub fn parse_message_peg(input: &[u8]) -> ParseResult<SmlMessages> {
     sml_parser::sml_messages(input).map_err(|_| ParseError::Unknown)
 }
 
@@ -28,52 +44,88 @@ peg::parser! {
     grammar sml_parser<'a>() for [u8] {
 
         pub (crate) rule sml_body() -> SmlMessages
-            = a:(sml_message_envelope())* { SmlMessages { messages: a } }
+            = a:(sml_message_envelope())* padding() { SmlMessages { messages: a } }
 
         pub (crate) rule sml_messages() -> SmlMessages
-            = header() a:(sml_message_envelope())* footer() { SmlMessages { messages: a } }
+            = header() a:(sml_message_envelope())* padding() footer() { SmlMessages { messages: a } }
 
         rule header() -> ()
             = ([0x1b] [0x1b] [0x1b] [0x1b] [0x01] [0x01] [0x01] [0x01])
 
+        rule padding()
+            = [0x00]*<0,3>
+
         rule footer() -> ()
             = ([0x1b] [0x1b] [0x1b] [0x1b] [0x1a] [0..=255]*<3,3>)
 
         rule sml_message_envelope() -> SmlMessageEnvelope
-            = [0x76] transaction_id() group_no() abort_on_error() a:sml_message_body() crc() end_of_message() { a }
+            = [0x76] transaction_id:transaction_id() group_no:group_no() abort_on_error:abort_on_error() body:sml_message_body() crc:crc() end_of_message() { SmlMessageEnvelope { transaction_id, group_no, abort_on_error, body, crc } }
 
         rule end_of_message() = [0x00]
-        rule crc() = [0x63] any_number() any_number()
+        rule crc() -> u16 = crc:unsigned_16() { crc.swap_bytes() }
 
-        rule sml_message_body() -> SmlMessageEnvelope
-            = get_open_response() / get_list_response() / get_close_response() // and more types
+        rule sml_message_body() -> SmlMessageBody
+            = get_open_response() / get_list_response() / get_close_response() / get_proc_parameter_response() / attention_response() // and more types
 
-        rule get_open_response() -> SmlMessageEnvelope
-            = ([0x72] [0x63] [0x01] [0x01]) [0x76] a: get_open_response_content() { SmlMessageEnvelope::GetOpenResponse(a)}
+        rule get_open_response() -> SmlMessageBody
+            = ([0x72] [0x63] [0x01] [0x01]) [0x76] a: get_open_response_content() { SmlMessageBody::GetOpenResponse(a)}
 
         rule get_open_response_content() -> GetOpenResponseBody
-            = [0x01] [0x01] req_file_id:string() server_id:string() [0x01] [0x01] { GetOpenResponseBody { server_id: server_id, req_file_id: req_file_id }}
+            = codepage:optional_string() client_id:optional_string() req_file_id:string() server_id:string() ref_time:optional_sml_time() sml_version:optional_unsigned_8() { GetOpenResponseBody { codepage, client_id, server_id, req_file_id, ref_time, sml_version }}
 
-        rule get_close_response() -> SmlMessageEnvelope
-            = ([0x72] [0x63] [0x02] [0x01]) [0x71] get_close_response_content() { SmlMessageEnvelope::GetCloseResponse}
+        rule optional_sml_time() -> Option<u32>
+            = (t:sml_time() { Some(t) }) / ([0x01] { None })
 
-        rule get_close_response_content()
-            = [0x01]
+        rule sml_time() -> u32
+            = [0x72] [0x62] [0x01..=0x02] t:unsigned_32() { t }
 
-        rule get_list_response() -> SmlMessageEnvelope
-            = ([0x72] [0x63] [0x07] [0x01]) [0x77] a: get_list_response_content() { SmlMessageEnvelope::GetListResponse(a)}
+        rule get_close_response() -> SmlMessageBody
+            = ([0x72] [0x63] [0x02] [0x01]) [0x71] a: get_close_response_content() { SmlMessageBody::GetCloseResponse(a)}
 
-        rule list_signature()
-            = [0x01]
+        rule get_close_response_content() -> GetCloseResponseBody
+            = global_signature:optional_string() { GetCloseResponseBody { global_signature }}
 
-        rule act_gateway_time()
-            = ([0x01]*<0,1>)
+        rule get_list_response() -> SmlMessageBody
+            = ([0x72] [0x63] [0x07] [0x01]) [0x77] a: get_list_response_content() { SmlMessageBody::GetListResponse(a)}
 
-        rule get_list_response_content() -> GetListResponseBody
-            = [0x01] server_id:string() list_name:string() obscure_prefix_in_get_list_response() value_list:list_sml_value() list_signature() act_gateway_time() { GetListResponseBody { server_id: server_id, list_name: list_name, value_list: value_list }}
+        rule get_proc_parameter_response() -> SmlMessageBody
+            = ([0x72] [0x63] [0x05] [0x01]) [0x73] a: get_proc_parameter_response_content() { SmlMessageBody::GetProcParameterResponse(a)}
+
+        rule get_proc_parameter_response_content() -> GetProcParameterResponseBody
+            = server_id:string() parameter_tree_path:tree_path() parameter_tree:sml_tree() { GetProcParameterResponseBody { server_id, parameter_tree_path, parameter_tree }}
+
+        rule tree_path() -> Vec<Vec<u8>>
+            = l:list_length() n:(string())*<{l}> { n }
+
+        rule sml_tree() -> SmlTree
+            = [0x73] parameter_name:string() parameter_value:optional_proc_par_value() child_list:child_list() { SmlTree { parameter_name, parameter_value, child_list }}
+
+        rule child_list() -> Vec<SmlTree>
+            = ([0x01] { vec![] }) / (l:list_length() n:(sml_tree())*<{l}> { n })
+
+        rule optional_proc_par_value() -> Option<SmlProcParValue>
+            = ([0x72] [0x62] [0x01] v:value() { Some(SmlProcParValue::Value(v)) }) / ([0x72] [0x62] [0x04] t:sml_time() { Some(SmlProcParValue::Time(t)) }) / ([0x01] { None })
 
-        rule obscure_prefix_in_get_list_response()
-            = [0x72] [0x62] [0..=255] [0x65] [0..=255] [0..=255] [0..=255] [0..=255]
+        rule list_length() -> usize
+            = l:[0x70..=0x7f] { (l & 0x0f) as usize }
+
+        rule attention_response() -> SmlMessageBody
+            = ([0x72] [0x63] [0xff] [0x01]) [0x74] a: attention_response_content() { SmlMessageBody::AttentionResponse(a)}
+
+        rule attention_response_content() -> AttentionResponseBody
+            = server_id:string() attention_number:string() attention_message:string() attention_details() { AttentionResponseBody { server_id, attention_number, attention_message }}
+
+        rule attention_details()
+            = (sml_tree() {}) / [0x01]
+
+        rule list_signature() -> Option<Vec<u8>>
+            = optional_string()
+
+        rule act_gateway_time() -> Option<u32>
+            = t:(optional_sml_time())? { t.flatten() }
+
+        rule get_list_response_content() -> GetListResponseBody
+            = client_id:optional_string() server_id:string() list_name:string() act_sensor_time:optional_sml_time() value_list:list_sml_value() list_signature:list_signature() act_gateway_time:act_gateway_time() { GetListResponseBody { client_id, server_id, list_name, act_sensor_time, value_list, list_signature, act_gateway_time }}
 
         rule list_sml_value1() -> Vec<SmlListEntry> = [0x71] n:(single_sml_value())*<1,1> { n }
         rule list_sml_value2() -> Vec<SmlListEntry> = [0x72] n:(single_sml_value())*<2,2> { n }
@@ -92,7 +144,7 @@ peg::parser! {
         rule list_sml_value15() -> Vec<SmlListEntry> = [0x7f] n:(single_sml_value())*<15,15> { n }
         rule list_sml_value() -> Vec<SmlListEntry> = list_sml_value1()/list_sml_value1()/list_sml_value2()/list_sml_value3()/list_sml_value4()/list_sml_value5()/list_sml_value6()/list_sml_value7()/list_sml_value8()/list_sml_value9()/list_sml_value10()/list_sml_value11()/list_sml_value12()/list_sml_value13()/list_sml_value14()/list_sml_value15()
         rule single_sml_value() -> SmlListEntry
-            = [0x77] obj_name: string() status: optional_unsigned_32() val_time: string() unit: (optional_unsigned_8()) scaler: scaler() value: value() sml_value_signature() { SmlListEntry { object_name: obj_name, status: status, value_time: val_time, unit, scaler: scaler, value: value }}
+            = [0x77] obj_name: string() status: optional_unsigned_32() val_time: string() unit: (optional_unsigned_8()) scaler: scaler() value: value() sml_value_signature() { SmlListEntry { object_name: obj_name, status, value_time: val_time, unit, scaler, value }}
 
         rule scaler() -> Option<i8>
             = optional_signed_8()
@@ -107,14 +159,14 @@ peg::parser! {
             (v:string() { AnyValue::String(v)}) / (v:unsigned_16() { AnyValue::Unsigned(v as usize)}) / (v:signed_16() { AnyValue::Signed(v as isize)}) /
             (v:signed_64() { AnyValue::Signed(v as isize)}) / (v:signed_32() { AnyValue::Signed(v as isize)}) / (v:unsigned_32() { AnyValue::Unsigned(v as usize)})
 
-        rule transaction_id()
-            =(string4())
+        rule transaction_id() -> Vec<u8>
+            = string()
 
-        rule group_no()
-            =([0x62] any_number())
+        rule group_no() -> u8
+            = unsigned_8()
 
-        rule abort_on_error()
-            =([0x62] [0x00])
+        rule abort_on_error() -> u8
+            = unsigned_8()
 
         rule message_checksum()
             = (any_number() any_number() any_number())
@@ -125,32 +177,28 @@ peg::parser! {
         rule unsigned_16() -> u16
             = [0x63] n:$([0..=255]*<2,2>) { {
                     let mut rdr = Cursor::new(n);
-                    let res = rdr.read_u16::<BigEndian>().unwrap();
-                    res
+                    rdr.read_u16::<BigEndian>().unwrap()
                 }
             }
 
         rule unsigned_32() -> u32
             = [0x65] n:$([0..=255]*<4,4>) { {
                     let mut rdr = Cursor::new(n);
-                    let res = rdr.read_u32::<BigEndian>().unwrap();
-                    res
+                    rdr.read_u32::<BigEndian>().unwrap()
                 }
             }
 
         rule signed_32() -> i32
             = [0x55] n:$([0..=255]*<4,4>) { {
                     let mut rdr = Cursor::new(n);
-                    let res = rdr.read_i32::<BigEndian>().unwrap();
-                    res
+                    rdr.read_i32::<BigEndian>().unwrap()
                 }
             }
 
         rule unsigned_64() -> u64
             = [0x69] n:$([0..=255]*<8,8>) { {
                     let mut rdr = Cursor::new(n);
-                    let res = rdr.read_u64::<BigEndian>().unwrap();
-                    res
+                    rdr.read_u64::<BigEndian>().unwrap()
                 }
             }
 
@@ -158,32 +206,28 @@ peg::parser! {
         rule signed_64() -> i64
             = [0x59] n:$([0..=255]*<8,8>) { {
                     let mut rdr = Cursor::new(n);
-                    let res = rdr.read_i64::<BigEndian>().unwrap();
-                    res
+                    rdr.read_i64::<BigEndian>().unwrap()
                 }
             }
 
         pub rule signed_16() -> i16
             = [0x53] n:$([0..=255]*<2,2>) { {
                     let mut rdr = Cursor::new(n);
-                    let res = rdr.read_i16::<BigEndian>().unwrap();
-                    res
+                    rdr.read_i16::<BigEndian>().unwrap()
                 }
             }
 
         rule signed_8() -> i8
             = [0x52] n:$([0..=255]*<1,1>) { {
                     let mut rdr = Cursor::new(n);
-                    let res = rdr.read_i8().unwrap();
-                    res
+                    rdr.read_i8().unwrap()
                 }
             }
 
         rule unsigned_8() -> u8
             = [0x62] n:$([0..=255]*<1,1>) { {
                     let mut rdr = Cursor::new(n);
-                    let res = rdr.read_u8().unwrap();
-                    res
+                    rdr.read_u8().unwrap()
                 }
             }
 
@@ -199,6 +243,9 @@ peg::parser! {
         rule optional_unsigned_8() -> Option<u8>
             = (v:unsigned_8() { Some(v) }) / ( [0x01] { None })
 
+        rule optional_string() -> Option<Vec<u8>>
+            = ([0x01] { None }) / (v:string() { Some(v) })
+
         rule optional_unsigned_32() -> Option<u32>
             = (v:unsigned_32() { Some(v) }) / ( [0x01] { None })
 
@@ -259,6 +306,7 @@ peg::parser! {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::transport::crc16;
     #[test]
     pub fn open() {
         //
@@ -290,10 +338,20 @@ mod test {
         assert_eq!(
             result,
             Ok(SmlMessages {
-                messages: vec![SmlMessageEnvelope::GetOpenResponse(GetOpenResponseBody {
-                    server_id: vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a],
-                    req_file_id: vec![0x04, 0x03, 0x02, 0x01]
-                })]
+                messages: vec![SmlMessageEnvelope {
+                    transaction_id: vec![0x03, 0x2b, 0x18, 0x0f],
+                    group_no: 0,
+                    abort_on_error: 0,
+                    body: SmlMessageBody::GetOpenResponse(GetOpenResponseBody {
+                        codepage: None,
+                        client_id: None,
+                        server_id: vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a],
+                        req_file_id: vec![0x04, 0x03, 0x02, 0x01],
+                        ref_time: None,
+                        sml_version: None,
+                    }),
+                    crc: 0x0049
+                }]
             })
         )
     }
@@ -348,32 +406,120 @@ mod test {
         assert_eq!(
             result,
             Ok(SmlMessages {
-                messages: vec![SmlMessageEnvelope::GetListResponse(GetListResponseBody {
-                    server_id: vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
-                    list_name: vec![1, 0, 98, 10, 255, 255],
-                    value_list: vec![
-                        SmlListEntry {
-                            object_name: vec![129, 129, 199, 130, 3, 255],
-                            status: None,
-                            value_time: vec![],
-                            unit: None,
-                            scaler: None,
-                            value: AnyValue::String(vec![73, 83, 75])
-                        },
-                        SmlListEntry {
-                            object_name: vec![1, 0, 1, 8, 0, 255],
-                            status: Some(386),
-                            value_time: vec![],
-                            unit: Some(30),
-                            scaler: Some(-1),
-                            value: AnyValue::Signed(0)
-                        }
-                    ]
-                })]
+                messages: vec![SmlMessageEnvelope {
+                    transaction_id: vec![0x01, 0xD3, 0xD7, 0xBB],
+                    group_no: 0,
+                    abort_on_error: 0,
+                    body: SmlMessageBody::GetListResponse(GetListResponseBody {
+                        client_id: None,
+                        server_id: vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
+                        list_name: vec![1, 0, 98, 10, 255, 255],
+                        act_sensor_time: Some(0x018A4D15),
+                        value_list: vec![
+                            SmlListEntry {
+                                object_name: vec![129, 129, 199, 130, 3, 255],
+                                status: None,
+                                value_time: vec![],
+                                unit: None,
+                                scaler: None,
+                                value: AnyValue::String(vec![73, 83, 75])
+                            },
+                            SmlListEntry {
+                                object_name: vec![1, 0, 1, 8, 0, 255],
+                                status: Some(386),
+                                value_time: vec![],
+                                unit: Some(30),
+                                scaler: Some(-1),
+                                value: AnyValue::Signed(0)
+                            }
+                        ],
+                        list_signature: None,
+                        act_gateway_time: None,
+                    }),
+                    crc: 0x12c6
+                }]
+            })
+        )
+    }
+
+    #[test]
+    pub fn keeps_optional_fields_of_open_response() {
+        let example_open = vec![
+            0x76, //
+            /*      */ 0x05, 0x03, 0x2b, 0x18, 0x0f, // transactionId:
+            /*      */ 0x62, 0x02, // groupNo:
+            /*      */ 0x62, 0x01, //abortOnError:
+            /*      */ 0x72, // messageBody: list with 2 entries
+            /*          */ 0x63, 0x01, 0x01, // getOpenResponse:
+            /*          */ 0x76, // list with 6 entries
+            /*              */ 0x02, 0x31, // codepage
+            /*              */ 0x03, 0xaa, 0xbb, // clientId
+            /*              */ 0x05, 0x04, 0x03, 0x02, 0x01, // reqFileId:
+            /*              */ 0x03, 0x01, 0x02, // serverId
+            /*              */ 0x72, 0x62, 0x01, 0x65, 0x00, 0x00, 0x01, 0x00, // refTime
+            /*              */ 0x62, 0x01, // smlVersion
+            /*          */ 0x63, 0x49, 0x00, // CRC checksum of this message
+            /*          */ 0x00, // end of this
+            /* */ 0x00, 0x00, // padding
+        ];
+
+        let result = sml_parser::sml_body(&example_open);
+
+        assert_eq!(
+            result,
+            Ok(SmlMessages {
+                messages: vec![SmlMessageEnvelope {
+                    transaction_id: vec![0x03, 0x2b, 0x18, 0x0f],
+                    group_no: 2,
+                    abort_on_error: 1,
+                    body: SmlMessageBody::GetOpenResponse(GetOpenResponseBody {
+                        codepage: Some(vec![0x31]),
+                        client_id: Some(vec![0xaa, 0xbb]),
+                        server_id: vec![0x01, 0x02],
+                        req_file_id: vec![0x04, 0x03, 0x02, 0x01],
+                        ref_time: Some(256),
+                        sml_version: Some(1),
+                    }),
+                    crc: 0x0049
+                }]
             })
         )
     }
 
+    #[test]
+    pub fn keeps_list_signature() {
+        let example_list = vec![
+            0x76, 0x05, 0x01, 0xD3, 0xD7, 0xBB, 0x62, 0x00, 0x62, 0x00, 0x72, //
+            /*          */ 0x63, 0x07, 0x01, // getListResponse
+            /*          */ 0x77, //
+            /*              */ 0x01, // clientId / optional
+            /*              */ 0x03, 0x01, 0x02, // serverId
+            /*              */ 0x01, // listName
+            /*              */ 0x01, // actSensorTime / optional
+            /*              */ 0x71, // valList
+            /*                  */ 0x77, 0x07, 0x01, 0x00, 0x10, 0x07, 0x00, 0xFF, 0x01, 0x01,
+            0x62, 0x1B, 0x52, 0x00, 0x53, 0x01, 0x2c, 0x01, // SML_ListEntry
+            /*                  */ 0x05, 0xde, 0xad, 0xbe,
+            0xef, // listSignature / optional
+            /*                  */ 0x72, 0x62, 0x02, 0x65, 0x63, 0xa0, 0x00,
+            0x00, // actGatewayTime
+            /*      */ 0x63, 0xC6, 0x12, // crc
+            /*      */ 0x00, // end of message
+        ];
+
+        let result = sml_parser::sml_body(&example_list).unwrap();
+
+        match &result.messages[0].body {
+            SmlMessageBody::GetListResponse(body) => {
+                assert_eq!(body.list_signature, Some(vec![0xde, 0xad, 0xbe, 0xef]));
+                assert_eq!(body.act_sensor_time, None);
+                assert_eq!(body.act_gateway_time, Some(0x63a00000));
+                assert_eq!(body.value_list[0].value, AnyValue::Signed(300));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
     #[test]
     pub fn get_close_response() {
         let example_close = vec![
@@ -397,9 +543,18 @@ mod test {
         assert_eq!(
             result,
             Ok(SmlMessages {
-                messages: vec![SmlMessageEnvelope::GetCloseResponse]
+                messages: vec![SmlMessageEnvelope {
+                    transaction_id: vec![0x03, 0x2b, 0x18, 0x11],
+                    group_no: 0,
+                    abort_on_error: 0,
+                    body: SmlMessageBody::GetCloseResponse(GetCloseResponseBody {
+                        global_signature: None
+                    }),
+                    crc: 0x36fa
+                }]
             })
-        )
+        );
+        assert_eq!(crc16(&example_close[8..24]), 0x36fa);
     }
 
     // From here on: Generate "generic types", should be solved by build scripts in the future
@@ -0,0 +1,113 @@
+//! Units as used in SML list entries (DLMS unit codes, cf. IEC 62056-62)
+
+pub const DAY: u8 = 4;
+pub const HOUR: u8 = 5;
+pub const MINUTE: u8 = 6;
+pub const SECOND: u8 = 7;
+pub const DEGREE_CELSIUS: u8 = 9;
+pub const CUBIC_METRE: u8 = 13;
+pub const CUBIC_METRE_PER_HOUR: u8 = 15;
+pub const KILOGRAM: u8 = 20;
+pub const BAR: u8 = 24;
+pub const JOULE: u8 = 25;
+pub const JOULE_PER_HOUR: u8 = 26;
+pub const WATT: u8 = 27;
+pub const VOLT_AMPERE: u8 = 28;
+pub const VAR: u8 = 29;
+pub const WATT_HOUR: u8 = 30;
+pub const VOLT_AMPERE_HOUR: u8 = 31;
+pub const VAR_HOUR: u8 = 32;
+pub const AMPERE: u8 = 33;
+pub const VOLT: u8 = 35;
+pub const HERTZ: u8 = 44;
+pub const KELVIN: u8 = 52;
+
+/// Find the DLMS unit code for a unit symbol as used in ASCII protocols
+///
+/// Returns the unit code and the power of ten which has to be added to the scaler,
+/// e.g. `kWh` is mapped to Wh with an additional scaler of 3.
+/// ```
+/// use hackdose_sml_parser::application::units::{unit_from_symbol, WATT_HOUR};
+/// assert_eq!(unit_from_symbol("kWh"), Some((WATT_HOUR, 3)));
+/// ```
+pub fn unit_from_symbol(symbol: &str) -> Option<(u8, i8)> {
+    let unit = match symbol {
+        "a" => (1, 0),
+        "mo" => (2, 0),
+        "wk" => (3, 0),
+        "d" => (DAY, 0),
+        "h" => (HOUR, 0),
+        "min" => (MINUTE, 0),
+        "s" => (SECOND, 0),
+        "°C" => (DEGREE_CELSIUS, 0),
+        "m3" | "m³" => (CUBIC_METRE, 0),
+        "m3/h" | "m³/h" => (CUBIC_METRE_PER_HOUR, 0),
+        "l" => (19, 0),
+        "kg" => (KILOGRAM, 0),
+        "bar" => (BAR, 0),
+        "J" => (JOULE, 0),
+        "kJ" => (JOULE, 3),
+        "MJ" => (JOULE, 6),
+        "GJ" => (JOULE, 9),
+        "W" => (WATT, 0),
+        "kW" => (WATT, 3),
+        "MW" => (WATT, 6),
+        "VA" => (VOLT_AMPERE, 0),
+        "kVA" => (VOLT_AMPERE, 3),
+        "var" => (VAR, 0),
+        "kvar" => (VAR, 3),
+        "Wh" => (WATT_HOUR, 0),
+        "kWh" => (WATT_HOUR, 3),
+        "MWh" => (WATT_HOUR, 6),
+        "VAh" => (VOLT_AMPERE_HOUR, 0),
+        "kVAh" => (VOLT_AMPERE_HOUR, 3),
+        "varh" => (VAR_HOUR, 0),
+        "kvarh" => (VAR_HOUR, 3),
+        "A" => (AMPERE, 0),
+        "V" => (VOLT, 0),
+        "kV" => (VOLT, 3),
+        "Hz" => (HERTZ, 0),
+        "K" => (KELVIN, 0),
+        "%" => (56, 0),
+        _ => return None,
+    };
+    Some(unit)
+}
+
+/// Symbol of a DLMS unit code, e.g. `Wh` for [WATT_HOUR]
+/// ```
+/// use hackdose_sml_parser::application::units::{unit_symbol, WATT_HOUR};
+/// assert_eq!(unit_symbol(WATT_HOUR), Some("Wh"));
+/// ```
+pub fn unit_symbol(unit: u8) -> Option<&'static str> {
+    let symbol = match unit {
+        1 => "a",
+        2 => "mo",
+        3 => "wk",
+        DAY => "d",
+        HOUR => "h",
+        MINUTE => "min",
+        SECOND => "s",
+        DEGREE_CELSIUS => "°C",
+        CUBIC_METRE => "m3",
+        CUBIC_METRE_PER_HOUR => "m3/h",
+        19 => "l",
+        KILOGRAM => "kg",
+        BAR => "bar",
+        JOULE => "J",
+        JOULE_PER_HOUR => "J/h",
+        WATT => "W",
+        VOLT_AMPERE => "VA",
+        VAR => "var",
+        WATT_HOUR => "Wh",
+        VOLT_AMPERE_HOUR => "VAh",
+        VAR_HOUR => "varh",
+        AMPERE => "A",
+        VOLT => "V",
+        HERTZ => "Hz",
+        KELVIN => "K",
+        56 => "%",
+        _ => return None,
+    };
+    Some(symbol)
+}
@@ -0,0 +1,437 @@
+use std::{io, time::Duration};
+
+#[cfg(feature = "tokio")]
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time::Instant,
+};
+
+/// Magic bytes and format version at the start of every capture
+static CAPTURE_HEADER: &[u8] = &[b'H', b'D', b'C', b'A', b'P', 0x01];
+
+#[cfg(feature = "tokio")]
+const BUFFER_SIZE: usize = 512;
+
+/// Longest chunk accepted in a capture, guards against corrupt length fields
+#[cfg(feature = "tokio")]
+const MAX_RECORD_LENGTH: usize = 64 * 1024;
+
+/// A chunk of raw bytes as it was read from the meter
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct CaptureRecord {
+    /// time elapsed since the start of the recording
+    pub offset: Duration,
+    pub data: Vec<u8>,
+}
+
+/// Speed at which a capture is replayed
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ReplaySpeed {
+    /// keep the timing of the recording
+    Original,
+    /// replay faster by the given factor (e.g. `10.0` replays ten times as fast)
+    Accelerated(f64),
+    /// emit all chunks as fast as they are consumed
+    Unthrottled,
+}
+
+impl ReplaySpeed {
+    /// Replay faster by `factor`, `None` unless it is finite and positive
+    pub fn accelerated(factor: f64) -> Option<Self> {
+        (factor.is_finite() && factor > 0.0).then_some(ReplaySpeed::Accelerated(factor))
+    }
+
+    /// Time after the start at which a chunk is due, `None` to emit it at once
+    ///
+    /// Invalid factors of `Accelerated` replay without delay, tiny factors saturate.
+    #[cfg(feature = "tokio")]
+    fn due(&self, offset: Duration) -> Option<Duration> {
+        match *self {
+            ReplaySpeed::Original => Some(offset),
+            ReplaySpeed::Accelerated(factor) if factor > 0.0 => Some(
+                Duration::try_from_secs_f64(offset.as_secs_f64() / factor).unwrap_or(Duration::MAX),
+            ),
+            ReplaySpeed::Accelerated(_) | ReplaySpeed::Unthrottled => None,
+        }
+    }
+}
+
+/// Writes a capture, i.e. the header followed by timestamped chunks
+#[cfg(feature = "tokio")]
+pub struct CaptureWriter<W> {
+    writer: W,
+    start: Instant,
+}
+
+#[cfg(feature = "tokio")]
+impl<W: AsyncWrite + Unpin> CaptureWriter<W> {
+    /// Write the capture header and start the clock for the chunk offsets
+    pub async fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(CAPTURE_HEADER).await?;
+        Ok(Self {
+            writer,
+            start: Instant::now(),
+        })
+    }
+
+    /// Record a chunk which has been read just now
+    pub async fn record(&mut self, data: &[u8]) -> io::Result<()> {
+        let record = CaptureRecord {
+            offset: self.start.elapsed(),
+            data: data.to_vec(),
+        };
+        self.write_record(&record).await
+    }
+
+    /// Record a chunk with an explicit offset
+    pub async fn write_record(&mut self, record: &CaptureRecord) -> io::Result<()> {
+        if record.data.len() > MAX_RECORD_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "capture record too long",
+            ));
+        }
+        self.writer
+            .write_u64(record.offset.as_micros() as u64)
+            .await?;
+        self.writer.write_u32(record.data.len() as u32).await?;
+        self.writer.write_all(&record.data).await?;
+        self.writer.flush().await
+    }
+}
+
+/// Reads the chunks of a capture one by one
+#[cfg(feature = "tokio")]
+pub struct CaptureReader<R> {
+    reader: R,
+}
+
+#[cfg(feature = "tokio")]
+impl<R: AsyncRead + Unpin> CaptureReader<R> {
+    /// Check the capture header
+    pub async fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 6];
+        reader.read_exact(&mut header).await?;
+        if header != CAPTURE_HEADER {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a hackdose capture",
+            ));
+        }
+        Ok(Self { reader })
+    }
+
+    /// Read the next chunk, `None` at the end of the capture
+    pub async fn next_record(&mut self) -> io::Result<Option<CaptureRecord>> {
+        let mut record_header = [0; 12];
+        let mut read = 0;
+        while read < record_header.len() {
+            let n = self.reader.read(&mut record_header[read..]).await?;
+            if n == 0 {
+                if read == 0 {
+                    return Ok(None);
+                }
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            read += n;
+        }
+        let (offset, length) = record_header.split_at(8);
+        let offset = u64::from_be_bytes(offset.try_into().unwrap());
+        let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+        if length > MAX_RECORD_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "capture record too long",
+            ));
+        }
+
+        let mut data = vec![0; length];
+        self.reader.read_exact(&mut data).await?;
+        Ok(Some(CaptureRecord {
+            offset: Duration::from_micros(offset),
+            data,
+        }))
+    }
+}
+
+/// Forward a byte stream while recording it into a capture
+///
+/// The returned reader yields the same bytes as `stream`. If writing the capture fails,
+/// recording stops but the bytes are still forwarded.
+/// ```no_run
+/// use hackdose_sml_parser::capture::record_capture;
+/// use hackdose_sml_parser::message_stream::sml_message_stream;
+///
+/// # async fn run(serial: tokio::io::DuplexStream, file: tokio::io::DuplexStream) {
+/// let message_stream = sml_message_stream(record_capture(serial, file));
+/// # }
+/// ```
+#[cfg(feature = "tokio")]
+pub fn record_capture(
+    mut stream: impl AsyncRead + Unpin + Send + 'static,
+    capture: impl AsyncWrite + Unpin + Send + 'static,
+) -> impl AsyncRead {
+    let (mut tx, rx) = tokio::io::duplex(BUFFER_SIZE);
+
+    tokio::spawn(async move {
+        let mut buf = [0; BUFFER_SIZE];
+        let mut writer = CaptureWriter::new(capture).await.ok();
+        while let Ok(n) = stream.read(&mut buf).await {
+            if n == 0 {
+                break;
+            }
+            if let Some(w) = writer.as_mut() {
+                if w.record(&buf[..n]).await.is_err() {
+                    writer = None;
+                }
+            }
+            if tx.write_all(&buf[..n]).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+/// Replay a capture as a byte stream
+///
+/// The returned reader ends when the capture ends or turns out to be corrupt.
+#[cfg(feature = "tokio")]
+pub fn replay_capture(
+    capture: impl AsyncRead + Unpin + Send + 'static,
+    speed: ReplaySpeed,
+) -> impl AsyncRead {
+    let (mut tx, rx) = tokio::io::duplex(BUFFER_SIZE);
+
+    tokio::spawn(async move {
+        let mut reader = match CaptureReader::new(capture).await {
+            Ok(reader) => reader,
+            Err(_) => return,
+        };
+        let start = Instant::now();
+        while let Ok(Some(record)) = reader.next_record().await {
+            if let Some(due) = speed.due(record.offset) {
+                tokio::time::sleep(due.saturating_sub(start.elapsed())).await;
+            }
+            if tx.write_all(&record.data).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+/// Split a capture held in memory into its chunks
+pub fn parse_capture(capture: &[u8]) -> io::Result<Vec<CaptureRecord>> {
+    let mut rest = capture
+        .strip_prefix(CAPTURE_HEADER)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a hackdose capture"))?;
+    let mut records = vec![];
+    while !rest.is_empty() {
+        if rest.len() < 12 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let (record_header, remaining) = rest.split_at(12);
+        let (offset, length) = record_header.split_at(8);
+        let offset = u64::from_be_bytes(offset.try_into().unwrap());
+        let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+        if remaining.len() < length {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let (data, remaining) = remaining.split_at(length);
+        records.push(CaptureRecord {
+            offset: Duration::from_micros(offset),
+            data: data.to_vec(),
+        });
+        rest = remaining;
+    }
+    Ok(records)
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod test {
+    use super::*;
+
+    fn records() -> Vec<CaptureRecord> {
+        vec![
+            CaptureRecord {
+                offset: Duration::from_millis(0),
+                data: vec![0x1b, 0x1b, 0x1b, 0x1b],
+            },
+            CaptureRecord {
+                offset: Duration::from_millis(1500),
+                data: vec![0x01, 0x01, 0x01, 0x01, 0x76],
+            },
+            CaptureRecord {
+                offset: Duration::from_millis(3000),
+                data: vec![],
+            },
+        ]
+    }
+
+    async fn write_capture(records: &[CaptureRecord]) -> Vec<u8> {
+        let mut buf = vec![];
+        {
+            let mut writer = CaptureWriter::new(&mut buf).await.unwrap();
+            for record in records {
+                writer.write_record(record).await.unwrap();
+            }
+        }
+        buf
+    }
+
+    #[tokio::test]
+    pub async fn reads_what_was_written() {
+        let capture = write_capture(&records()).await;
+
+        let mut reader = CaptureReader::new(&capture[..]).await.unwrap();
+        let mut read = vec![];
+        while let Some(record) = reader.next_record().await.unwrap() {
+            read.push(record);
+        }
+
+        assert_eq!(read, records());
+    }
+
+    #[tokio::test]
+    pub async fn parses_capture_in_memory() {
+        let capture = write_capture(&records()).await;
+
+        assert_eq!(parse_capture(&capture).unwrap(), records());
+        assert!(parse_capture(&capture[..capture.len() - 2]).is_err());
+        assert!(parse_capture(&capture[1..]).is_err());
+    }
+
+    #[tokio::test]
+    pub async fn rejects_foreign_files() {
+        let result = CaptureReader::new(&b"\x1b\x1b\x1b\x1b\x01\x01\x01\x01"[..]).await;
+
+        assert_eq!(
+            result.err().map(|e| e.kind()),
+            Some(io::ErrorKind::InvalidData)
+        );
+    }
+
+    #[tokio::test]
+    pub async fn reports_truncated_records() {
+        let capture = write_capture(&records()).await;
+
+        let mut reader = CaptureReader::new(&capture[..capture.len() - 2])
+            .await
+            .unwrap();
+        reader.next_record().await.unwrap();
+        reader.next_record().await.unwrap();
+
+        assert!(reader.next_record().await.is_err());
+    }
+
+    #[tokio::test]
+    pub async fn records_while_forwarding() {
+        let (mut meter, serial) = tokio::io::duplex(64);
+        let (file, mut capture) = tokio::io::duplex(64);
+
+        let mut forwarded = record_capture(serial, file);
+        meter.write_all(&[0x1b, 0x1b]).await.unwrap();
+        drop(meter);
+
+        let mut buf = vec![];
+        forwarded.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, vec![0x1b, 0x1b]);
+
+        let mut reader = CaptureReader::new(&mut capture).await.unwrap();
+        let record = reader.next_record().await.unwrap().unwrap();
+        assert_eq!(record.data, vec![0x1b, 0x1b]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    pub async fn replays_with_original_timing() {
+        let capture = write_capture(&records()).await;
+        let start = Instant::now();
+
+        let mut replay = replay_capture(std::io::Cursor::new(capture), ReplaySpeed::Original);
+        let mut buf = vec![];
+        replay.read_to_end(&mut buf).await.unwrap();
+
+        assert_eq!(
+            buf,
+            vec![0x1b, 0x1b, 0x1b, 0x1b, 0x01, 0x01, 0x01, 0x01, 0x76]
+        );
+        assert!(start.elapsed() >= Duration::from_millis(3000));
+    }
+
+    #[tokio::test(start_paused = true)]
+    pub async fn replays_accelerated() {
+        let capture = write_capture(&records()).await;
+        let start = Instant::now();
+
+        let mut replay = replay_capture(
+            std::io::Cursor::new(capture),
+            ReplaySpeed::Accelerated(10.0),
+        );
+        let mut buf = vec![];
+        replay.read_to_end(&mut buf).await.unwrap();
+
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(300));
+        assert!(elapsed < Duration::from_millis(3000));
+    }
+
+    #[test]
+    pub fn rejects_invalid_factors() {
+        assert_eq!(
+            ReplaySpeed::accelerated(2.5),
+            Some(ReplaySpeed::Accelerated(2.5))
+        );
+        for factor in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert_eq!(ReplaySpeed::accelerated(factor), None);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    pub async fn replays_invalid_factors_without_delay() {
+        for factor in [0.0, -1.0, f64::NAN] {
+            let capture = write_capture(&records()).await;
+            let start = Instant::now();
+
+            let mut replay = replay_capture(
+                std::io::Cursor::new(capture),
+                ReplaySpeed::Accelerated(factor),
+            );
+            let mut buf = vec![];
+            replay.read_to_end(&mut buf).await.unwrap();
+
+            assert_eq!(buf.len(), 9);
+            assert!(start.elapsed() < Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    pub fn saturates_tiny_factors() {
+        let speed = ReplaySpeed::accelerated(1e-300).unwrap();
+
+        assert_eq!(speed.due(Duration::ZERO), Some(Duration::ZERO));
+        assert_eq!(speed.due(Duration::from_secs(1)), Some(Duration::MAX));
+    }
+
+    #[tokio::test]
+    pub async fn rejects_oversized_records() {
+        let mut capture = CAPTURE_HEADER.to_vec();
+        capture.extend(0u64.to_be_bytes());
+        capture.extend(u32::MAX.to_be_bytes());
+
+        let mut reader = CaptureReader::new(&capture[..]).await.unwrap();
+
+        assert_eq!(
+            reader.next_record().await.err().map(|e| e.kind()),
+            Some(io::ErrorKind::InvalidData)
+        );
+        let mut writer = CaptureWriter::new(vec![]).await.unwrap();
+        let record = CaptureRecord {
+            offset: Duration::ZERO,
+            data: vec![0; MAX_RECORD_LENGTH + 1],
+        };
+        assert!(writer.write_record(&record).await.is_err());
+    }
+}
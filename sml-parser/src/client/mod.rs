@@ -0,0 +1,439 @@
+use std::{io, time::Duration};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    application::{
+        domain::{
+            AttentionResponseBody, GetListRequestBody, GetListResponseBody, GetOpenResponseBody,
+            GetProcParameterRequestBody, GetProcParameterResponseBody, OpenRequestBody,
+            SmlMessageBody, SmlRequest,
+        },
+        encoder::encode_request,
+        parser::parse_body,
+    },
+    transport::{encode_frame, SMLMessageBuilder},
+};
+
+/// Identification and timing used by [SmlClient]
+#[derive(Debug, Clone)]
+pub struct ClientConfiguration {
+    pub client_id: Vec<u8>,
+    /// address a particular meter behind a gateway, `None` for any
+    pub server_id: Option<Vec<u8>>,
+    pub username: Option<Vec<u8>>,
+    pub password: Option<Vec<u8>>,
+    /// time to wait for a response before the request is sent again
+    pub timeout: Duration,
+    /// number of repetitions after the first attempt timed out
+    pub retries: usize,
+}
+
+impl Default for ClientConfiguration {
+    fn default() -> Self {
+        Self {
+            client_id: vec![0x00; 6],
+            server_id: None,
+            username: None,
+            password: None,
+            timeout: Duration::from_secs(5),
+            retries: 2,
+        }
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    /// no response arrived, not even after all retries
+    Timeout,
+    /// the meter refused the request
+    Attention(AttentionResponseBody),
+    /// the meter answered with a response not matching the request
+    UnexpectedResponse(SmlMessageBody),
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+pub type ClientResult<T> = Result<T, ClientError>;
+
+/// Client for meters and gateways which only send data on request
+///
+/// Every request is sent as an SML file consisting of `SML_PublicOpen.Req`, the actual
+/// request and `SML_PublicClose.Req`. The response is identified by its transaction id,
+/// all other messages received in the meantime are discarded.
+/// ```no_run
+/// use hackdose_sml_parser::client::{ClientConfiguration, SmlClient};
+///
+/// # async fn run(transport: tokio::io::DuplexStream) {
+/// let mut client = SmlClient::new(transport, ClientConfiguration::default());
+/// let list = client.get_list(None).await;
+/// # }
+/// ```
+pub struct SmlClient<T> {
+    transport: T,
+    configuration: ClientConfiguration,
+    builder: SMLMessageBuilder,
+    /// bytes received after the last complete SML file
+    unprocessed: Vec<u8>,
+    transaction_counter: u32,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> SmlClient<T> {
+    pub fn new(transport: T, configuration: ClientConfiguration) -> Self {
+        Self {
+            transport,
+            configuration,
+            builder: SMLMessageBuilder::Empty,
+            unprocessed: vec![],
+            transaction_counter: 0,
+        }
+    }
+
+    /// Open and close a session, e.g. to find out the server id of the meter
+    pub async fn open(&mut self) -> ClientResult<GetOpenResponseBody> {
+        match self.request(None).await? {
+            SmlMessageBody::GetOpenResponse(body) => Ok(body),
+            other => Err(ClientError::UnexpectedResponse(other)),
+        }
+    }
+
+    /// Query a list of values (`SML_GetList.Req`), `None` requests the default list
+    pub async fn get_list(
+        &mut self,
+        list_name: Option<Vec<u8>>,
+    ) -> ClientResult<GetListResponseBody> {
+        let request = SmlRequest::GetListRequest(GetListRequestBody {
+            client_id: self.configuration.client_id.clone(),
+            server_id: self.configuration.server_id.clone(),
+            username: self.configuration.username.clone(),
+            password: self.configuration.password.clone(),
+            list_name,
+        });
+        match self.request(Some(request)).await? {
+            SmlMessageBody::GetListResponse(body) => Ok(body),
+            other => Err(ClientError::UnexpectedResponse(other)),
+        }
+    }
+
+    /// Query a parameter tree (`SML_GetProcParameter.Req`)
+    pub async fn get_proc_parameter(
+        &mut self,
+        parameter_tree_path: Vec<Vec<u8>>,
+    ) -> ClientResult<GetProcParameterResponseBody> {
+        let request = SmlRequest::GetProcParameterRequest(GetProcParameterRequestBody {
+            server_id: self.configuration.server_id.clone(),
+            username: self.configuration.username.clone(),
+            password: self.configuration.password.clone(),
+            parameter_tree_path,
+        });
+        match self.request(Some(request)).await? {
+            SmlMessageBody::GetProcParameterResponse(body) => Ok(body),
+            other => Err(ClientError::UnexpectedResponse(other)),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Send a request wrapped into open and close requests and wait for its response
+    ///
+    /// Without a request, the response to the open request is returned.
+    async fn request(&mut self, request: Option<SmlRequest>) -> ClientResult<SmlMessageBody> {
+        for _ in 0..=self.configuration.retries {
+            let open_transaction_id = self.next_transaction_id();
+            let open = SmlRequest::OpenRequest(OpenRequestBody {
+                client_id: self.configuration.client_id.clone(),
+                req_file_id: open_transaction_id.clone(),
+                server_id: self.configuration.server_id.clone(),
+                username: self.configuration.username.clone(),
+                password: self.configuration.password.clone(),
+            });
+            let mut body = encode_request(&open_transaction_id, &open);
+            let expected = match &request {
+                Some(request) => {
+                    let transaction_id = self.next_transaction_id();
+                    body.append(&mut encode_request(&transaction_id, request));
+                    transaction_id
+                }
+                None => open_transaction_id,
+            };
+            body.append(&mut encode_request(
+                &self.next_transaction_id(),
+                &SmlRequest::CloseRequest,
+            ));
+
+            self.transport.write_all(&encode_frame(&body)).await?;
+            self.transport.flush().await?;
+
+            let timeout = self.configuration.timeout;
+            if let Ok(response) = tokio::time::timeout(timeout, self.response(&expected)).await {
+                return match response? {
+                    SmlMessageBody::AttentionResponse(attention) => {
+                        Err(ClientError::Attention(attention))
+                    }
+                    response => Ok(response),
+                };
+            }
+        }
+        Err(ClientError::Timeout)
+    }
+
+    async fn response(&mut self, transaction_id: &[u8]) -> ClientResult<SmlMessageBody> {
+        let mut buf = [0; 512];
+        loop {
+            if self.unprocessed.is_empty() {
+                let n = self.transport.read(&mut buf).await?;
+                if n == 0 {
+                    return Err(ClientError::Io(io::ErrorKind::UnexpectedEof.into()));
+                }
+                self.unprocessed = buf[..n].to_vec();
+            }
+            self.builder.record(&self.unprocessed);
+            self.unprocessed = vec![];
+
+            if let SMLMessageBuilder::Complete { ref data, ref rest } = self.builder {
+                let messages = parse_body(data).map(|m| m.messages).unwrap_or_default();
+                self.unprocessed = rest.to_vec();
+                self.builder = SMLMessageBuilder::Empty;
+                if let Some(response) = messages
+                    .into_iter()
+                    .find(|message| message.transaction_id == transaction_id)
+                {
+                    return Ok(response.body);
+                }
+            }
+        }
+    }
+
+    fn next_transaction_id(&mut self) -> Vec<u8> {
+        self.transaction_counter = self.transaction_counter.wrapping_add(1);
+        self.transaction_counter.to_be_bytes().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::DuplexStream;
+
+    use super::*;
+    use crate::application::domain::{AnyValue, SmlListEntry, SmlProcParValue, SmlTree};
+
+    fn transaction_ids(body: &[u8]) -> Vec<Vec<u8>> {
+        body.windows(12)
+            .filter(|w| w[0] == 0x76 && w[1] == 0x05 && w[6] == 0x62 && w[10] == 0x72)
+            .map(|w| w[2..6].to_vec())
+            .collect()
+    }
+
+    fn message(transaction_id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut message = vec![0x76, 0x05];
+        message.extend_from_slice(transaction_id);
+        message.extend_from_slice(&[0x62, 0x00, 0x62, 0x00]);
+        message.extend_from_slice(body);
+        message.extend_from_slice(&[0x63, 0x00, 0x00, 0x00]);
+        message
+    }
+
+    fn open_response() -> Vec<u8> {
+        vec![
+            0x72, 0x63, 0x01, 0x01, 0x76, 0x01, 0x01, 0x05, 0x04, 0x03, 0x02, 0x01, 0x03, 0x0a,
+            0x0b, 0x01, 0x01,
+        ]
+    }
+
+    fn list_response() -> Vec<u8> {
+        vec![
+            0x72, 0x63, 0x07, 0x01, 0x77, 0x01, 0x03, 0x0a, 0x0b, 0x07, 0x01, 0x00, 0x62, 0x0a,
+            0xff, 0xff, 0x72, 0x62, 0x01, 0x65, 0x01, 0x8a, 0x4d, 0x15, 0x71, 0x77, 0x07, 0x01,
+            0x00, 0x10, 0x07, 0x00, 0xff, 0x01, 0x01, 0x62, 0x1b, 0x52, 0x00, 0x55, 0x00, 0x00,
+            0x01, 0x2c, 0x01, 0x01, 0x01,
+        ]
+    }
+
+    fn close_response() -> Vec<u8> {
+        vec![0x72, 0x63, 0x02, 0x01, 0x71, 0x01]
+    }
+
+    /// Simulate a meter which answers every request file using `respond`
+    fn meter(
+        mut meter: DuplexStream,
+        respond: impl Fn(usize, &[Vec<u8>]) -> Option<Vec<u8>> + Send + 'static,
+    ) {
+        tokio::spawn(async move {
+            let mut builder = SMLMessageBuilder::Empty;
+            let mut buf = [0; 512];
+            let mut file = 0;
+            while let Ok(n) = meter.read(&mut buf).await {
+                if n == 0 {
+                    break;
+                }
+                builder.record(&buf[..n]);
+                if let SMLMessageBuilder::Complete { ref data, .. } = builder {
+                    let ids = transaction_ids(data);
+                    builder = SMLMessageBuilder::Empty;
+                    if let Some(response) = respond(file, &ids) {
+                        let _ = meter.write_all(&encode_frame(&response)).await;
+                    }
+                    file += 1;
+                }
+            }
+        });
+    }
+
+    #[tokio::test]
+    pub async fn gets_list() {
+        let (transport, server) = tokio::io::duplex(1024);
+        meter(server, |_, ids| {
+            let mut response = message(&ids[0], &open_response());
+            response.append(&mut message(&ids[1], &list_response()));
+            response.append(&mut message(&ids[2], &close_response()));
+            Some(response)
+        });
+
+        let mut client = SmlClient::new(transport, ClientConfiguration::default());
+        let list = client.get_list(None).await.unwrap();
+
+        assert_eq!(
+            list.value_list,
+            vec![SmlListEntry {
+                object_name: vec![1, 0, 16, 7, 0, 255],
+                status: None,
+                value_time: vec![],
+                unit: Some(27),
+                scaler: Some(0),
+                value: AnyValue::Signed(300)
+            }]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn opens_session() {
+        let (transport, server) = tokio::io::duplex(1024);
+        meter(server, |_, ids| {
+            let mut response = message(&ids[0], &open_response());
+            response.append(&mut message(&ids[1], &close_response()));
+            Some(response)
+        });
+
+        let mut client = SmlClient::new(transport, ClientConfiguration::default());
+        let open = client.open().await.unwrap();
+
+        assert_eq!(open.server_id, vec![0x0a, 0x0b]);
+    }
+
+    #[tokio::test]
+    pub async fn ignores_responses_to_other_transactions() {
+        let (transport, server) = tokio::io::duplex(1024);
+        meter(server, |_, ids| {
+            let mut stale = message(&[0xff, 0xff, 0xff, 0xff], &close_response());
+            let mut response = message(&ids[1], &list_response());
+            stale.append(&mut response);
+            Some(stale)
+        });
+
+        let mut client = SmlClient::new(transport, ClientConfiguration::default());
+
+        assert!(client.get_list(None).await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    pub async fn retries_after_timeout() {
+        let (transport, server) = tokio::io::duplex(1024);
+        meter(server, |file, ids| {
+            if file == 0 {
+                None
+            } else {
+                Some(message(&ids[1], &list_response()))
+            }
+        });
+
+        let mut client = SmlClient::new(transport, ClientConfiguration::default());
+
+        assert!(client.get_list(None).await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    pub async fn gives_up_after_retries() {
+        let (transport, server) = tokio::io::duplex(1024);
+        meter(server, |_, _| None);
+
+        let mut client = SmlClient::new(transport, ClientConfiguration::default());
+
+        assert!(matches!(
+            client.get_list(None).await,
+            Err(ClientError::Timeout)
+        ));
+    }
+
+    #[tokio::test]
+    pub async fn reports_attention_response() {
+        let (transport, server) = tokio::io::duplex(1024);
+        meter(server, |_, ids| {
+            Some(message(
+                &ids[1],
+                &[
+                    0x72, 0x63, 0xff, 0x01, 0x74, 0x03, 0x0a, 0x0b, 0x07, 0x81, 0x81, 0xc7, 0xc7,
+                    0xfe, 0x03, 0x01, 0x01,
+                ],
+            ))
+        });
+
+        let mut client = SmlClient::new(transport, ClientConfiguration::default());
+
+        match client.get_proc_parameter(vec![vec![0x01]]).await {
+            Err(ClientError::Attention(attention)) => assert_eq!(
+                attention.attention_number,
+                vec![0x81, 0x81, 0xc7, 0xc7, 0xfe, 0x03]
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    pub async fn gets_proc_parameter() {
+        let (transport, server) = tokio::io::duplex(1024);
+        meter(server, |_, ids| {
+            Some(message(
+                &ids[1],
+                &[
+                    0x72, 0x63, 0x05, 0x01, 0x73, 0x03, 0x0a, 0x0b, // serverId
+                    0x71, 0x07, 0x81, 0x81, 0xc7, 0x82, 0x01, 0xff, // parameterTreePath
+                    0x73, 0x07, 0x81, 0x81, 0xc7, 0x82, 0x01, 0xff, // parameterName
+                    0x01, // parameterValue
+                    0x71, // child list
+                    0x73, 0x07, 0x81, 0x81, 0xc7, 0x82, 0x03, 0xff, // parameterName
+                    0x72, 0x62, 0x01, 0x04, 0x49, 0x53, 0x4b, // value
+                    0x01, // no children
+                ],
+            ))
+        });
+
+        let mut client = SmlClient::new(transport, ClientConfiguration::default());
+        let parameter = client
+            .get_proc_parameter(vec![vec![0x81, 0x81, 0xc7, 0x82, 0x01, 0xff]])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            parameter.parameter_tree,
+            SmlTree {
+                parameter_name: vec![0x81, 0x81, 0xc7, 0x82, 0x01, 0xff],
+                parameter_value: None,
+                child_list: vec![SmlTree {
+                    parameter_name: vec![0x81, 0x81, 0xc7, 0x82, 0x03, 0xff],
+                    parameter_value: Some(SmlProcParValue::Value(AnyValue::String(vec![
+                        0x49, 0x53, 0x4b
+                    ]))),
+                    child_list: vec![]
+                }]
+            }
+        );
+    }
+}
@@ -0,0 +1,234 @@
+//! IEC 62056-21 ("D0") ASCII telegrams as sent by many older meters and by the
+//! optical interface of modern metering devices ("mME") in D0 mode
+
+#[cfg(feature = "tokio")]
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    sync::mpsc,
+};
+#[cfg(feature = "tokio")]
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+
+use crate::application::{
+    domain::{AnyValue, SmlListEntry},
+    units::unit_from_symbol,
+};
+
+#[cfg(feature = "tokio")]
+pub mod mode_c;
+pub mod parser;
+
+/// A complete telegram, e.g.
+/// ```text
+/// /ISK5\2M550T-1011
+///
+/// 1-0:1.8.0*255(001234.5678*kWh)
+/// !
+/// ```
+#[derive(PartialEq, Debug, Clone)]
+pub struct D0Telegram {
+    /// identification line without the leading `/`
+    pub identification: String,
+    pub data_lines: Vec<D0DataLine>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct D0DataLine {
+    /// the six OBIS value groups
+    pub object_name: Vec<u8>,
+    /// the values in parentheses, some lines carry several (e.g. a timestamp and a value)
+    pub values: Vec<D0Value>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct D0Value {
+    pub value: String,
+    pub unit: Option<String>,
+}
+
+impl D0Telegram {
+    /// The readings of the telegram in the same form as the entries of an SML list
+    pub fn list_entries(&self) -> Vec<SmlListEntry> {
+        self.data_lines
+            .iter()
+            .filter_map(|line| line.list_entry())
+            .collect()
+    }
+}
+
+impl D0DataLine {
+    /// The reading of this line, taking the last value if there are several
+    pub fn list_entry(&self) -> Option<SmlListEntry> {
+        let value = self.values.last()?;
+        Some(list_entry(self.object_name.clone(), value))
+    }
+}
+
+/// Convert an ASCII value into an SML list entry
+///
+/// Decimal numbers become signed values with a scaler, units are converted to DLMS units
+/// (e.g. `kWh` to Wh). Anything else is kept as a string.
+pub(crate) fn list_entry(object_name: Vec<u8>, value: &D0Value) -> SmlListEntry {
+    let unit = value.unit.as_deref().and_then(unit_from_symbol);
+    let (value, scaler) = match parse_decimal(&value.value) {
+        Some((number, decimals)) => {
+            let unit_scaler = unit.map(|(_, scaler)| scaler).unwrap_or(0);
+            (AnyValue::Signed(number), Some(unit_scaler - decimals))
+        }
+        None => (AnyValue::String(value.value.as_bytes().to_vec()), None),
+    };
+    SmlListEntry {
+        object_name,
+        status: None,
+        value_time: vec![],
+        unit: unit.map(|(unit, _)| unit),
+        scaler,
+        value,
+    }
+}
+
+/// Longest fraction kept as a number, so that the scaler fits into an `i8`
+const MAX_DECIMALS: usize = 9;
+
+/// Split a decimal like `001234.5678` into `(12345678, 4)`
+fn parse_decimal(value: &str) -> Option<(isize, i8)> {
+    let (integer, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if fraction.len() > MAX_DECIMALS || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let number = format!("{}{}", integer, fraction).parse::<isize>().ok()?;
+    Some((number, fraction.len() as i8))
+}
+
+/// Read D0 telegrams pushed by a meter from a reader
+/// ```no_run
+/// use std::io::Cursor;
+/// use hackdose_sml_parser::d0::d0_telegram_stream;
+///
+/// let cursor = Cursor::new(b"/ISK5\r\n\r\n1.8.0(1*kWh)\r\n!\r\n".to_vec());
+/// let telegram_stream = d0_telegram_stream(cursor);
+/// ```
+#[cfg(feature = "tokio")]
+pub fn d0_telegram_stream(
+    mut stream: impl AsyncRead + Unpin + Send + 'static,
+) -> impl Stream<Item = D0Telegram> {
+    let (tx, rx) = mpsc::channel::<D0Telegram>(256);
+
+    let mut buf = [0; 512];
+    let mut recorded = vec![];
+
+    tokio::spawn(async move {
+        while let Ok(n) = stream.read(&mut buf).await {
+            if n == 0 {
+                break;
+            }
+            recorded.extend_from_slice(&buf[..n]);
+            while let Some(telegram) = take_telegram(&mut recorded) {
+                if let Ok(telegram) = parser::parse_telegram(&String::from_utf8_lossy(&telegram)) {
+                    let _ = tx.send(telegram).await;
+                }
+            }
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+/// Remove the first complete telegram (from `/` to the line ending after `!`) from the
+/// buffer, dropping everything before it
+pub fn take_telegram(recorded: &mut Vec<u8>) -> Option<Vec<u8>> {
+    match recorded.iter().position(|b| *b == b'/') {
+        Some(start) => {
+            recorded.drain(..start);
+        }
+        None => {
+            recorded.clear();
+            return None;
+        }
+    }
+    let end = recorded.iter().position(|b| *b == b'!')?;
+    let line_end = recorded[end..].iter().position(|b| *b == b'\n')?;
+    Some(recorded.drain(..=end + line_end).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::application::units::{WATT, WATT_HOUR};
+    use crate::d0::parser::parse_telegram;
+
+    #[test]
+    pub fn converts_readings() {
+        let telegram = parse_telegram(
+            "/ISK5\\2M550T-1011\r\n\r\n\
+            1-0:96.1.0*255(1ISK0012345678)\r\n\
+            1-0:1.8.0*255(001234.5678*kWh)\r\n\
+            1-0:16.7.0*255(-000123*W)\r\n\
+            !\r\n",
+        )
+        .unwrap();
+
+        let entries = telegram.list_entries();
+
+        assert_eq!(
+            entries[0].value,
+            AnyValue::String(b"1ISK0012345678".to_vec())
+        );
+        assert_eq!(entries[1].value, AnyValue::Signed(12345678));
+        assert_eq!(entries[1].unit, Some(WATT_HOUR));
+        assert_eq!(entries[1].scaler, Some(-1));
+        assert_eq!(
+            entries[2],
+            SmlListEntry {
+                object_name: vec![1, 0, 16, 7, 0, 255],
+                status: None,
+                value_time: vec![],
+                unit: Some(WATT),
+                scaler: Some(0),
+                value: AnyValue::Signed(-123)
+            }
+        );
+    }
+
+    #[test]
+    pub fn keeps_long_fractions_as_strings() {
+        assert_eq!(parse_decimal("0.123456789"), Some((123456789, 9)));
+        assert_eq!(parse_decimal("0.1234567890"), None);
+
+        let value = D0Value {
+            value: format!("0.{}", "0".repeat(200)),
+            unit: Some("kWh".to_string()),
+        };
+        let entry = list_entry(vec![1, 0, 1, 8, 0, 255], &value);
+
+        assert_eq!(entry.value, AnyValue::String(value.value.into_bytes()));
+        assert_eq!(entry.scaler, None);
+    }
+
+    #[test]
+    pub fn takes_telegram_from_buffer() {
+        let mut recorded = b"junk\r\n/ISK5\r\n\r\n1.8.0(1)\r\n!\r\n/ISK5".to_vec();
+
+        let telegram = take_telegram(&mut recorded);
+
+        assert_eq!(telegram, Some(b"/ISK5\r\n\r\n1.8.0(1)\r\n!\r\n".to_vec()));
+        assert_eq!(recorded, b"/ISK5".to_vec());
+        assert_eq!(take_telegram(&mut recorded), None);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    pub async fn streams_telegrams() {
+        use tokio_stream::StreamExt;
+
+        let input = b"/ISK5\r\n\r\n1.8.0(1*kWh)\r\n!\r\n/ISK5\r\n\r\n1.8.0(2*kWh)\r\n!\r\n";
+        let mut stream = d0_telegram_stream(std::io::Cursor::new(input.to_vec()));
+
+        let mut values = vec![];
+        while let Some(telegram) = stream.next().await {
+            values.push(telegram.data_lines[0].values[0].value.clone());
+        }
+
+        assert_eq!(values, vec!["1".to_string(), "2".to_string()]);
+    }
+}
@@ -0,0 +1,342 @@
+//! Mode C readout: the reader sends a request at 300 baud, the meter answers with its
+//! identification and the maximal baud rate, and sends the data block after both sides
+//! switched to the agreed baud rate.
+
+use std::{io, time::Duration};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    application::parser::ParseError,
+    d0::{
+        parser::{block_check_character, parse_data_block},
+        D0Telegram,
+    },
+};
+
+const STX: u8 = 0x02;
+const ETX: u8 = 0x03;
+const ACK: u8 = 0x06;
+
+/// Baud rate used to start the communication
+pub const INITIAL_BAUD_RATE: u32 = 300;
+
+/// Baud rate switching of the underlying serial port
+pub trait SetBaudRate {
+    fn set_baud_rate(&mut self, baud_rate: u32) -> io::Result<()>;
+}
+
+#[cfg(feature = "serial")]
+impl SetBaudRate for tokio_serial::SerialStream {
+    fn set_baud_rate(&mut self, baud_rate: u32) -> io::Result<()> {
+        tokio_serial::SerialPort::set_baud_rate(self, baud_rate)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ModeCOptions {
+    /// device address for buses with several meters, `None` for any
+    pub address: Option<String>,
+    /// upper limit for the baud rate, even if the meter offers more
+    pub max_baud_rate: u32,
+    /// time to wait for the identification and the data block
+    pub timeout: Duration,
+}
+
+impl Default for ModeCOptions {
+    fn default() -> Self {
+        Self {
+            address: None,
+            max_baud_rate: 9600,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum ModeCError {
+    Io(io::Error),
+    Timeout,
+    /// the identification line does not follow the `/XXXZ...` pattern
+    InvalidIdentification(String),
+    /// the block check character does not match the data block
+    ChecksumMismatch,
+    Parse(ParseError),
+}
+
+impl From<io::Error> for ModeCError {
+    fn from(e: io::Error) -> Self {
+        ModeCError::Io(e)
+    }
+}
+
+/// Perform a mode C data readout
+///
+/// The port is left at the baud rate agreed upon with the meter.
+pub async fn read_mode_c<T>(port: &mut T, options: &ModeCOptions) -> Result<D0Telegram, ModeCError>
+where
+    T: AsyncRead + AsyncWrite + SetBaudRate + Unpin,
+{
+    port.set_baud_rate(INITIAL_BAUD_RATE)?;
+    let request = format!("/?{}!\r\n", options.address.as_deref().unwrap_or(""));
+    port.write_all(request.as_bytes()).await?;
+    port.flush().await?;
+
+    let identification = tokio::time::timeout(options.timeout, read_identification(port))
+        .await
+        .map_err(|_| ModeCError::Timeout)??;
+
+    let offered = identification
+        .chars()
+        .nth(3)
+        .and_then(baud_rate)
+        .ok_or_else(|| ModeCError::InvalidIdentification(identification.clone()))?;
+    let (baud_rate_char, baud_rate) = BAUD_RATES
+        .iter()
+        .rev()
+        .find(|(_, rate)| *rate <= offered.min(options.max_baud_rate))
+        .copied()
+        .unwrap_or(('0', INITIAL_BAUD_RATE));
+
+    // protocol control 0 (normal), mode 0 (data readout)
+    port.write_all(&[ACK, b'0', baud_rate_char as u8, b'0', b'\r', b'\n'])
+        .await?;
+    port.flush().await?;
+    // the acknowledgement has to leave the port before switching
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    port.set_baud_rate(baud_rate)?;
+
+    let block = tokio::time::timeout(options.timeout, read_data_block(port))
+        .await
+        .map_err(|_| ModeCError::Timeout)??;
+    let data_lines =
+        parse_data_block(&String::from_utf8_lossy(&block)).map_err(ModeCError::Parse)?;
+
+    Ok(D0Telegram {
+        identification,
+        data_lines,
+    })
+}
+
+static BAUD_RATES: &[(char, u32)] = &[
+    ('0', 300),
+    ('1', 600),
+    ('2', 1200),
+    ('3', 2400),
+    ('4', 4800),
+    ('5', 9600),
+    ('6', 19200),
+];
+
+fn baud_rate(c: char) -> Option<u32> {
+    BAUD_RATES
+        .iter()
+        .find(|(rate_char, _)| *rate_char == c)
+        .map(|(_, rate)| *rate)
+}
+
+/// Read the identification line, returning it without `/` and line ending
+async fn read_identification<T: AsyncRead + Unpin>(port: &mut T) -> Result<String, ModeCError> {
+    let mut line = vec![];
+    loop {
+        let byte = port.read_u8().await?;
+        if line.is_empty() && byte != b'/' {
+            continue;
+        }
+        line.push(byte);
+        if byte == b'\n' {
+            break;
+        }
+    }
+    let line = String::from_utf8_lossy(&line);
+    Ok(line[1..].trim_end().to_string())
+}
+
+/// Read the data block between STX and ETX and check its block check character
+async fn read_data_block<T: AsyncRead + Unpin>(port: &mut T) -> Result<Vec<u8>, ModeCError> {
+    while port.read_u8().await? != STX {}
+    let mut block = vec![];
+    loop {
+        let byte = port.read_u8().await?;
+        block.push(byte);
+        if byte == ETX {
+            break;
+        }
+    }
+    let bcc = port.read_u8().await?;
+    if block_check_character(&block) != bcc {
+        return Err(ModeCError::ChecksumMismatch);
+    }
+    block.pop();
+    Ok(block)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+    };
+
+    use tokio::io::{DuplexStream, ReadBuf};
+
+    use super::*;
+
+    struct Port {
+        stream: DuplexStream,
+        baud_rates: Arc<Mutex<Vec<u32>>>,
+    }
+
+    impl SetBaudRate for Port {
+        fn set_baud_rate(&mut self, baud_rate: u32) -> io::Result<()> {
+            self.baud_rates.lock().unwrap().push(baud_rate);
+            Ok(())
+        }
+    }
+
+    impl AsyncRead for Port {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.stream).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for Port {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.stream).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.stream).poll_flush(cx)
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.stream).poll_shutdown(cx)
+        }
+    }
+
+    fn port() -> (Port, DuplexStream, Arc<Mutex<Vec<u32>>>) {
+        let (stream, meter) = tokio::io::duplex(1024);
+        let baud_rates = Arc::new(Mutex::new(vec![]));
+        let port = Port {
+            stream,
+            baud_rates: baud_rates.clone(),
+        };
+        (port, meter, baud_rates)
+    }
+
+    fn data_block(data: &[u8], corrupt: bool) -> Vec<u8> {
+        let mut block = data.to_vec();
+        block.push(ETX);
+        let bcc = block_check_character(&block) ^ corrupt as u8;
+        let mut message = vec![STX];
+        message.append(&mut block);
+        message.push(bcc);
+        message
+    }
+
+    async fn read_line(meter: &mut DuplexStream) -> Vec<u8> {
+        let mut line = vec![];
+        loop {
+            let byte = meter.read_u8().await.unwrap();
+            line.push(byte);
+            if byte == b'\n' {
+                return line;
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    pub async fn reads_data_after_handshake() {
+        let (mut port, mut meter, baud_rates) = port();
+        let meter = tokio::spawn(async move {
+            let request = read_line(&mut meter).await;
+            meter.write_all(b"/ISK5MT174-0001\r\n").await.unwrap();
+            let acknowledgement = read_line(&mut meter).await;
+            meter
+                .write_all(&data_block(b"1.8.0(001234.5678*kWh)\r\n!\r\n", false))
+                .await
+                .unwrap();
+            (request, acknowledgement)
+        });
+
+        let telegram = read_mode_c(&mut port, &ModeCOptions::default())
+            .await
+            .unwrap();
+        let (request, acknowledgement) = meter.await.unwrap();
+
+        assert_eq!(request, b"/?!\r\n".to_vec());
+        assert_eq!(acknowledgement, vec![ACK, b'0', b'5', b'0', b'\r', b'\n']);
+        assert_eq!(*baud_rates.lock().unwrap(), vec![300, 9600]);
+        assert_eq!(telegram.identification, "ISK5MT174-0001");
+        assert_eq!(telegram.data_lines[0].object_name, vec![1, 0, 1, 8, 0, 255]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    pub async fn limits_baud_rate() {
+        let (mut port, mut meter, baud_rates) = port();
+        tokio::spawn(async move {
+            read_line(&mut meter).await;
+            meter.write_all(b"/LGZ6ZMD120\r\n").await.unwrap();
+            read_line(&mut meter).await;
+            meter
+                .write_all(&data_block(b"1.8.0(1*kWh)\r\n!\r\n", false))
+                .await
+                .unwrap();
+        });
+
+        let options = ModeCOptions {
+            address: None,
+            max_baud_rate: 2400,
+            timeout: Duration::from_secs(10),
+        };
+        read_mode_c(&mut port, &options).await.unwrap();
+
+        assert_eq!(*baud_rates.lock().unwrap(), vec![300, 2400]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    pub async fn rejects_corrupt_data_block() {
+        let (mut port, mut meter, _) = port();
+        tokio::spawn(async move {
+            read_line(&mut meter).await;
+            meter.write_all(b"/ISK5MT174-0001\r\n").await.unwrap();
+            read_line(&mut meter).await;
+            meter
+                .write_all(&data_block(b"1.8.0(1*kWh)\r\n!\r\n", true))
+                .await
+                .unwrap();
+        });
+
+        let result = read_mode_c(&mut port, &ModeCOptions::default()).await;
+
+        assert!(matches!(result, Err(ModeCError::ChecksumMismatch)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    pub async fn times_out_without_answer() {
+        let (mut port, _meter, _) = port();
+
+        let result = read_mode_c(&mut port, &ModeCOptions::default()).await;
+
+        assert!(matches!(result, Err(ModeCError::Timeout)));
+    }
+
+    #[cfg(feature = "serial")]
+    #[test]
+    pub fn serial_streams_can_switch_baud_rate() {
+        fn port<T: AsyncRead + AsyncWrite + SetBaudRate + Unpin>() {}
+
+        port::<tokio_serial::SerialStream>();
+    }
+}
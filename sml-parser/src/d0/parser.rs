@@ -0,0 +1,162 @@
+use crate::{
+    application::parser::{ParseError, ParseResult},
+    d0::{D0DataLine, D0Telegram, D0Value},
+};
+
+/// Parse a complete telegram as pushed by the D0 interface (`/` identification ... `!`)
+pub fn parse_telegram(input: &str) -> ParseResult<D0Telegram> {
+    d0_parser::telegram(input).map_err(|_| ParseError::Unknown)
+}
+
+/// Parse the data block of a mode C readout (between STX and ETX)
+pub fn parse_data_block(input: &str) -> ParseResult<Vec<D0DataLine>> {
+    d0_parser::data_block(input).map_err(|_| ParseError::Unknown)
+}
+
+/// Parse an object name like `1-0:1.8.0*255`, `1.8.0` or `C.1.0` into six OBIS value groups
+pub fn parse_object_name(input: &str) -> ParseResult<Vec<u8>> {
+    d0_parser::object_name(input).map_err(|_| ParseError::Unknown)
+}
+
+/// Block check character of a mode C readout, i.e. the XOR of all bytes after STX up to
+/// and including ETX
+pub fn block_check_character(block: &[u8]) -> u8 {
+    block.iter().fold(0, |bcc, byte| bcc ^ byte)
+}
+
+peg::parser! {
+    grammar d0_parser() for str {
+
+        pub rule telegram() -> D0Telegram
+            = "/" identification:$((!eol() [_])*) eol() eol()? data_lines:data_line()* "!" eol()? { D0Telegram { identification: identification.to_string(), data_lines }}
+
+        pub rule data_block() -> Vec<D0DataLine>
+            = data_lines:data_line()* "!" eol()? { data_lines }
+
+        rule data_line() -> D0DataLine
+            = object_name:object_name() values:value()+ eol() continued:continuation()* { D0DataLine { object_name, values: values.into_iter().chain(continued.into_iter().flatten()).collect() }}
+
+        // values continued on the following line (e.g. the gas reading of DSMR 2.2)
+        rule continuation() -> Vec<D0Value>
+            = values:value()+ eol() { values }
+
+        rule value() -> D0Value
+            = "(" value:$((!['*' | ')'] [_])*) unit:("*" u:$((![')'] [_])*) { u })? ")" { D0Value { value: value.to_string(), unit: unit.map(|u| u.to_string()) }}
+
+        rule eol() = "\r\n" / "\n"
+
+        pub rule object_name() -> Vec<u8>
+            = full_object_name() / short_object_name()
+
+        rule full_object_name() -> Vec<u8>
+            = a:number() "-" b:number() ":" c:group_c() "." d:number() e:("." e:number() { e })? f:storage()? { vec![a, b, c, d, e.unwrap_or(0), f.unwrap_or(255)] }
+
+        rule short_object_name() -> Vec<u8>
+            = c:group_c() "." d:group_c() e:("." e:number() { e })? f:storage()? {
+                let a = if c == 0 || c >= 96 { 0 } else { 1 };
+                vec![a, 0, c, d, e.unwrap_or(0), f.unwrap_or(255)]
+            }
+
+        rule storage() -> u8
+            = ("*" / "&") f:number() { f }
+
+        rule group_c() -> u8
+            = number() / "C" { 96 } / "F" { 97 } / "L" { 98 } / "P" { 99 }
+
+        rule number() -> u8
+            = n:$(['0'..='9']*<1,3>) {? n.parse().or(Err("number")) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn parses_pushed_telegram() {
+        let telegram = "/ISk5MT174-0001\r\n\r\n\
+            1-0:0.0.0*255(12345678)\r\n\
+            1-0:1.8.0*255(00012345.6789*kWh)\r\n\
+            1-0:16.7.0*255(-000123*W)\r\n\
+            !\r\n";
+
+        let result = parse_telegram(telegram).unwrap();
+
+        assert_eq!(result.identification, "ISk5MT174-0001");
+        assert_eq!(
+            result.data_lines[1],
+            D0DataLine {
+                object_name: vec![1, 0, 1, 8, 0, 255],
+                values: vec![D0Value {
+                    value: "00012345.6789".to_string(),
+                    unit: Some("kWh".to_string())
+                }]
+            }
+        );
+        assert_eq!(result.data_lines.len(), 3);
+    }
+
+    #[test]
+    pub fn parses_short_object_names() {
+        assert_eq!(
+            parse_object_name("1.8.0").unwrap(),
+            vec![1, 0, 1, 8, 0, 255]
+        );
+        assert_eq!(
+            parse_object_name("C.1.0").unwrap(),
+            vec![0, 0, 96, 1, 0, 255]
+        );
+        assert_eq!(
+            parse_object_name("F.F").unwrap(),
+            vec![0, 0, 97, 97, 0, 255]
+        );
+        assert_eq!(
+            parse_object_name("1.8.1*01").unwrap(),
+            vec![1, 0, 1, 8, 1, 1]
+        );
+    }
+
+    #[test]
+    pub fn parses_full_object_names() {
+        assert_eq!(
+            parse_object_name("0-0:96.1.0").unwrap(),
+            vec![0, 0, 96, 1, 0, 255]
+        );
+        assert_eq!(
+            parse_object_name("1-0:1.8.0*255").unwrap(),
+            vec![1, 0, 1, 8, 0, 255]
+        );
+        assert!(parse_object_name("1-0:1.8.0*256").is_err());
+    }
+
+    #[test]
+    pub fn parses_lines_with_several_values() {
+        let block = "0-1:24.2.1(101209112500W)(12785.123*m3)\r\n!\r\n";
+
+        let result = parse_data_block(block).unwrap();
+
+        assert_eq!(result[0].values.len(), 2);
+        assert_eq!(result[0].values[1].unit, Some("m3".to_string()));
+    }
+
+    #[test]
+    pub fn parses_continued_lines() {
+        let block = "0-1:24.3.0(090212160000)(00)(60)(1)(0-1:24.2.1)(m3)\r\n(00001.001)\r\n!\r\n";
+
+        let result = parse_data_block(block).unwrap();
+
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].values.len(), 7);
+        assert_eq!(result[0].values[6].value, "00001.001");
+    }
+
+    #[test]
+    pub fn rejects_garbage() {
+        assert!(parse_telegram("/ISK5\r\n1-0:1.8.0(12\r\n!\r\n").is_err());
+    }
+
+    #[test]
+    pub fn computes_block_check_character() {
+        assert_eq!(block_check_character(b"1.8.0(1)\r\n!\r\n\x03"), 0x2b);
+    }
+}
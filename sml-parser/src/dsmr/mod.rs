@@ -0,0 +1,441 @@
+//! Telegrams of the P1 port of Dutch, Belgian and Luxembourgian meters (DSMR 2.2 up to
+//! 5.0), an ASCII format based on IEC 62056-21 with a checksum and M-Bus sub-meters
+//! (gas, water, heat) attached to the electricity meter
+
+use std::{collections::BTreeMap, io};
+
+#[cfg(feature = "tokio")]
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    sync::mpsc,
+};
+#[cfg(feature = "tokio")]
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+
+use crate::{
+    application::{
+        domain::{AnyValue, SmlListEntry},
+        parser::ParseError,
+    },
+    d0::{list_entry, D0DataLine, D0Value},
+};
+
+use self::parser::parse_timestamp;
+
+pub mod parser;
+
+/// A complete telegram, e.g.
+/// ```text
+/// /ISk5\2MT382-1000
+///
+/// 1-3:0.2.8(50)
+/// 0-0:1.0.0(101209113020W)
+/// 1-0:1.8.1(123456.789*kWh)
+/// 1-0:1.7.0(01.193*kW)
+/// 0-1:24.1.0(003)
+/// 0-1:24.2.1(101209112500W)(12785.123*m3)
+/// !EF2F
+/// ```
+#[derive(PartialEq, Debug, Clone)]
+pub struct DsmrTelegram {
+    /// identification line without the leading `/`
+    pub identification: String,
+    pub data_lines: Vec<D0DataLine>,
+    /// checksum after `!`, only sent from DSMR 4 on
+    pub checksum: Option<u16>,
+}
+
+/// Local time as sent in DSMR telegrams
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct DsmrTimestamp {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    /// `Some(true)` for summer time, `None` if the meter does not tell (DSMR 2.2)
+    pub daylight_saving_time: Option<bool>,
+}
+
+/// A device attached to the M-Bus of the meter
+#[derive(PartialEq, Debug, Clone)]
+pub struct MbusChannel {
+    /// channel number 1 to 4 (value group B of the OBIS number)
+    pub channel: u8,
+    /// M-Bus device type, e.g. 3 for gas, 4 for heat and 7 for water
+    pub device_type: Option<u8>,
+    pub equipment_id: Option<String>,
+    /// time of the last reading
+    pub timestamp: Option<DsmrTimestamp>,
+    /// last reading of the meter, e.g. in m³
+    pub reading: Option<SmlListEntry>,
+}
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum DsmrError {
+    Io(io::Error),
+    /// the checksum after `!` does not match the telegram
+    ChecksumMismatch {
+        expected: u16,
+        actual: u16,
+    },
+    Parse(ParseError),
+}
+
+impl From<io::Error> for DsmrError {
+    fn from(e: io::Error) -> Self {
+        DsmrError::Io(e)
+    }
+}
+
+/// Pairs of positive and negative power which are combined into the sum active power
+/// (positive, negative, sum), as DSMR meters do not send the latter
+static POWER_BALANCES: &[([u8; 6], [u8; 6], [u8; 6])] = &[
+    (
+        [1, 0, 1, 7, 0, 255],
+        [1, 0, 2, 7, 0, 255],
+        [1, 0, 16, 7, 0, 255],
+    ),
+    (
+        [1, 0, 21, 7, 0, 255],
+        [1, 0, 22, 7, 0, 255],
+        [1, 0, 36, 7, 0, 255],
+    ),
+    (
+        [1, 0, 41, 7, 0, 255],
+        [1, 0, 42, 7, 0, 255],
+        [1, 0, 56, 7, 0, 255],
+    ),
+    (
+        [1, 0, 61, 7, 0, 255],
+        [1, 0, 62, 7, 0, 255],
+        [1, 0, 76, 7, 0, 255],
+    ),
+];
+
+impl DsmrTelegram {
+    /// DSMR version as sent by the meter (e.g. `50` for 5.0), not sent before DSMR 4
+    pub fn version(&self) -> Option<&str> {
+        self.value(&[1, 3, 0, 2, 8, 255])
+            .map(|value| value.value.as_str())
+    }
+
+    /// Time of the telegram
+    pub fn timestamp(&self) -> Option<DsmrTimestamp> {
+        self.value(&[0, 0, 1, 0, 0, 255])
+            .and_then(|value| parse_timestamp(&value.value))
+    }
+
+    /// The readings of the telegram in the same form as the entries of an SML list
+    ///
+    /// The sum active power (total and per phase) is derived from the delivered and
+    /// received power, readings of M-Bus devices are taken without their timestamp.
+    pub fn list_entries(&self) -> Vec<SmlListEntry> {
+        let mut entries = self
+            .data_lines
+            .iter()
+            .filter_map(|line| match mbus_reading(line) {
+                Some(reading) => Some(reading),
+                None => line.list_entry(),
+            })
+            .collect::<Vec<_>>();
+
+        for (positive, negative, sum) in POWER_BALANCES {
+            let find = |object_name: &[u8]| entries.iter().find(|e| e.object_name == object_name);
+            if find(sum).is_some() {
+                continue;
+            }
+            if let (Some(positive), Some(negative)) = (find(positive), find(negative)) {
+                if let Some(balance) = balance(sum, positive, negative) {
+                    entries.push(balance);
+                }
+            }
+        }
+        entries
+    }
+
+    /// Devices attached to the M-Bus of the meter, ordered by channel
+    pub fn mbus_channels(&self) -> Vec<MbusChannel> {
+        let mut channels = BTreeMap::new();
+        for line in &self.data_lines {
+            let channel = match line.object_name[..] {
+                [0, channel @ 1..=4, ..] => channel,
+                _ => continue,
+            };
+            let entry = channels.entry(channel).or_insert(MbusChannel {
+                channel,
+                device_type: None,
+                equipment_id: None,
+                timestamp: None,
+                reading: None,
+            });
+            match line.object_name[2..5] {
+                [24, 1, 0] => {
+                    entry.device_type = line.values.last().and_then(|v| v.value.parse().ok())
+                }
+                [96, 1, 0] => entry.equipment_id = line.values.last().map(|v| decode_hex(&v.value)),
+                _ => {
+                    if let Some(reading) = mbus_reading(line) {
+                        entry.timestamp =
+                            line.values.first().and_then(|v| parse_timestamp(&v.value));
+                        entry.reading = Some(reading);
+                    }
+                }
+            }
+        }
+        channels.into_values().collect()
+    }
+
+    fn value(&self, object_name: &[u8]) -> Option<&D0Value> {
+        self.data_lines
+            .iter()
+            .find(|line| line.object_name == object_name)
+            .and_then(|line| line.values.last())
+    }
+}
+
+/// The reading of an M-Bus device, i.e. the value after the timestamp
+///
+/// DSMR 4 and newer send `0-n:24.2.1(timestamp)(value*unit)`, DSMR 2.2 and 3 send
+/// `0-n:24.3.0(timestamp)(..)(..)(..)(object name)(unit)` followed by `(value)` on the
+/// next line.
+fn mbus_reading(line: &D0DataLine) -> Option<SmlListEntry> {
+    match line.object_name[..] {
+        [0, 1..=4, 24, 2, ..] => {
+            let value = line.values.last()?;
+            Some(list_entry(line.object_name.clone(), value))
+        }
+        [0, 1..=4, 24, 3, 0, _] => {
+            let [.., unit, value] = &line.values[..] else {
+                return None;
+            };
+            let value = D0Value {
+                value: value.value.clone(),
+                unit: Some(unit.value.clone()),
+            };
+            Some(list_entry(line.object_name.clone(), &value))
+        }
+        _ => None,
+    }
+}
+
+/// Difference of two readings with the same unit as a reading with `object_name`
+///
+/// Returns `None` if the difference does not fit into an `isize`.
+fn balance(
+    object_name: &[u8],
+    positive: &SmlListEntry,
+    negative: &SmlListEntry,
+) -> Option<SmlListEntry> {
+    let (AnyValue::Signed(p), AnyValue::Signed(n)) = (&positive.value, &negative.value) else {
+        return None;
+    };
+    if positive.unit != negative.unit {
+        return None;
+    }
+    let (p_scaler, n_scaler) = (positive.scaler.unwrap_or(0), negative.scaler.unwrap_or(0));
+    let scaler = p_scaler.min(n_scaler);
+    let rescale = |value: isize, from: i8| {
+        10_isize
+            .checked_pow(from.abs_diff(scaler) as u32)?
+            .checked_mul(value)
+    };
+    let value = rescale(*p, p_scaler)?.checked_sub(rescale(*n, n_scaler)?)?;
+    Some(SmlListEntry {
+        object_name: object_name.to_vec(),
+        status: None,
+        value_time: vec![],
+        unit: positive.unit,
+        scaler: Some(scaler),
+        value: AnyValue::Signed(value),
+    })
+}
+
+/// Equipment identifiers are sent as hex encoded ASCII, keep the raw value otherwise
+fn decode_hex(value: &str) -> String {
+    let decoded = (0..value.len())
+        .step_by(2)
+        .map(|i| {
+            value
+                .get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+        })
+        .collect::<Option<Vec<_>>>()
+        .and_then(|bytes| String::from_utf8(bytes).ok());
+    match decoded {
+        Some(decoded)
+            if value.len().is_multiple_of(2) && decoded.chars().all(|c| c.is_ascii_graphic()) =>
+        {
+            decoded
+        }
+        _ => value.to_string(),
+    }
+}
+
+/// Read DSMR telegrams from the P1 port
+///
+/// Telegrams with a wrong checksum are dropped.
+/// ```no_run
+/// use std::io::Cursor;
+/// use hackdose_sml_parser::dsmr::dsmr_telegram_stream;
+///
+/// let cursor = Cursor::new(b"/ISk5\\2MT382-1000\r\n\r\n1-0:1.7.0(01.193*kW)\r\n!\r\n".to_vec());
+/// let telegram_stream = dsmr_telegram_stream(cursor);
+/// ```
+#[cfg(feature = "tokio")]
+pub fn dsmr_telegram_stream(
+    mut stream: impl AsyncRead + Unpin + Send + 'static,
+) -> impl Stream<Item = DsmrTelegram> {
+    let (tx, rx) = mpsc::channel::<DsmrTelegram>(256);
+
+    let mut buf = [0; 512];
+    let mut recorded = vec![];
+
+    tokio::spawn(async move {
+        while let Ok(n) = stream.read(&mut buf).await {
+            if n == 0 {
+                break;
+            }
+            recorded.extend_from_slice(&buf[..n]);
+            while let Some(telegram) = crate::d0::take_telegram(&mut recorded) {
+                if let Ok(telegram) = parser::parse_telegram(&telegram) {
+                    let _ = tx.send(telegram).await;
+                }
+            }
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::application::{
+        obis::Obis,
+        units::{CUBIC_METRE, WATT},
+    };
+    use crate::dsmr::parser::parse_telegram;
+
+    const TELEGRAM: &[u8] = b"/ISk5\\2MT382-1000\r\n\r\n\
+        1-3:0.2.8(50)\r\n\
+        0-0:1.0.0(101209113020W)\r\n\
+        1-0:1.8.1(123456.789*kWh)\r\n\
+        1-0:1.7.0(01.193*kW)\r\n\
+        1-0:2.7.0(00.200*kW)\r\n\
+        1-0:21.7.0(00.000*kW)\r\n\
+        1-0:22.7.0(00.310*kW)\r\n\
+        0-1:24.1.0(003)\r\n\
+        0-1:96.1.0(3232323241424344313233343536373839)\r\n\
+        0-1:24.2.1(101209112500W)(12785.123*m3)\r\n\
+        !\r\n";
+
+    fn find(entries: &[SmlListEntry], obis: Obis) -> &SmlListEntry {
+        entries
+            .iter()
+            .find(|e| e.object_name == obis.obis_number())
+            .unwrap()
+    }
+
+    #[test]
+    pub fn derives_sum_active_power() {
+        let telegram = parse_telegram(TELEGRAM).unwrap();
+
+        let entries = telegram.list_entries();
+
+        let sum = find(&entries, Obis::SumActiveInstantaneousPower);
+        assert_eq!(sum.value, AnyValue::Signed(993));
+        assert_eq!(sum.unit, Some(WATT));
+        assert_eq!(sum.scaler, Some(0));
+        let l1 = find(&entries, Obis::SumActiveInstantaneousPowerPhaseL1);
+        assert_eq!(l1.value, AnyValue::Signed(-310));
+    }
+
+    #[test]
+    pub fn skips_balance_on_overflow() {
+        let entry = |value, scaler| SmlListEntry {
+            object_name: vec![],
+            status: None,
+            value_time: vec![],
+            unit: Some(WATT),
+            scaler: Some(scaler),
+            value: AnyValue::Signed(value),
+        };
+
+        let balance_of = |p, n| balance(&[], &p, &n).map(|entry| entry.value);
+
+        assert_eq!(
+            balance_of(entry(2, 1), entry(5, 0)),
+            Some(AnyValue::Signed(15))
+        );
+        assert_eq!(balance_of(entry(1, 127), entry(1, -128)), None);
+        assert_eq!(balance_of(entry(isize::MAX, 1), entry(1, 0)), None);
+        assert_eq!(balance_of(entry(isize::MIN, 0), entry(1, 0)), None);
+    }
+
+    #[test]
+    pub fn reads_header_fields() {
+        let telegram = parse_telegram(TELEGRAM).unwrap();
+
+        assert_eq!(telegram.version(), Some("50"));
+        assert_eq!(telegram.timestamp().map(|t| t.second), Some(20));
+    }
+
+    #[test]
+    pub fn reads_mbus_channels() {
+        let telegram = parse_telegram(TELEGRAM).unwrap();
+
+        let channels = telegram.mbus_channels();
+
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].channel, 1);
+        assert_eq!(channels[0].device_type, Some(3));
+        assert_eq!(
+            channels[0].equipment_id.as_deref(),
+            Some("2222ABCD123456789")
+        );
+        assert_eq!(channels[0].timestamp.map(|t| t.minute), Some(25));
+        let reading = channels[0].reading.as_ref().unwrap();
+        assert_eq!(reading.value, AnyValue::Signed(12785123));
+        assert_eq!(reading.unit, Some(CUBIC_METRE));
+        assert_eq!(reading.scaler, Some(-3));
+    }
+
+    #[test]
+    pub fn reads_gas_of_dsmr_2_2() {
+        let telegram = parse_telegram(
+            b"/KFM5KAIFA-METER\r\n\r\n\
+            0-1:24.3.0(090212160000)(00)(60)(1)(0-1:24.2.1)(m3)\r\n\
+            (00001.001)\r\n\
+            !\r\n",
+        )
+        .unwrap();
+
+        let channels = telegram.mbus_channels();
+
+        assert_eq!(channels[0].timestamp.map(|t| t.year), Some(2009));
+        let reading = channels[0].reading.as_ref().unwrap();
+        assert_eq!(reading.value, AnyValue::Signed(1001));
+        assert_eq!(reading.unit, Some(CUBIC_METRE));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    pub async fn streams_telegrams() {
+        use tokio_stream::StreamExt;
+
+        let mut input = TELEGRAM.to_vec();
+        input.extend_from_slice(b"/ISk5\\2MT382-1000\r\n\r\n1-0:1.7.0(1*kW)\r\n!0000\r\n");
+        input.extend_from_slice(TELEGRAM);
+        let mut stream = dsmr_telegram_stream(std::io::Cursor::new(input));
+
+        let mut count = 0;
+        while stream.next().await.is_some() {
+            count += 1;
+        }
+
+        assert_eq!(count, 2);
+    }
+}
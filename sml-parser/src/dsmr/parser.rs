@@ -0,0 +1,178 @@
+use crate::{
+    application::parser::ParseError,
+    d0::parser::parse_data_block,
+    dsmr::{DsmrError, DsmrTelegram, DsmrTimestamp},
+};
+
+/// Parse a P1 telegram (`/` identification ... `!` checksum)
+///
+/// The checksum after `!` (DSMR 4 and newer) is validated, telegrams of older versions
+/// do not carry one.
+pub fn parse_telegram(input: &[u8]) -> Result<DsmrTelegram, DsmrError> {
+    let start = input
+        .iter()
+        .position(|b| *b == b'/')
+        .ok_or(DsmrError::Parse(ParseError::Unknown))?;
+    let end = start
+        + input[start..]
+            .iter()
+            .position(|b| *b == b'!')
+            .ok_or(DsmrError::Parse(ParseError::Unknown))?;
+
+    let checksum = String::from_utf8_lossy(&input[end + 1..]);
+    let checksum = match checksum.trim() {
+        "" => None,
+        checksum => {
+            let expected = u16::from_str_radix(checksum, 16)
+                .map_err(|_| DsmrError::Parse(ParseError::Unknown))?;
+            let actual = crc16(&input[start..=end]);
+            if expected != actual {
+                return Err(DsmrError::ChecksumMismatch { expected, actual });
+            }
+            Some(expected)
+        }
+    };
+
+    let telegram = String::from_utf8_lossy(&input[start + 1..=end]);
+    let (identification, data_block) = telegram
+        .split_once('\n')
+        .ok_or(DsmrError::Parse(ParseError::Unknown))?;
+    let data_block = data_block
+        .strip_prefix("\r\n")
+        .or_else(|| data_block.strip_prefix('\n'))
+        .unwrap_or(data_block);
+    let data_lines = parse_data_block(data_block).map_err(DsmrError::Parse)?;
+
+    Ok(DsmrTelegram {
+        identification: identification.trim_end().to_string(),
+        data_lines,
+        checksum,
+    })
+}
+
+/// Parse a timestamp like `101209113020W` (YYMMDDhhmmss, `W` for winter and `S` for
+/// summer time, missing in DSMR 2.2)
+pub fn parse_timestamp(input: &str) -> Option<DsmrTimestamp> {
+    let (digits, daylight_saving_time) = match input.as_bytes().last()? {
+        b'W' => (&input[..input.len() - 1], Some(false)),
+        b'S' => (&input[..input.len() - 1], Some(true)),
+        _ => (input, None),
+    };
+    if digits.len() != 12 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let field = |i: usize| digits[i..i + 2].parse::<u8>().ok();
+    Some(DsmrTimestamp {
+        year: 2000 + field(0)? as u16,
+        month: field(2)?,
+        day: field(4)?,
+        hour: field(6)?,
+        minute: field(8)?,
+        second: field(10)?,
+        daylight_saving_time,
+    })
+}
+
+/// CRC16 as used by DSMR 4 and newer (polynomial 0x8005 reflected, initial value 0),
+/// computed from `/` up to and including `!`
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in data {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            if crc & 1 == 1 {
+                crc = (crc >> 1) ^ 0xa001;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DSMR_5: &[u8] = b"/ISk5\\2MT382-1000\r\n\r\n\
+        1-3:0.2.8(50)\r\n\
+        0-0:1.0.0(101209113020W)\r\n\
+        1-0:1.8.1(123456.789*kWh)\r\n\
+        1-0:1.7.0(01.193*kW)\r\n\
+        1-0:2.7.0(00.000*kW)\r\n\
+        0-1:24.1.0(003)\r\n\
+        0-1:24.2.1(101209112500W)(12785.123*m3)\r\n\
+        !";
+
+    fn with_checksum(telegram: &[u8]) -> Vec<u8> {
+        let mut telegram = telegram.to_vec();
+        telegram.extend_from_slice(format!("{:04X}\r\n", crc16(&telegram)).as_bytes());
+        telegram
+    }
+
+    #[test]
+    pub fn computes_checksum() {
+        assert_eq!(crc16(b"123456789"), 0xbb3d);
+    }
+
+    #[test]
+    pub fn parses_telegram_with_checksum() {
+        let telegram = parse_telegram(&with_checksum(DSMR_5)).unwrap();
+
+        assert_eq!(telegram.identification, "ISk5\\2MT382-1000");
+        assert_eq!(telegram.checksum, Some(crc16(DSMR_5)));
+        assert_eq!(telegram.data_lines.len(), 7);
+        assert_eq!(
+            telegram.data_lines[6].object_name,
+            vec![0, 1, 24, 2, 1, 255]
+        );
+    }
+
+    #[test]
+    pub fn rejects_wrong_checksum() {
+        let mut input = DSMR_5.to_vec();
+        input.extend_from_slice(b"0000\r\n");
+
+        let result = parse_telegram(&input);
+
+        assert!(matches!(
+            result,
+            Err(DsmrError::ChecksumMismatch { expected: 0, .. })
+        ));
+    }
+
+    #[test]
+    pub fn parses_telegram_without_checksum() {
+        let input = b"/KFM5KAIFA-METER\r\n\r\n\
+            1-0:1.8.1(00123.456*kWh)\r\n\
+            0-1:24.3.0(090212160000)(00)(60)(1)(0-1:24.2.1)(m3)\r\n\
+            (00001.001)\r\n\
+            !\r\n";
+
+        let telegram = parse_telegram(input).unwrap();
+
+        assert_eq!(telegram.checksum, None);
+        assert_eq!(telegram.data_lines.len(), 2);
+    }
+
+    #[test]
+    pub fn parses_timestamps() {
+        assert_eq!(
+            parse_timestamp("101209113020W"),
+            Some(DsmrTimestamp {
+                year: 2010,
+                month: 12,
+                day: 9,
+                hour: 11,
+                minute: 30,
+                second: 20,
+                daylight_saving_time: Some(false),
+            })
+        );
+        assert_eq!(
+            parse_timestamp("090212160000").map(|t| t.daylight_saving_time),
+            Some(None)
+        );
+        assert_eq!(parse_timestamp("1012091130"), None);
+    }
+}
@@ -15,6 +15,38 @@
 //! This reflects the main use-case for using this crate: It converts a byte-stream
 //! to a stream of valid SML messages.
 //!
+//! # Client
+//! Some meters and gateways only send data on request. The [client] module sends
+//! requests and waits for the matching responses.
+//!
+//! # D0
+//! Meters speaking IEC 62056-21 instead of SML are supported by the [d0] module. Its
+//! readings are converted into the same list entries as SML readings.
+//!
+//! # DSMR
+//! The [dsmr] module reads the P1 port of Dutch, Belgian and Luxembourgian meters,
+//! including the readings of attached gas and water meters.
+//!
+//! # M-Bus
+//! Heat, gas and water meters sending wired or wireless M-Bus telegrams are decoded by
+//! the [mbus] module, including AES decryption of OMS telegrams.
+//!
+//! # Capture
+//! The [capture] module records raw byte streams with timestamps and replays them,
+//! e.g. to reproduce problems with a particular meter.
+//!
+//! # Features
+//! Everything reading from or writing to devices (message streams, [client], recording
+//! and replaying captures, D0 mode C) needs the `tokio` feature, which is enabled by default. Without it the
+//! parsers compile to `wasm32-unknown-unknown`.
+//!
 pub mod application;
+pub mod capture;
+#[cfg(feature = "tokio")]
+pub mod client;
+pub mod d0;
+pub mod dsmr;
+pub mod mbus;
+#[cfg(feature = "tokio")]
 pub mod message_stream;
 pub mod transport;
@@ -0,0 +1,52 @@
+//! AES-128 decryption of encrypted telegrams (security profiles of OMS volume 2)
+
+use aes::Aes128;
+use cbc::cipher::{block_padding::NoPadding, BlockDecryptMut, KeyIvInit};
+use cmac::{Cmac, Mac};
+
+use crate::mbus::MbusError;
+
+/// Mode 5: AES-CBC with the IV built from manufacturer, address and access number
+pub(crate) fn decrypt_mode_5(
+    key: &[u8; 16],
+    manufacturer: [u8; 2],
+    address: [u8; 6],
+    access_number: u8,
+    data: &[u8],
+) -> Result<Vec<u8>, MbusError> {
+    let mut iv = [access_number; 16];
+    iv[..2].copy_from_slice(&manufacturer);
+    iv[2..8].copy_from_slice(&address);
+    decrypt(key, &iv, data)
+}
+
+/// Mode 7: AES-CBC with a zero IV and a key derived from the message counter
+pub(crate) fn decrypt_mode_7(
+    key: &[u8; 16],
+    message_counter: u32,
+    identification: [u8; 4],
+    data: &[u8],
+) -> Result<Vec<u8>, MbusError> {
+    let mut input = [0x07; 16];
+    // derivation constant of the encryption key for messages from the meter
+    input[0] = 0x00;
+    input[1..5].copy_from_slice(&message_counter.to_le_bytes());
+    input[5..9].copy_from_slice(&identification);
+    let mut mac =
+        <Cmac<Aes128> as Mac>::new_from_slice(key).map_err(|_| MbusError::DecryptionFailed)?;
+    mac.update(&input);
+    let derived: [u8; 16] = mac.finalize().into_bytes().into();
+    decrypt(&derived, &[0; 16], data)
+}
+
+fn decrypt(key: &[u8; 16], iv: &[u8; 16], data: &[u8]) -> Result<Vec<u8>, MbusError> {
+    let mut buffer = data.to_vec();
+    cbc::Decryptor::<Aes128>::new(key.into(), iv.into())
+        .decrypt_padded_mut::<NoPadding>(&mut buffer)
+        .map_err(|_| MbusError::DecryptionFailed)?;
+    // every encrypted payload starts with two idle fillers
+    if !buffer.starts_with(&[0x2f, 0x2f]) {
+        return Err(MbusError::DecryptionFailed);
+    }
+    Ok(buffer)
+}
@@ -0,0 +1,159 @@
+//! Link layer of wired M-Bus (EN 13757-2) and wireless M-Bus (EN 13757-4)
+
+use crate::mbus::MbusError;
+
+/// Address of a device in the link layer of wireless M-Bus or the long header of
+/// the application layer
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub(crate) struct DeviceAddress {
+    pub manufacturer: [u8; 2],
+    /// identification number as BCD, least significant byte first
+    pub identification: [u8; 4],
+    pub version: u8,
+    pub device_type: u8,
+}
+
+impl DeviceAddress {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            identification: [bytes[0], bytes[1], bytes[2], bytes[3]],
+            manufacturer: [bytes[4], bytes[5]],
+            version: bytes[6],
+            device_type: bytes[7],
+        }
+    }
+
+    /// The address as used for the IV of mode 5 (identification, version, device type)
+    pub fn address(&self) -> [u8; 6] {
+        let i = self.identification;
+        [i[0], i[1], i[2], i[3], self.version, self.device_type]
+    }
+}
+
+/// Wired long frame `68 L L 68 C A CI ... CS 16`, returning C field, primary address
+/// and the data starting with the CI field
+pub(crate) fn parse_wired_frame(frame: &[u8]) -> Result<(u8, u8, &[u8]), MbusError> {
+    let [0x68, length, length_repeated, 0x68, ..] = frame[..] else {
+        return Err(MbusError::InvalidFrame);
+    };
+    let length = length as usize;
+    if length != length_repeated as usize || length < 3 || frame.len() != length + 6 {
+        return Err(MbusError::InvalidFrame);
+    }
+    if frame[length + 5] != 0x16 {
+        return Err(MbusError::InvalidFrame);
+    }
+    let user_data = &frame[4..4 + length];
+    let checksum = user_data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    if checksum != frame[4 + length] {
+        return Err(MbusError::ChecksumMismatch);
+    }
+    Ok((user_data[0], user_data[1], &user_data[2..]))
+}
+
+/// Wireless frame `L C M A CI ...` without block CRCs, returning the C field, the
+/// device address of the link layer and the data starting with the CI field
+pub(crate) fn parse_wireless_frame(frame: &[u8]) -> Result<(u8, DeviceAddress, &[u8]), MbusError> {
+    if frame.len() < 11 || frame[0] as usize != frame.len() - 1 {
+        return Err(MbusError::InvalidFrame);
+    }
+    let address = DeviceAddress {
+        manufacturer: [frame[2], frame[3]],
+        identification: [frame[4], frame[5], frame[6], frame[7]],
+        version: frame[8],
+        device_type: frame[9],
+    };
+    Ok((frame[1], address, &frame[10..]))
+}
+
+/// Check and remove the block CRCs of a wireless frame in format A
+///
+/// Many receivers already strip them, in that case the frame can be passed to the
+/// decoder directly.
+pub fn remove_frame_a_crcs(frame: &[u8]) -> Result<Vec<u8>, MbusError> {
+    let length = *frame.first().ok_or(MbusError::InvalidFrame)? as usize;
+    let mut data = Vec::with_capacity(length + 1);
+    let mut rest = frame;
+    let mut block_length = 10;
+    while data.len() < length + 1 {
+        let block_length_with_crc = block_length.min(length + 1 - data.len()) + 2;
+        if rest.len() < block_length_with_crc {
+            return Err(MbusError::InvalidFrame);
+        }
+        let (block, remaining) = rest.split_at(block_length_with_crc);
+        let (block, crc) = block.split_at(block.len() - 2);
+        if crc16(block).to_be_bytes() != crc {
+            return Err(MbusError::ChecksumMismatch);
+        }
+        data.extend_from_slice(block);
+        rest = remaining;
+        block_length = 16;
+    }
+    Ok(data)
+}
+
+/// CRC16 of EN 13757 (polynomial 0x3D65, initial value 0, inverted)
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            if crc & 0x8000 != 0 {
+                crc = (crc << 1) ^ 0x3d65;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    !crc
+}
+
+/// Short frame requesting class 2 data (REQ_UD2) from the device with the primary address
+pub fn request_data_frame(address: u8) -> [u8; 5] {
+    let control = 0x5b;
+    [0x10, control, address, control.wrapping_add(address), 0x16]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn computes_checksum() {
+        assert_eq!(crc16(b"123456789"), 0xc2b7);
+    }
+
+    #[test]
+    pub fn removes_block_crcs() {
+        let frame = (0..30u8).collect::<Vec<_>>();
+        let frame = [vec![29], frame[1..].to_vec()].concat();
+        let mut with_crcs = vec![];
+        for block in [&frame[..10], &frame[10..26], &frame[26..]] {
+            with_crcs.extend_from_slice(block);
+            with_crcs.extend_from_slice(&crc16(block).to_be_bytes());
+        }
+
+        assert_eq!(remove_frame_a_crcs(&with_crcs).unwrap(), frame);
+
+        with_crcs[0] = 28;
+        assert!(matches!(
+            remove_frame_a_crcs(&with_crcs),
+            Err(MbusError::ChecksumMismatch)
+        ));
+    }
+
+    #[test]
+    pub fn rejects_wired_frame_with_wrong_checksum() {
+        let frame = [0x68, 0x03, 0x03, 0x68, 0x08, 0x01, 0x72, 0x00, 0x16];
+
+        assert!(matches!(
+            parse_wired_frame(&frame),
+            Err(MbusError::ChecksumMismatch)
+        ));
+    }
+
+    #[test]
+    pub fn builds_request() {
+        assert_eq!(request_data_frame(1), [0x10, 0x5b, 0x01, 0x5c, 0x16]);
+    }
+}
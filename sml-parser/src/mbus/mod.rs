@@ -0,0 +1,443 @@
+//! Wired M-Bus (EN 13757-2/3) and wireless M-Bus (EN 13757-4, OMS) telegrams as sent
+//! by heat, gas and water meters
+//!
+//! Encrypted telegrams (security modes 5 and 7) are decrypted with the keys configured
+//! in the [MbusDecoder].
+
+use std::collections::HashMap;
+
+use crate::application::domain::SmlListEntry;
+
+use self::{
+    crypto::{decrypt_mode_5, decrypt_mode_7},
+    frame::{parse_wired_frame, parse_wireless_frame, DeviceAddress},
+    records::{parse_records, DataRecord, Function, Quantity},
+};
+
+mod crypto;
+pub mod frame;
+pub mod records;
+
+/// Device types (media) of the M-Bus header
+pub mod device_type {
+    pub const ELECTRICITY: u8 = 0x02;
+    pub const GAS: u8 = 0x03;
+    pub const HEAT: u8 = 0x04;
+    pub const WARM_WATER: u8 = 0x06;
+    pub const WATER: u8 = 0x07;
+    pub const HEAT_COST_ALLOCATOR: u8 = 0x08;
+    pub const COOLING_OUTLET: u8 = 0x0a;
+    pub const COOLING_INLET: u8 = 0x0b;
+    pub const HEAT_INLET: u8 = 0x0c;
+    pub const HEAT_COOLING: u8 = 0x0d;
+    pub const HOT_WATER: u8 = 0x15;
+    pub const COLD_WATER: u8 = 0x16;
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct MbusTelegram {
+    pub c_field: u8,
+    /// primary address of wired M-Bus
+    pub primary_address: Option<u8>,
+    /// three letter manufacturer code (FLAG association)
+    pub manufacturer: String,
+    pub identification: u32,
+    pub version: u8,
+    pub device_type: u8,
+    pub access_number: Option<u8>,
+    pub status: Option<u8>,
+    pub records: Vec<DataRecord>,
+}
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum MbusError {
+    InvalidFrame,
+    ChecksumMismatch,
+    InvalidRecord,
+    UnsupportedCiField(u8),
+    UnsupportedSecurityMode(u8),
+    /// the telegram is encrypted, but there is no key for the device
+    MissingKey(u32),
+    /// the payload did not decrypt to a valid telegram, usually caused by a wrong key
+    DecryptionFailed,
+}
+
+/// Decoder for M-Bus telegrams holding the AES keys of encrypted devices
+#[derive(Debug, Clone, Default)]
+pub struct MbusDecoder {
+    /// AES-128 keys by identification number of the device
+    pub keys: HashMap<u32, [u8; 16]>,
+}
+
+impl MbusDecoder {
+    /// Decode a wired long frame (`68 L L 68 ... 16`)
+    pub fn decode_wired(&self, frame: &[u8]) -> Result<MbusTelegram, MbusError> {
+        let (c_field, primary_address, data) = parse_wired_frame(frame)?;
+        let mut telegram = self.decode_application_layer(c_field, None, data)?;
+        telegram.primary_address = Some(primary_address);
+        Ok(telegram)
+    }
+
+    /// Decode a wireless frame (`L C M A CI ...`) without block CRCs, cf.
+    /// [frame::remove_frame_a_crcs]
+    pub fn decode_wireless(&self, frame: &[u8]) -> Result<MbusTelegram, MbusError> {
+        let (c_field, address, data) = parse_wireless_frame(frame)?;
+        self.decode_application_layer(c_field, Some(address), data)
+    }
+
+    fn decode_application_layer(
+        &self,
+        c_field: u8,
+        link_address: Option<DeviceAddress>,
+        data: &[u8],
+    ) -> Result<MbusTelegram, MbusError> {
+        let (message_counter, data) = match data.first() {
+            Some(0x90) => authentication_and_fragmentation_layer(data)?,
+            _ => (None, data),
+        };
+        let ci_field = *data.first().ok_or(MbusError::InvalidFrame)?;
+        let (address, header_length) = match ci_field {
+            // long header with the address of the device
+            0x72 => (
+                Some(DeviceAddress::from_bytes(
+                    data.get(1..9).ok_or(MbusError::InvalidFrame)?,
+                )),
+                12,
+            ),
+            0x7a => (link_address, 4),
+            0x78 => (link_address, 0),
+            _ => return Err(MbusError::UnsupportedCiField(ci_field)),
+        };
+        // access number, status and configuration at the end of the header
+        let header = data
+            .get(1 + header_length - header_length.min(4)..1 + header_length)
+            .ok_or(MbusError::InvalidFrame)?;
+        let mut payload = &data[1 + header_length..];
+        let address = address.unwrap_or(DeviceAddress {
+            manufacturer: [0, 0],
+            identification: [0; 4],
+            version: 0,
+            device_type: 0,
+        });
+        let identification = bcd_identification(address.identification);
+
+        let decrypted;
+        if let [access_number, _status, configuration_low, configuration_high] = header[..] {
+            let configuration = u16::from_le_bytes([configuration_low, configuration_high]);
+            let mode = ((configuration >> 8) & 0x1f) as u8;
+            let encrypted_length = ((configuration >> 4) & 0x0f) as usize * 16;
+            if mode != 0 {
+                if mode == 7 {
+                    // configuration field extension
+                    payload = payload.get(1..).ok_or(MbusError::InvalidFrame)?;
+                }
+                let key = self
+                    .keys
+                    .get(&identification)
+                    .ok_or(MbusError::MissingKey(identification))?;
+                let encrypted = payload
+                    .get(..encrypted_length)
+                    .ok_or(MbusError::InvalidFrame)?;
+                let mut plain = match mode {
+                    5 => decrypt_mode_5(
+                        key,
+                        address.manufacturer,
+                        address.address(),
+                        access_number,
+                        encrypted,
+                    )?,
+                    7 => decrypt_mode_7(
+                        key,
+                        message_counter.ok_or(MbusError::InvalidFrame)?,
+                        address.identification,
+                        encrypted,
+                    )?,
+                    _ => return Err(MbusError::UnsupportedSecurityMode(mode)),
+                };
+                plain.extend_from_slice(&payload[encrypted_length..]);
+                decrypted = plain;
+                payload = &decrypted;
+            }
+        }
+
+        Ok(MbusTelegram {
+            c_field,
+            primary_address: None,
+            manufacturer: manufacturer(address.manufacturer),
+            identification,
+            version: address.version,
+            device_type: address.device_type,
+            access_number: header.first().copied(),
+            status: header.get(1).copied(),
+            records: parse_records(payload)?,
+        })
+    }
+}
+
+/// Skip the authentication and fragmentation layer, returning the message counter
+///
+/// The MAC is not verified, a wrong key is detected when decrypting.
+fn authentication_and_fragmentation_layer(data: &[u8]) -> Result<(Option<u32>, &[u8]), MbusError> {
+    let length = *data.get(1).ok_or(MbusError::InvalidFrame)? as usize;
+    let afl = data.get(2..2 + length).ok_or(MbusError::InvalidFrame)?;
+    let [control_low, control_high, ..] = afl[..] else {
+        return Err(MbusError::InvalidFrame);
+    };
+    let fragmentation_control = u16::from_le_bytes([control_low, control_high]);
+    let mut position = 2;
+    // message control
+    if fragmentation_control & 0x2000 != 0 {
+        position += 1;
+    }
+    // key information
+    if fragmentation_control & 0x0200 != 0 {
+        position += 2;
+    }
+    let message_counter = if fragmentation_control & 0x0800 != 0 {
+        let counter = afl
+            .get(position..position + 4)
+            .ok_or(MbusError::InvalidFrame)?;
+        Some(u32::from_le_bytes([
+            counter[0], counter[1], counter[2], counter[3],
+        ]))
+    } else {
+        None
+    };
+    Ok((message_counter, &data[2 + length..]))
+}
+
+fn bcd_identification(bytes: [u8; 4]) -> u32 {
+    bytes.iter().rev().fold(0, |id, byte| {
+        id * 100 + (byte >> 4) as u32 * 10 + (byte & 0x0f) as u32
+    })
+}
+
+/// Manufacturer code from three letters packed into five bits each
+fn manufacturer(bytes: [u8; 2]) -> String {
+    let code = u16::from_le_bytes(bytes);
+    [10, 5, 0]
+        .iter()
+        .map(|shift| (((code >> shift) & 0x1f) as u8 + 64) as char)
+        .collect()
+}
+
+impl MbusTelegram {
+    /// The current readings in the same form as the entries of an SML list
+    ///
+    /// Records are mapped to the OBIS numbers of the device's medium (e.g. `8-b:1.0.0`
+    /// for the volume of a water meter) with `channel` as value group B. Historic
+    /// values, tariffs and quantities without an OBIS counterpart are left out, they
+    /// are available in [MbusTelegram::records].
+    pub fn list_entries(&self, channel: u8) -> Vec<SmlListEntry> {
+        self.records
+            .iter()
+            .filter(|record| {
+                record.function == Function::Instantaneous
+                    && record.storage_number == 0
+                    && record.tariff == 0
+                    && record.subunit == 0
+            })
+            .filter_map(|record| {
+                let (a, c, d) = obis_number(self.device_type, record.quantity)?;
+                Some(SmlListEntry {
+                    object_name: vec![a, channel, c, d, 0, 255],
+                    status: None,
+                    value_time: vec![],
+                    unit: record.unit,
+                    scaler: Some(record.scaler),
+                    value: record.value.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Value groups A, C and D of a quantity measured by a device type
+fn obis_number(device_type: u8, quantity: Quantity) -> Option<(u8, u8, u8)> {
+    use device_type::*;
+    match device_type {
+        ELECTRICITY => match quantity {
+            Quantity::Energy => Some((1, 1, 8)),
+            Quantity::Power => Some((1, 1, 7)),
+            _ => None,
+        },
+        GAS => match quantity {
+            Quantity::Volume => Some((7, 3, 0)),
+            Quantity::VolumeFlow => Some((7, 43, 0)),
+            _ => None,
+        },
+        HEAT | HEAT_INLET | HEAT_COOLING | COOLING_OUTLET | COOLING_INLET => {
+            let a = match device_type {
+                COOLING_OUTLET | COOLING_INLET => 5,
+                _ => 6,
+            };
+            let c = match quantity {
+                Quantity::Energy => 1,
+                Quantity::Volume => 2,
+                Quantity::Power => 8,
+                Quantity::VolumeFlow => 9,
+                Quantity::FlowTemperature => 10,
+                Quantity::ReturnTemperature => 11,
+                Quantity::TemperatureDifference => 12,
+                _ => return None,
+            };
+            Some((a, c, 0))
+        }
+        HEAT_COST_ALLOCATOR => match quantity {
+            Quantity::HeatCostAllocatorUnits => Some((4, 1, 0)),
+            _ => None,
+        },
+        WATER | COLD_WATER | WARM_WATER | HOT_WATER => {
+            let a = match device_type {
+                WATER | COLD_WATER => 8,
+                _ => 9,
+            };
+            match quantity {
+                Quantity::Volume => Some((a, 1, 0)),
+                Quantity::VolumeFlow => Some((a, 2, 0)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use aes::Aes128;
+    use cbc::cipher::{block_padding::NoPadding, BlockEncryptMut, KeyIvInit};
+    use cmac::{Cmac, Mac};
+
+    use super::*;
+    use crate::application::{domain::AnyValue, units::CUBIC_METRE};
+
+    const KEY: [u8; 16] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f,
+    ];
+
+    // volume 12.565 m³, padded with idle fillers to one block
+    const RECORDS: [u8; 16] = [
+        0x2f, 0x2f, 0x03, 0x13, 0x15, 0x31, 0x00, 0x2f, 0x2f, 0x2f, 0x2f, 0x2f, 0x2f, 0x2f, 0x2f,
+        0x2f,
+    ];
+
+    fn encrypt(key: &[u8; 16], iv: &[u8; 16], data: &[u8]) -> Vec<u8> {
+        let mut buffer = data.to_vec();
+        cbc::Encryptor::<Aes128>::new(key.into(), iv.into())
+            .encrypt_padded_mut::<NoPadding>(&mut buffer, data.len())
+            .unwrap();
+        buffer
+    }
+
+    #[test]
+    pub fn decodes_wired_frame() {
+        let frame = [
+            0x68, 0x1f, 0x1f, 0x68, 0x08, 0x02, 0x72, 0x78, 0x56, 0x34, 0x12, 0x24, 0x40, 0x01,
+            0x07, 0x55, 0x00, 0x00, 0x00, 0x03, 0x13, 0x15, 0x31, 0x00, 0xda, 0x02, 0x3b, 0x13,
+            0x01, 0x8b, 0x60, 0x04, 0x37, 0x18, 0x02, 0x18, 0x16,
+        ];
+
+        let telegram = MbusDecoder::default().decode_wired(&frame).unwrap();
+
+        assert_eq!(telegram.primary_address, Some(2));
+        assert_eq!(telegram.manufacturer, "PAD");
+        assert_eq!(telegram.identification, 12345678);
+        assert_eq!(telegram.device_type, device_type::WATER);
+        assert_eq!(telegram.access_number, Some(0x55));
+        assert_eq!(telegram.records.len(), 3);
+        assert_eq!(
+            telegram.list_entries(1),
+            vec![SmlListEntry {
+                object_name: vec![8, 1, 1, 0, 0, 255],
+                status: None,
+                value_time: vec![],
+                unit: Some(CUBIC_METRE),
+                scaler: Some(-3),
+                value: AnyValue::Signed(12565),
+            }]
+        );
+    }
+
+    #[test]
+    pub fn decrypts_mode_5() {
+        // link layer: manufacturer KAM, identification 12345678, version 1, gas
+        let link = [0x44, 0x2d, 0x2c, 0x78, 0x56, 0x34, 0x12, 0x01, 0x03];
+        let mut iv = [0x2a; 16];
+        iv[..8].copy_from_slice(&[0x2d, 0x2c, 0x78, 0x56, 0x34, 0x12, 0x01, 0x03]);
+        let mut frame = vec![0];
+        frame.extend_from_slice(&link);
+        // short header: access number 0x2a, status 0, 1 block encrypted in mode 5
+        frame.extend_from_slice(&[0x7a, 0x2a, 0x00, 0x10, 0x05]);
+        frame.extend_from_slice(&encrypt(&KEY, &iv, &RECORDS));
+        frame[0] = frame.len() as u8 - 1;
+
+        let decoder = MbusDecoder {
+            keys: HashMap::from([(12345678, KEY)]),
+        };
+        let telegram = decoder.decode_wireless(&frame).unwrap();
+
+        assert_eq!(telegram.manufacturer, "KAM");
+        assert_eq!(
+            telegram.list_entries(1)[0].object_name,
+            vec![7, 1, 3, 0, 0, 255]
+        );
+        assert_eq!(telegram.records[0].value, AnyValue::Signed(12565));
+
+        let wrong_key = MbusDecoder {
+            keys: HashMap::from([(12345678, [0; 16])]),
+        };
+        assert!(matches!(
+            wrong_key.decode_wireless(&frame),
+            Err(MbusError::DecryptionFailed)
+        ));
+        assert!(matches!(
+            MbusDecoder::default().decode_wireless(&frame),
+            Err(MbusError::MissingKey(12345678))
+        ));
+    }
+
+    #[test]
+    pub fn decrypts_mode_7() {
+        let link = [0x44, 0x2d, 0x2c, 0x78, 0x56, 0x34, 0x12, 0x01, 0x07];
+        let counter: u32 = 0x0102;
+        let mut input = [0x07; 16];
+        input[0] = 0x00;
+        input[1..5].copy_from_slice(&counter.to_le_bytes());
+        input[5..9].copy_from_slice(&[0x78, 0x56, 0x34, 0x12]);
+        let mut mac = <Cmac<Aes128> as Mac>::new_from_slice(&KEY).unwrap();
+        mac.update(&input);
+        let derived: [u8; 16] = mac.finalize().into_bytes().into();
+
+        let mut frame = vec![0];
+        frame.extend_from_slice(&link);
+        // AFL with message counter, then short header with configuration extension
+        frame.extend_from_slice(&[0x90, 0x06, 0x00, 0x08, 0x02, 0x01, 0x00, 0x00]);
+        frame.extend_from_slice(&[0x7a, 0x01, 0x00, 0x10, 0x07, 0x10]);
+        frame.extend_from_slice(&encrypt(&derived, &[0; 16], &RECORDS));
+        frame[0] = frame.len() as u8 - 1;
+
+        let decoder = MbusDecoder {
+            keys: HashMap::from([(12345678, KEY)]),
+        };
+        let telegram = decoder.decode_wireless(&frame).unwrap();
+
+        assert_eq!(
+            telegram.list_entries(2)[0].object_name,
+            vec![8, 2, 1, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    pub fn rejects_unknown_ci_field() {
+        let frame = [
+            0x0b, 0x44, 0x2d, 0x2c, 0x78, 0x56, 0x34, 0x12, 0x01, 0x07, 0x51, 0x00,
+        ];
+
+        assert!(matches!(
+            MbusDecoder::default().decode_wireless(&frame),
+            Err(MbusError::UnsupportedCiField(0x51))
+        ));
+    }
+}
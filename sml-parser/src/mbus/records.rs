@@ -0,0 +1,397 @@
+//! Data records of the application layer (EN 13757-3): DIF, DIFEs, VIF, VIFEs and data
+
+use crate::{
+    application::{domain::AnyValue, units::*},
+    mbus::MbusError,
+};
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Function {
+    Instantaneous,
+    Maximum,
+    Minimum,
+    /// value during error state
+    Error,
+}
+
+/// Physical quantity given by the primary VIF
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Quantity {
+    Energy,
+    Volume,
+    Mass,
+    OnTime,
+    OperatingTime,
+    Power,
+    VolumeFlow,
+    MassFlow,
+    FlowTemperature,
+    ReturnTemperature,
+    TemperatureDifference,
+    ExternalTemperature,
+    Pressure,
+    Date,
+    DateTime,
+    HeatCostAllocatorUnits,
+    AveragingDuration,
+    ActualityDuration,
+    FabricationNumber,
+    EnhancedIdentification,
+    BusAddress,
+    /// extension tables, plain text and manufacturer specific VIFs
+    Other,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct DataRecord {
+    pub function: Function,
+    /// 0 for the current value, historic values otherwise
+    pub storage_number: u64,
+    pub tariff: u32,
+    pub subunit: u16,
+    pub quantity: Quantity,
+    /// DLMS unit code as used in SML list entries
+    pub unit: Option<u8>,
+    pub scaler: i8,
+    pub value: AnyValue,
+    /// VIF and VIFEs as sent by the meter
+    pub vif: Vec<u8>,
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn byte(&mut self) -> Result<u8, MbusError> {
+        let byte = *self
+            .data
+            .get(self.position)
+            .ok_or(MbusError::InvalidRecord)?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], MbusError> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + n)
+            .ok_or(MbusError::InvalidRecord)?;
+        self.position += n;
+        Ok(bytes)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+}
+
+/// Parse the data records following the header of the application layer
+///
+/// Records without data (selections for readout) are skipped, manufacturer specific
+/// data at the end is ignored.
+pub fn parse_records(data: &[u8]) -> Result<Vec<DataRecord>, MbusError> {
+    let mut cursor = Cursor { data, position: 0 };
+    let mut records = vec![];
+
+    while !cursor.is_empty() {
+        let dif = cursor.byte()?;
+        match dif {
+            // idle filler
+            0x2f => continue,
+            // manufacturer specific data up to the end
+            0x0f | 0x1f => break,
+            _ => {}
+        }
+
+        let function = match (dif >> 4) & 0x03 {
+            0 => Function::Instantaneous,
+            1 => Function::Maximum,
+            2 => Function::Minimum,
+            _ => Function::Error,
+        };
+        let mut storage_number = ((dif >> 6) & 0x01) as u64;
+        let mut tariff = 0;
+        let mut subunit = 0;
+        let mut extension = dif & 0x80 != 0;
+        let mut i = 0;
+        while extension {
+            let dife = cursor.byte()?;
+            if i >= 10 {
+                return Err(MbusError::InvalidRecord);
+            }
+            storage_number |= ((dife & 0x0f) as u64) << (1 + 4 * i);
+            tariff |= (((dife >> 4) & 0x03) as u32) << (2 * i);
+            subunit |= (((dife >> 6) & 0x01) as u16) << i;
+            extension = dife & 0x80 != 0;
+            i += 1;
+        }
+
+        let mut vif = vec![cursor.byte()?];
+        while vif[vif.len() - 1] & 0x80 != 0 {
+            vif.push(cursor.byte()?);
+        }
+        if vif[0] & 0x7f == 0x7c {
+            // plain text unit
+            let length = cursor.byte()? as usize;
+            cursor.bytes(length)?;
+        }
+
+        let (quantity, unit, vif_scaler) = quantity(vif[0]);
+        let value = read_value(&mut cursor, dif & 0x0f, quantity)?;
+        let (value, value_scaler) = match value {
+            Some(value) => value,
+            None => continue,
+        };
+
+        records.push(DataRecord {
+            function,
+            storage_number,
+            tariff,
+            subunit,
+            quantity,
+            unit,
+            scaler: vif_scaler + value_scaler,
+            value,
+            vif,
+        });
+    }
+    Ok(records)
+}
+
+/// Quantity, unit and scaler of a primary VIF
+fn quantity(vif: u8) -> (Quantity, Option<u8>, i8) {
+    let n = (vif & 0x07) as i8;
+    let nn = (vif & 0x03) as i8;
+    match vif & 0x7f {
+        0x00..=0x07 => (Quantity::Energy, Some(WATT_HOUR), n - 3),
+        0x08..=0x0f => (Quantity::Energy, Some(JOULE), n),
+        0x10..=0x17 => (Quantity::Volume, Some(CUBIC_METRE), n - 6),
+        0x18..=0x1f => (Quantity::Mass, Some(KILOGRAM), n - 3),
+        0x20..=0x23 => (Quantity::OnTime, Some(duration_unit(vif)), 0),
+        0x24..=0x27 => (Quantity::OperatingTime, Some(duration_unit(vif)), 0),
+        0x28..=0x2f => (Quantity::Power, Some(WATT), n - 3),
+        0x30..=0x37 => (Quantity::Power, Some(JOULE_PER_HOUR), n),
+        0x38..=0x3f => (Quantity::VolumeFlow, Some(CUBIC_METRE_PER_HOUR), n - 6),
+        // m³/min and m³/s have no DLMS unit
+        0x40..=0x47 => (Quantity::VolumeFlow, None, n - 7),
+        0x48..=0x4f => (Quantity::VolumeFlow, None, n - 9),
+        0x50..=0x57 => (Quantity::MassFlow, None, n - 3),
+        0x58..=0x5b => (Quantity::FlowTemperature, Some(DEGREE_CELSIUS), nn - 3),
+        0x5c..=0x5f => (Quantity::ReturnTemperature, Some(DEGREE_CELSIUS), nn - 3),
+        0x60..=0x63 => (Quantity::TemperatureDifference, Some(KELVIN), nn - 3),
+        0x64..=0x67 => (Quantity::ExternalTemperature, Some(DEGREE_CELSIUS), nn - 3),
+        0x68..=0x6b => (Quantity::Pressure, Some(BAR), nn - 3),
+        0x6c => (Quantity::Date, None, 0),
+        0x6d => (Quantity::DateTime, None, 0),
+        0x6e => (Quantity::HeatCostAllocatorUnits, None, 0),
+        0x70..=0x73 => (Quantity::AveragingDuration, Some(duration_unit(vif)), 0),
+        0x74..=0x77 => (Quantity::ActualityDuration, Some(duration_unit(vif)), 0),
+        0x78 => (Quantity::FabricationNumber, None, 0),
+        0x79 => (Quantity::EnhancedIdentification, None, 0),
+        0x7a => (Quantity::BusAddress, None, 0),
+        _ => (Quantity::Other, None, 0),
+    }
+}
+
+fn duration_unit(vif: u8) -> u8 {
+    match vif & 0x03 {
+        0 => SECOND,
+        1 => MINUTE,
+        2 => HOUR,
+        _ => DAY,
+    }
+}
+
+/// Read the data of a record, returning the value and an additional scaler
+/// (`None` for records without data)
+fn read_value(
+    cursor: &mut Cursor,
+    data_field: u8,
+    quantity: Quantity,
+) -> Result<Option<(AnyValue, i8)>, MbusError> {
+    let value = match data_field {
+        0x00 | 0x08 => return Ok(None),
+        0x02 if quantity == Quantity::Date => AnyValue::String(date(cursor.bytes(2)?)),
+        0x04 if quantity == Quantity::DateTime => AnyValue::String(date_time(cursor.bytes(4)?)),
+        0x01 => integer(cursor.bytes(1)?),
+        0x02 => integer(cursor.bytes(2)?),
+        0x03 => integer(cursor.bytes(3)?),
+        0x04 => integer(cursor.bytes(4)?),
+        0x06 => integer(cursor.bytes(6)?),
+        0x07 => integer(cursor.bytes(8)?),
+        0x05 => {
+            let bytes = cursor.bytes(4)?;
+            let real = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            return Ok(Some((
+                AnyValue::Signed((real * 1000.0).round() as isize),
+                -3,
+            )));
+        }
+        0x09 => bcd(cursor.bytes(1)?)?,
+        0x0a => bcd(cursor.bytes(2)?)?,
+        0x0b => bcd(cursor.bytes(3)?)?,
+        0x0c => bcd(cursor.bytes(4)?)?,
+        0x0e => bcd(cursor.bytes(6)?)?,
+        0x0d => {
+            let lvar = cursor.byte()?;
+            match lvar {
+                0x00..=0xbf => {
+                    let mut text = cursor.bytes(lvar as usize)?.to_vec();
+                    text.reverse();
+                    AnyValue::String(text)
+                }
+                0xc0..=0xcf => bcd(cursor.bytes((lvar - 0xc0) as usize)?)?,
+                0xd0..=0xdf => match bcd(cursor.bytes((lvar - 0xd0) as usize)?)? {
+                    AnyValue::Signed(value) => AnyValue::Signed(-value),
+                    value => value,
+                },
+                0xe0..=0xef => integer(cursor.bytes((lvar - 0xe0) as usize)?),
+                _ => return Err(MbusError::InvalidRecord),
+            }
+        }
+        _ => return Err(MbusError::InvalidRecord),
+    };
+    Ok(Some((value, 0)))
+}
+
+/// Signed little endian integer
+fn integer(bytes: &[u8]) -> AnyValue {
+    if bytes.len() > 8 {
+        return AnyValue::String(bytes.to_vec());
+    }
+    let mut value: i64 = 0;
+    for (i, byte) in bytes.iter().enumerate() {
+        value |= (*byte as i64) << (8 * i);
+    }
+    let shift = 64 - 8 * bytes.len() as u32;
+    if shift < 64 {
+        value = (value << shift) >> shift;
+    }
+    AnyValue::Signed(value as isize)
+}
+
+/// Little endian BCD, a leading `F` nibble marks a negative number
+fn bcd(bytes: &[u8]) -> Result<AnyValue, MbusError> {
+    let mut value: isize = 0;
+    let mut negative = false;
+    for (i, byte) in bytes.iter().rev().enumerate() {
+        for (j, nibble) in [byte >> 4, byte & 0x0f].into_iter().enumerate() {
+            if i == 0 && j == 0 && nibble == 0x0f {
+                negative = true;
+                continue;
+            }
+            if nibble > 9 {
+                return Err(MbusError::InvalidRecord);
+            }
+            value = value
+                .checked_mul(10)
+                .and_then(|v| v.checked_add(nibble as isize))
+                .ok_or(MbusError::InvalidRecord)?;
+        }
+    }
+    Ok(AnyValue::Signed(if negative { -value } else { value }))
+}
+
+/// Date of type G as `YYYY-MM-DD`
+fn date(bytes: &[u8]) -> Vec<u8> {
+    let value = u16::from_le_bytes([bytes[0], bytes[1]]);
+    let day = value & 0x1f;
+    let month = (value >> 8) & 0x0f;
+    let year = ((value >> 5) & 0x07) | ((value >> 9) & 0x78);
+    format!("{:04}-{:02}-{:02}", 2000 + year, month, day).into_bytes()
+}
+
+/// Date and time of type F as `YYYY-MM-DD hh:mm`
+fn date_time(bytes: &[u8]) -> Vec<u8> {
+    let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let minute = value & 0x3f;
+    let hour = (value >> 8) & 0x1f;
+    let day = (value >> 16) & 0x1f;
+    let month = (value >> 24) & 0x0f;
+    let year = ((value >> 21) & 0x07) | ((value >> 25) & 0x78);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        2000 + year,
+        month,
+        day,
+        hour,
+        minute
+    )
+    .into_bytes()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn parses_records() {
+        let records = parse_records(&[
+            0x03, 0x13, 0x15, 0x31, 0x00, // volume 12.565 m³
+            0xda, 0x02, 0x3b, 0x13, 0x01, // maximum volume flow, storage 5
+            0x8b, 0x60, 0x04, 0x37, 0x18, 0x02, // energy, tariff 2, subunit 1
+        ])
+        .unwrap();
+
+        assert_eq!(
+            records[0],
+            DataRecord {
+                function: Function::Instantaneous,
+                storage_number: 0,
+                tariff: 0,
+                subunit: 0,
+                quantity: Quantity::Volume,
+                unit: Some(CUBIC_METRE),
+                scaler: -3,
+                value: AnyValue::Signed(12565),
+                vif: vec![0x13],
+            }
+        );
+        assert_eq!(records[1].function, Function::Maximum);
+        assert_eq!(records[1].storage_number, 5);
+        assert_eq!(records[1].quantity, Quantity::VolumeFlow);
+        assert_eq!(records[1].value, AnyValue::Signed(113));
+        assert_eq!(records[2].tariff, 2);
+        assert_eq!(records[2].subunit, 1);
+        assert_eq!(records[2].unit, Some(WATT_HOUR));
+        assert_eq!(records[2].scaler, 1);
+        assert_eq!(records[2].value, AnyValue::Signed(21837));
+    }
+
+    #[test]
+    pub fn parses_special_values() {
+        let records = parse_records(&[
+            0x2f, // idle filler
+            0x02, 0x5a, 0xfe, 0xff, // flow temperature -0.2 °C
+            0x04, 0x6d, 0x1e, 0x0b, 0x8c, 0x21, // 2020-01-12 11:30
+            0x0d, 0xfd, 0x0e, 0x03, b'3', b'.', b'1', // firmware version
+            0x0f, 0x01, 0x02, // manufacturer specific
+        ])
+        .unwrap();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].value, AnyValue::Signed(-2));
+        assert_eq!(records[0].scaler, -1);
+        assert_eq!(
+            records[1].value,
+            AnyValue::String(b"2020-01-12 11:30".to_vec())
+        );
+        assert_eq!(records[2].quantity, Quantity::Other);
+        assert_eq!(records[2].vif, vec![0xfd, 0x0e]);
+        assert_eq!(records[2].value, AnyValue::String(b"1.3".to_vec()));
+    }
+
+    #[test]
+    pub fn rejects_truncated_records() {
+        assert!(matches!(
+            parse_records(&[0x04, 0x13, 0x01]),
+            Err(MbusError::InvalidRecord)
+        ));
+        assert!(matches!(
+            parse_records(&[0x0c, 0x13, 0x1a, 0x00, 0x00, 0x00]),
+            Err(MbusError::InvalidRecord)
+        ));
+    }
+}
@@ -10,8 +10,11 @@ use crate::{
 };
 
 /// Read SML message stream from a reader
-/// ```
+///
+/// The reader is dropped when it ends, fails or when the returned stream is dropped.
+/// ```no_run
 /// use std::io::Cursor;
+/// use hackdose_sml_parser::message_stream::sml_message_stream;
 ///
 /// let cursor = Cursor::new(vec![0x01, 0x02, 0x03]);
 /// let message_stream = sml_message_stream(cursor);
@@ -25,7 +28,14 @@ pub fn sml_message_stream(
     let mut builder = SMLMessageBuilder::Empty;
 
     tokio::spawn(async move {
-        while let Ok(n) = stream.read(&mut buf).await {
+        loop {
+            let n = tokio::select! {
+                read = stream.read(&mut buf) => match read {
+                    Ok(n) if n > 0 => n,
+                    _ => break,
+                },
+                _ = tx.closed() => break,
+            };
             emit_message(&mut builder, &buf[..n], tx.clone()).await;
         }
     });
@@ -39,7 +49,7 @@ async fn emit_message<'a>(
     tx: Sender<SmlMessages>,
 ) {
     let mut to_process = buf.to_vec();
-    while to_process.len() > 0 {
+    while !to_process.is_empty() {
         builder.record(&to_process);
         to_process = vec![];
 
@@ -49,7 +59,7 @@ async fn emit_message<'a>(
                 if let Ok(messages) = result {
                     let _ = tx.send(messages).await;
                 }
-                if rest.len() == 0 {
+                if rest.is_empty() {
                     *builder = SMLMessageBuilder::Empty;
                 } else {
                     to_process = rest.to_vec();
@@ -62,3 +72,26 @@ async fn emit_message<'a>(
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    pub async fn drops_reader_with_stream() {
+        let (mut meter, serial) = tokio::io::duplex(64);
+        let stream = sml_message_stream(serial);
+        meter.write_all(&[0x1b, 0x1b]).await.unwrap();
+
+        drop(stream);
+
+        // the buffer of the duplex fills up if the reader is kept
+        let result = tokio::time::timeout(
+            std::time::Duration::from_secs(1),
+            meter.write_all(&[0x1b; 128]),
+        )
+        .await;
+        assert!(matches!(result, Ok(Err(_))));
+    }
+}
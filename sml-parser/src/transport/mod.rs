@@ -22,6 +22,7 @@ pub enum SMLMessageBuilder {
 
 static START_SEQUENCE: &[u8] = &[0x1b, 0x1b, 0x1b, 0x1b, 0x01, 0x01, 0x01, 0x01];
 static END_SEQUENCE_WITHOUT_CRC: &[u8] = &[0x1b, 0x1b, 0x1b, 0x1b, 0x1a];
+static ESCAPE_SEQUENCE: &[u8] = &[0x1b, 0x1b, 0x1b, 0x1b];
 
 impl SMLMessageBuilder {
     pub fn record(&mut self, buf: &[u8]) {
@@ -74,14 +75,13 @@ impl SMLMessageBuilder {
                     *self = SMLMessageBuilder::Recording([].to_vec());
                     self.record(
                         &buf[maximal_start_sequence_occurance.index
-                            + maximal_start_sequence_occurance.length..]
-                            .to_vec(),
+                            + maximal_start_sequence_occurance.length..],
                     );
                 } else if maximal_start_sequence_occurance.length > 0 {
                     *self = SMLMessageBuilder::IncompleteStartSignature(
                         maximal_start_sequence_occurance.length + start,
                     );
-                } else if maximal_start_sequence_occurance.length == 0 && buf.len() > 0 {
+                } else if maximal_start_sequence_occurance.length == 0 && !buf.is_empty() {
                     *self = SMLMessageBuilder::Empty;
                 };
             }
@@ -105,6 +105,56 @@ impl SMLMessageBuilder {
         }
     }
 }
+/// Wrap an SML message body into a complete SML file
+///
+/// Adds the start sequence, escapes occurrences of the escape sequence, pads the body to
+/// a multiple of four bytes and appends the end sequence with padding length and checksum.
+/// ```
+/// use hackdose_sml_parser::transport::encode_frame;
+/// let frame = encode_frame(&[0x42, 0x43]);
+/// assert_eq!(frame[..10], [0x1b, 0x1b, 0x1b, 0x1b, 0x01, 0x01, 0x01, 0x01, 0x42, 0x43]);
+/// assert_eq!(frame[12..17], [0x1b, 0x1b, 0x1b, 0x1b, 0x1a]);
+/// ```
+pub fn encode_frame(body: &[u8]) -> Vec<u8> {
+    let mut frame = START_SEQUENCE.to_vec();
+    let mut i = 0;
+    while i < body.len() {
+        if body[i..].starts_with(ESCAPE_SEQUENCE) {
+            frame.extend_from_slice(ESCAPE_SEQUENCE);
+            frame.extend_from_slice(ESCAPE_SEQUENCE);
+            i += ESCAPE_SEQUENCE.len();
+        } else {
+            frame.push(body[i]);
+            i += 1;
+        }
+    }
+    let padding = (4 - frame.len() % 4) % 4;
+    frame.resize(frame.len() + padding, 0x00);
+    frame.extend_from_slice(END_SEQUENCE_WITHOUT_CRC);
+    frame.push(padding as u8);
+    let crc = crc16(&frame);
+    frame.extend_from_slice(&crc.swap_bytes().to_be_bytes());
+    frame
+}
+
+/// CRC-16/X-25 checksum as used for SML messages and files
+///
+/// Note that SML transmits the checksum with swapped bytes.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffff_u16;
+    for byte in data {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            if crc & 1 == 1 {
+                crc = (crc >> 1) ^ 0x8408;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    !crc
+}
+
 fn contains(this: &[u8], that: &[u8]) -> usize {
     let mut counter = 0;
     for pair in this.iter().zip(that.iter()) {
@@ -354,6 +404,55 @@ mod test {
         );
     }
 
+    #[test]
+    pub fn computes_checksum() {
+        assert_eq!(crc16(b"123456789"), 0x906e);
+    }
+
+    #[test]
+    pub fn frame_checksum_matches_close_response() {
+        // close response as sent by a meter, its message checksum covers the first 16 bytes
+        let body = &[
+            0x76, 0x05, 0x03, 0x2b, 0x18, 0x11, 0x62, 0x00, 0x62, 0x00, 0x72, 0x63, 0x02, 0x01,
+            0x71, 0x01, 0x63, 0xfa, 0x36, 0x00,
+        ];
+        assert_eq!(crc16(&body[..16]).swap_bytes().to_be_bytes(), [0xfa, 0x36]);
+
+        let frame = encode_frame(body);
+
+        assert_eq!(
+            frame,
+            [
+                0x1b, 0x1b, 0x1b, 0x1b, 0x01, 0x01, 0x01, 0x01, 0x76, 0x05, 0x03, 0x2b, 0x18, 0x11,
+                0x62, 0x00, 0x62, 0x00, 0x72, 0x63, 0x02, 0x01, 0x71, 0x01, 0x63, 0xfa, 0x36, 0x00,
+                0x1b, 0x1b, 0x1b, 0x1b, 0x1a, 0x00, 0x34, 0x00,
+            ]
+        );
+    }
+
+    #[test]
+    pub fn escapes_escape_sequence_in_frame() {
+        let frame = encode_frame(&[0x1b, 0x1b, 0x1b, 0x1b]);
+
+        assert_eq!(frame[8..16], [0x1b; 8]);
+    }
+
+    #[test]
+    pub fn frames_are_recorded_by_builder() {
+        let frame = encode_frame(&[0x42, 0x43, 0x44]);
+
+        let mut rec = SMLMessageBuilder::Empty;
+        rec.record(&frame);
+
+        assert_eq!(
+            rec,
+            SMLMessageBuilder::Complete {
+                data: vec![0x42, 0x43, 0x44, 0x00],
+                rest: vec![]
+            }
+        );
+    }
+
     #[test]
     pub fn takes_first_of_two_messages() {
         let buf = &[